@@ -4,6 +4,7 @@ use prisma_client_rust::NewClientError;
 use std::{net::SocketAddr, sync::Arc};
 use tower_http::trace::TraceLayer;
 use tracing_subscriber::{prelude::__tracing_subscriber_SubscriberExt, util::SubscriberInitExt};
+pub mod netcode;
 pub mod routes;
 
 #[tokio::main]
@@ -25,6 +26,7 @@ async fn main() {
     let app = Router::new()
         .nest("/api", routes::create_route())
         .layer(Extension(prisma_client))
+        .layer(Extension(Arc::new(netcode::TokenConfig::from_env())))
         .layer(TraceLayer::new_for_http());
 
     let addr = SocketAddr::from(([0, 0, 0, 0], 3000));
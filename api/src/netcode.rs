@@ -0,0 +1,50 @@
+use bevy_garage_netcode::{private_key_from_env, NETCODE_KEY_BYTES, PROTOCOL_ID};
+use renet::transport::ConnectToken;
+use std::{net::SocketAddr, time::SystemTime};
+
+const TOKEN_EXPIRE_SECONDS: u64 = 300;
+const TIMEOUT_SECONDS: i32 = 15;
+
+// NETCODE_PRIVATE_KEY=64 hex characters shared with the game server,
+// RENET_SERVER_ADDR=public address written into the tokens
+pub struct TokenConfig {
+    private_key: [u8; NETCODE_KEY_BYTES],
+    server_addresses: Vec<SocketAddr>,
+}
+
+impl TokenConfig {
+    pub fn from_env() -> Option<Self> {
+        let Some(private_key) = private_key_from_env() else {
+            tracing::warn!("NETCODE_PRIVATE_KEY not set, no connect tokens");
+            return None;
+        };
+        let addr = std::env::var("RENET_SERVER_ADDR").unwrap_or("127.0.0.1:5000".to_string());
+        Some(Self {
+            private_key,
+            server_addresses: vec![addr.parse().expect("invalid RENET_SERVER_ADDR")],
+        })
+    }
+
+    // a new client id per token, the token bytes are sent to the client as they are
+    pub fn generate(&self) -> Option<Vec<u8>> {
+        let current_time = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap();
+        let client_id = rand::random::<u64>();
+        let token = ConnectToken::generate(
+            current_time,
+            PROTOCOL_ID,
+            TOKEN_EXPIRE_SECONDS,
+            client_id,
+            TIMEOUT_SECONDS,
+            self.server_addresses.clone(),
+            None,
+            &self.private_key,
+        )
+        .ok()?;
+        let mut bytes = vec![];
+        token.write(&mut bytes).ok()?;
+        tracing::debug!("connect token for client {}", client_id);
+        Some(bytes)
+    }
+}
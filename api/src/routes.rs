@@ -1,3 +1,4 @@
+use crate::netcode::TokenConfig;
 use axum::{
     extract::Json,
     http::StatusCode,
@@ -14,6 +15,7 @@ use prisma_client_rust::{
 use serde::Deserialize;
 
 pub type DbExt = Extension<std::sync::Arc<db::PrismaClient>>;
+pub type TokenExt = Extension<std::sync::Arc<Option<TokenConfig>>>;
 type AppResult<T> = Result<T, AppError>;
 type AppJsonResult<T> = AppResult<Json<T>>;
 
@@ -27,7 +29,17 @@ pub struct ReplayBufferRecord {
 }
 
 pub fn create_route() -> Router {
-    Router::new().route("/replay", post(add_replay_buffer))
+    Router::new()
+        .route("/replay", post(add_replay_buffer))
+        .route("/connect", post(connect_token))
+}
+
+// netcode connect token for the renet client, secure auth on the game server
+async fn connect_token(Extension(tokens): TokenExt) -> AppResult<Vec<u8>> {
+    let Some(config) = &*tokens else {
+        return Err(AppError::Unavailable);
+    };
+    config.generate().ok_or(AppError::Unavailable)
 }
 
 async fn add_replay_buffer(
@@ -60,6 +72,7 @@ async fn add_replay_buffer(
 enum AppError {
     PrismaError(QueryError),
     NotFound,
+    Unavailable,
 }
 
 impl From<QueryError> for AppError {
@@ -80,6 +93,7 @@ impl IntoResponse for AppError {
             }
             AppError::PrismaError(_) => StatusCode::BAD_REQUEST,
             AppError::NotFound => StatusCode::NOT_FOUND,
+            AppError::Unavailable => StatusCode::SERVICE_UNAVAILABLE,
         };
 
         status.into_response()
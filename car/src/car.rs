@@ -1,4 +1,4 @@
-use crate::{joint::build_joint, spawn_wheel, CarSpec, WheelSpec};
+use crate::{joint::build_joint, spawn_wheel, CarSpec, SurfaceKind, WheelSpec};
 use bevy::prelude::*;
 use bevy_rapier3d::prelude::*;
 
@@ -52,6 +52,8 @@ impl CarWheels {
 }
 
 pub const STATIC_GROUP: Group = Group::GROUP_1;
+// track checkpoint sensors, only car bodies enter them
+pub const CHECKPOINT_GROUP: Group = Group::GROUP_2;
 pub const CAR_TRAINING_GROUP: Group = Group::GROUP_10;
 
 #[cfg(feature = "graphics")]
@@ -117,6 +119,7 @@ pub fn spawn_car_body(
     cmd.spawn((
         Name::new("car"),
         car,
+        SurfaceKind::Car,
         spec,
         #[cfg(feature = "graphics")]
         SceneBundle {
@@ -140,7 +143,7 @@ pub fn spawn_car_body(
             },
             Friction::coefficient(0.5),
             Restitution::coefficient(0.),
-            CollisionGroups::new(CAR_TRAINING_GROUP, STATIC_GROUP),
+            CollisionGroups::new(CAR_TRAINING_GROUP, STATIC_GROUP | CHECKPOINT_GROUP),
             ActiveEvents::COLLISION_EVENTS,
             ContactForceEventThreshold(0.1),
         ),
@@ -0,0 +1,296 @@
+use crate::{gaussian, CarWheels, SimRng, Wheel};
+use bevy::prelude::*;
+use bevy_rapier3d::prelude::*;
+use rand::{Rng, RngCore};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+
+const GRAVITY: Vec3 = Vec3::new(0., -9.81, 0.);
+
+// applied per reading axis: value + bias + gaussian(noise_std)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NoiseModel {
+    pub bias: f32,
+    pub noise_std: f32,
+    // samples per second
+    pub rate: f32,
+    // seconds between sampling and publishing
+    pub latency: f32,
+}
+
+impl NoiseModel {
+    pub fn new(bias: f32, noise_std: f32, rate: f32, latency: f32) -> Self {
+        Self {
+            bias,
+            noise_std,
+            rate,
+            latency,
+        }
+    }
+    pub fn apply<R: Rng>(&self, rng: &mut R, value: f32) -> f32 {
+        if self.noise_std <= 0. {
+            return value + self.bias;
+        }
+        value + self.bias + gaussian(rng, self.noise_std)
+    }
+    pub fn apply_vec3<R: Rng>(&self, rng: &mut R, v: Vec3) -> Vec3 {
+        Vec3::new(
+            self.apply(rng, v.x),
+            self.apply(rng, v.y),
+            self.apply(rng, v.z),
+        )
+    }
+}
+
+#[derive(Component, Debug, Clone, Serialize, Deserialize)]
+pub struct Instruments {
+    pub accelerometer: NoiseModel,
+    // sampled with accelerometer, only bias and noise are used
+    pub gyro: NoiseModel,
+    pub wheel_encoders: NoiseModel,
+    pub gnss: NoiseModel,
+    pub compass: NoiseModel,
+}
+
+impl Default for Instruments {
+    fn default() -> Self {
+        Self {
+            accelerometer: NoiseModel::new(0.05, 0.1, 100., 0.),
+            gyro: NoiseModel::new(0.002, 0.01, 100., 0.),
+            wheel_encoders: NoiseModel::new(0., 0.05, 50., 0.),
+            gnss: NoiseModel::new(0., 1.5, 10., 0.1),
+            compass: NoiseModel::new(0.01, 0.02, 20., 0.),
+        }
+    }
+}
+
+// every reading is sent as an event, the latest one is also a component of the car
+
+// car body frame specific force (m/s2, gravity included) and angular velocity (rad/s)
+#[derive(Event, Debug, Clone)]
+pub struct ImuReading {
+    pub entity: Entity,
+    pub seconds: f64,
+    pub accel: Vec3,
+    pub gyro: Vec3,
+}
+
+// rad/s around the axle relative to the car body, CarWheels order
+#[derive(Event, Debug, Clone)]
+pub struct WheelSpeedReading {
+    pub entity: Entity,
+    pub seconds: f64,
+    pub angular_speed: [f32; 4],
+}
+
+// world position, meters
+#[derive(Event, Debug, Clone)]
+pub struct GnssReading {
+    pub entity: Entity,
+    pub seconds: f64,
+    pub position: Vec3,
+}
+
+// radians from world +Z towards +X
+#[derive(Event, Debug, Clone)]
+pub struct CompassReading {
+    pub entity: Entity,
+    pub seconds: f64,
+    pub heading: f32,
+}
+
+pub trait Reading {
+    fn seconds(&self) -> f64;
+}
+
+macro_rules! impl_reading {
+    ($($t:ty),*) => {$(
+        impl Reading for $t {
+            fn seconds(&self) -> f64 {
+                self.seconds
+            }
+        }
+    )*};
+}
+impl_reading!(ImuReading, WheelSpeedReading, GnssReading, CompassReading);
+
+// sampled readings waiting for latency to pass
+struct Channel<T> {
+    next_sample: f64,
+    pending: VecDeque<T>,
+}
+
+impl<T> Default for Channel<T> {
+    fn default() -> Self {
+        Self {
+            next_sample: 0.,
+            pending: VecDeque::new(),
+        }
+    }
+}
+
+impl<T: Reading> Channel<T> {
+    fn should_sample(&mut self, seconds: f64, model: &NoiseModel) -> bool {
+        if model.rate <= 0. {
+            return false;
+        }
+        // fixed cadence at the declared rate, one reading per due frame
+        if self.next_sample <= 0. || seconds - self.next_sample > 1. {
+            self.next_sample = seconds;
+        }
+        if seconds < self.next_sample {
+            return false;
+        }
+        while self.next_sample <= seconds {
+            self.next_sample += 1. / model.rate as f64;
+        }
+        true
+    }
+    fn ready(&mut self, seconds: f64, model: &NoiseModel) -> Vec<T> {
+        let mut ready: Vec<T> = vec![];
+        while let Some(reading) = self.pending.front() {
+            if reading.seconds() + model.latency as f64 > seconds {
+                break;
+            }
+            ready.extend(self.pending.pop_front());
+        }
+        ready
+    }
+}
+
+#[derive(Component, Default)]
+pub struct InstrumentsState {
+    prev_linvel: Option<Vec3>,
+    imu: Channel<ImuReading>,
+    wheels: Channel<WheelSpeedReading>,
+    gnss: Channel<GnssReading>,
+    compass: Channel<CompassReading>,
+}
+
+pub fn add_instruments_state_system(q_car: Query<Entity, Added<Instruments>>, mut cmd: Commands) {
+    for e in q_car.iter() {
+        cmd.entity(e).insert(InstrumentsState::default());
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn instruments_system(
+    time: Res<Time>,
+    mut q_car: Query<(
+        Entity,
+        &Instruments,
+        &mut InstrumentsState,
+        &Transform,
+        &Velocity,
+        &CarWheels,
+    )>,
+    q_wheel: Query<(&Transform, &Velocity), With<Wheel>>,
+    mut cmd: Commands,
+    mut imu_events: EventWriter<ImuReading>,
+    mut wheel_events: EventWriter<WheelSpeedReading>,
+    mut gnss_events: EventWriter<GnssReading>,
+    mut compass_events: EventWriter<CompassReading>,
+    mut sim_rng: Option<ResMut<SimRng>>,
+) {
+    let seconds = time.elapsed_seconds_f64();
+    let dt = time.delta_seconds();
+    // noise is reproducible in seeded sessions
+    let mut thread_rng = rand::thread_rng();
+    let mut rng: &mut dyn RngCore = match sim_rng.as_mut() {
+        Some(sim_rng) => &mut sim_rng.0,
+        None => &mut thread_rng,
+    };
+    for (e, instruments, mut state, t, v, wheels) in q_car.iter_mut() {
+        let inverse_rotation = t.rotation.inverse();
+        let accel_world = match (state.prev_linvel, dt > 0.) {
+            (Some(prev_linvel), true) => (v.linvel - prev_linvel) / dt,
+            _ => Vec3::ZERO,
+        };
+        state.prev_linvel = Some(v.linvel);
+
+        if state.imu.should_sample(seconds, &instruments.accelerometer) {
+            let specific_force = inverse_rotation.mul_vec3(accel_world - GRAVITY);
+            let gyro = inverse_rotation.mul_vec3(v.angvel);
+            state.imu.pending.push_back(ImuReading {
+                entity: e,
+                seconds,
+                accel: instruments
+                    .accelerometer
+                    .apply_vec3(&mut rng, specific_force),
+                gyro: instruments.gyro.apply_vec3(&mut rng, gyro),
+            });
+        }
+        if state
+            .wheels
+            .should_sample(seconds, &instruments.wheel_encoders)
+        {
+            let mut angular_speed = [0.; 4];
+            for (i, wheel_entity) in wheels.entities.iter().enumerate() {
+                if let Ok((wheel_tr, wheel_v)) = q_wheel.get(*wheel_entity) {
+                    let axle = wheel_tr.rotation.mul_vec3(Vec3::Y);
+                    let spin = (wheel_v.angvel - v.angvel).dot(axle);
+                    angular_speed[i] = instruments.wheel_encoders.apply(&mut rng, spin);
+                }
+            }
+            state.wheels.pending.push_back(WheelSpeedReading {
+                entity: e,
+                seconds,
+                angular_speed,
+            });
+        }
+        if state.gnss.should_sample(seconds, &instruments.gnss) {
+            state.gnss.pending.push_back(GnssReading {
+                entity: e,
+                seconds,
+                position: instruments.gnss.apply_vec3(&mut rng, t.translation),
+            });
+        }
+        if state.compass.should_sample(seconds, &instruments.compass) {
+            let forward = t.rotation.mul_vec3(Vec3::Z);
+            let heading = forward.x.atan2(forward.z);
+            state.compass.pending.push_back(CompassReading {
+                entity: e,
+                seconds,
+                heading: instruments.compass.apply(&mut rng, heading),
+            });
+        }
+
+        for reading in state.imu.ready(seconds, &instruments.accelerometer) {
+            cmd.entity(e).insert(reading.clone());
+            imu_events.send(reading);
+        }
+        for reading in state.wheels.ready(seconds, &instruments.wheel_encoders) {
+            cmd.entity(e).insert(reading.clone());
+            wheel_events.send(reading);
+        }
+        for reading in state.gnss.ready(seconds, &instruments.gnss) {
+            cmd.entity(e).insert(reading.clone());
+            gnss_events.send(reading);
+        }
+        for reading in state.compass.ready(seconds, &instruments.compass) {
+            cmd.entity(e).insert(reading.clone());
+            compass_events.send(reading);
+        }
+    }
+}
+
+pub struct InstrumentsPlugin;
+
+impl Plugin for InstrumentsPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<ImuReading>()
+            .add_event::<WheelSpeedReading>()
+            .add_event::<GnssReading>()
+            .add_event::<CompassReading>()
+            .add_systems(
+                Update,
+                (
+                    add_instruments_state_system,
+                    // a fixed order of the SimRng draws
+                    instruments_system
+                        .in_set(crate::CarSet::Input)
+                        .after(crate::sensor::sensor_system),
+                ),
+            );
+    }
+}
@@ -1,19 +1,25 @@
 cfg_if::cfg_if! {if #[cfg(feature = "graphics")] {
     pub mod res;
-    pub mod sensor;
     pub use res::CarRes;
 }}
 
 pub mod car;
 pub mod esp;
+pub mod instruments;
 pub mod joint;
+pub mod lidar;
+pub mod rng;
+pub mod sensor;
 pub mod spawn;
 pub mod spec;
+pub mod surface;
 pub mod wheel;
 
 pub use car::*;
 pub use esp::*;
+pub use rng::*;
 pub use spec::*;
+pub use surface::*;
 pub use wheel::*;
 
 use bevy::prelude::SystemSet;
@@ -0,0 +1,301 @@
+use crate::{CarWheels, SurfaceKind};
+use bevy::prelude::*;
+use bevy_rapier3d::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::{self, File},
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+// rotating multi channel lidar mounted on a car
+#[derive(Component, Debug, Clone, Serialize, Deserialize)]
+pub struct Lidar {
+    // car local mount position
+    pub origin: [f32; 3],
+    // vertical channels between min and max pitch, radians
+    pub channels: usize,
+    pub min_pitch: f32,
+    pub max_pitch: f32,
+    // horizontal steps over fov centered at car forward, radians
+    pub azimuth_steps: usize,
+    pub fov: f32,
+    pub range: f32,
+    // seconds between scans
+    pub period: f32,
+    pub depth_image: bool,
+}
+
+impl Default for Lidar {
+    fn default() -> Self {
+        Self {
+            origin: [0., 0.6, 0.],
+            channels: 16,
+            min_pitch: -15_f32.to_radians(),
+            max_pitch: 15_f32.to_radians(),
+            azimuth_steps: 360,
+            fov: std::f32::consts::TAU,
+            range: 100.,
+            period: 0.1,
+            depth_image: true,
+        }
+    }
+}
+
+impl Lidar {
+    // car local ray direction, azimuth 0 - forward (+Z), positive to the left
+    pub fn ray_dir(&self, channel: usize, step: usize) -> Vec3 {
+        let pitch = if self.channels > 1 {
+            self.min_pitch
+                + (self.max_pitch - self.min_pitch) * channel as f32 / (self.channels - 1) as f32
+        } else {
+            (self.min_pitch + self.max_pitch) / 2.
+        };
+        let azimuth = -self.fov / 2. + self.fov * (step as f32 + 0.5) / self.azimuth_steps as f32;
+        Quat::from_rotation_y(azimuth)
+            .mul_quat(Quat::from_rotation_x(-pitch))
+            .mul_vec3(Vec3::Z)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct LidarPoint {
+    // lidar frame, axes of the car
+    pub position: Vec3,
+    pub normal: Vec3,
+    pub distance: f32,
+    pub channel: usize,
+    pub surface: SurfaceKind,
+    pub entity: Entity,
+}
+
+// row per channel (top row is max pitch), meters, 0. for no hit
+#[derive(Debug, Clone)]
+pub struct DepthImage {
+    pub width: usize,
+    pub height: usize,
+    pub depth: Vec<f32>,
+}
+
+impl DepthImage {
+    pub fn new(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            height,
+            depth: vec![0.; width * height],
+        }
+    }
+    // 8 bit binary pgm, near is bright
+    pub fn save_pgm(&self, path: &Path, range: f32) -> io::Result<()> {
+        let mut file = BufWriter::new(File::create(path)?);
+        write!(file, "P5\n{} {}\n255\n", self.width, self.height)?;
+        let pixels: Vec<u8> = self
+            .depth
+            .iter()
+            .map(|d| match *d > 0. {
+                true => (255. * (1. - d / range).clamp(0., 1.)) as u8,
+                false => 0,
+            })
+            .collect();
+        file.write_all(&pixels)?;
+        file.flush()
+    }
+}
+
+#[derive(Component, Debug, Default)]
+pub struct LidarScan {
+    pub seconds: f64,
+    pub points: Vec<LidarPoint>,
+    pub depth: Option<DepthImage>,
+    pub scans: usize,
+}
+
+pub fn lidar_system(
+    time: Res<Time>,
+    rapier_context: Res<RapierContext>,
+    #[cfg(feature = "graphics")] config: Res<crate::CarRes>,
+    mut q_car: Query<(Entity, &Lidar, &mut LidarScan, &Transform, &CarWheels)>,
+    q_surface: Query<&SurfaceKind>,
+    #[cfg(feature = "graphics")] mut gizmos: Gizmos,
+) {
+    let seconds = time.elapsed_seconds_f64();
+    for (e, lidar, mut scan, t, wheels) in q_car.iter_mut() {
+        if seconds < scan.seconds + lidar.period as f64 {
+            continue;
+        }
+        scan.seconds = seconds;
+        scan.scans += 1;
+        scan.points.clear();
+        let mut depth = match lidar.depth_image {
+            true => Some(DepthImage::new(lidar.azimuth_steps, lidar.channels)),
+            false => None,
+        };
+
+        let own_colliders =
+            |collider: Entity| collider != e && !wheels.entities.contains(&collider);
+        let filter = QueryFilter::new()
+            .exclude_sensors()
+            .predicate(&own_colliders);
+        let inverse_rotation = t.rotation.inverse();
+        let origin = t.translation + t.rotation.mul_vec3(Vec3::from(lidar.origin));
+        for channel in 0..lidar.channels {
+            for step in 0..lidar.azimuth_steps {
+                let dir = t.rotation.mul_vec3(lidar.ray_dir(channel, step));
+                let Some((hit_entity, hit)) =
+                    rapier_context.cast_ray_and_get_normal(origin, dir, lidar.range, true, filter)
+                else {
+                    continue;
+                };
+                let surface = q_surface.get(hit_entity).copied().unwrap_or_default();
+                scan.points.push(LidarPoint {
+                    position: inverse_rotation.mul_vec3(hit.point - origin),
+                    normal: inverse_rotation.mul_vec3(hit.normal),
+                    distance: hit.time_of_impact,
+                    channel,
+                    surface,
+                    entity: hit_entity,
+                });
+                if let Some(depth) = depth.as_mut() {
+                    let row = lidar.channels - 1 - channel;
+                    depth.depth[row * lidar.azimuth_steps + step] = hit.time_of_impact;
+                }
+                #[cfg(feature = "graphics")]
+                if config.show_rays {
+                    gizmos.sphere(hit.point, Quat::IDENTITY, 0.05, Color::srgb(0.2, 0.8, 0.9));
+                }
+            }
+        }
+        scan.depth = depth;
+    }
+}
+
+pub fn add_lidar_scan_system(q_lidar: Query<Entity, Added<Lidar>>, mut cmd: Commands) {
+    for e in q_lidar.iter() {
+        cmd.entity(e).insert(LidarScan::default());
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PointCloudFormat {
+    Pcd,
+    Ply,
+}
+
+// ascii PCD v0.7: x y z normal_x normal_y normal_z surface channel
+pub fn write_pcd(path: &Path, points: &[LidarPoint]) -> io::Result<()> {
+    let mut file = BufWriter::new(File::create(path)?);
+    writeln!(file, "# .PCD v0.7 - Point Cloud Data file format")?;
+    writeln!(file, "VERSION 0.7")?;
+    writeln!(
+        file,
+        "FIELDS x y z normal_x normal_y normal_z surface channel"
+    )?;
+    writeln!(file, "SIZE 4 4 4 4 4 4 1 2")?;
+    writeln!(file, "TYPE F F F F F F U U")?;
+    writeln!(file, "COUNT 1 1 1 1 1 1 1 1")?;
+    writeln!(file, "WIDTH {}", points.len())?;
+    writeln!(file, "HEIGHT 1")?;
+    writeln!(file, "VIEWPOINT 0 0 0 1 0 0 0")?;
+    writeln!(file, "POINTS {}", points.len())?;
+    writeln!(file, "DATA ascii")?;
+    write_rows(&mut file, points)
+}
+
+// ascii PLY with the same properties as PCD
+pub fn write_ply(path: &Path, points: &[LidarPoint]) -> io::Result<()> {
+    let mut file = BufWriter::new(File::create(path)?);
+    writeln!(file, "ply")?;
+    writeln!(file, "format ascii 1.0")?;
+    writeln!(file, "element vertex {}", points.len())?;
+    for property in ["x", "y", "z", "nx", "ny", "nz"] {
+        writeln!(file, "property float {property}")?;
+    }
+    writeln!(file, "property uchar surface")?;
+    writeln!(file, "property ushort channel")?;
+    writeln!(file, "end_header")?;
+    write_rows(&mut file, points)
+}
+
+fn write_rows<W: Write>(file: &mut W, points: &[LidarPoint]) -> io::Result<()> {
+    for p in points.iter() {
+        writeln!(
+            file,
+            "{} {} {} {} {} {} {} {}",
+            p.position.x,
+            p.position.y,
+            p.position.z,
+            p.normal.x,
+            p.normal.y,
+            p.normal.z,
+            p.surface.label(),
+            p.channel
+        )?;
+    }
+    file.flush()
+}
+
+// CAR_LIDAR_DUMP=dir writes every scan, CAR_LIDAR_DUMP_FORMAT=pcd|ply (default pcd)
+#[derive(Resource, Debug)]
+pub struct LidarDump {
+    pub dir: PathBuf,
+    pub format: PointCloudFormat,
+}
+
+impl LidarDump {
+    pub fn from_env() -> Option<Self> {
+        let dir = PathBuf::from(std::env::var("CAR_LIDAR_DUMP").ok()?);
+        let format = match std::env::var("CAR_LIDAR_DUMP_FORMAT").as_deref() {
+            Ok("ply") => PointCloudFormat::Ply,
+            _ => PointCloudFormat::Pcd,
+        };
+        Some(Self { dir, format })
+    }
+    pub fn dump(&self, index: usize, lidar: &Lidar, scan: &LidarScan) -> io::Result<()> {
+        fs::create_dir_all(&self.dir)?;
+        let name = format!("lidar_{index}_{:06}", scan.scans);
+        match self.format {
+            PointCloudFormat::Pcd => {
+                write_pcd(&self.dir.join(name.clone() + ".pcd"), &scan.points)?
+            }
+            PointCloudFormat::Ply => {
+                write_ply(&self.dir.join(name.clone() + ".ply"), &scan.points)?
+            }
+        }
+        if let Some(depth) = scan.depth.as_ref() {
+            depth.save_pgm(&self.dir.join(name + ".pgm"), lidar.range)?;
+        }
+        Ok(())
+    }
+}
+
+pub fn lidar_dump_system(
+    dump: Res<LidarDump>,
+    q_scan: Query<(Entity, &Lidar, &LidarScan), Changed<LidarScan>>,
+) {
+    for (e, lidar, scan) in q_scan.iter() {
+        if scan.scans == 0 {
+            continue;
+        }
+        if let Err(err) = dump.dump(e.index() as usize, lidar, scan) {
+            println!("lidar dump error {:?}: {err}", dump.dir);
+        }
+    }
+}
+
+pub struct LidarPlugin;
+
+impl Plugin for LidarPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            Update,
+            (
+                add_lidar_scan_system,
+                lidar_system.in_set(crate::CarSet::Input),
+            ),
+        );
+        if let Some(dump) = LidarDump::from_env() {
+            app.insert_resource(dump)
+                .add_systems(Update, lidar_dump_system.after(lidar_system));
+        }
+    }
+}
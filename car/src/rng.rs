@@ -0,0 +1,18 @@
+use bevy::prelude::*;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use std::f32::consts::TAU;
+
+// seeded rng for random spawns and sensor noise in recorded, re-simulated and training sessions
+#[derive(Resource)]
+pub struct SimRng(pub StdRng);
+
+pub fn sim_rng_seed(seed: u64) -> SimRng {
+    SimRng(StdRng::seed_from_u64(seed))
+}
+
+// zero mean normal sample, Box-Muller
+pub fn gaussian<R: Rng + ?Sized>(rng: &mut R, std: f32) -> f32 {
+    let u1: f32 = rng.gen_range(f32::EPSILON..1.);
+    let u2: f32 = rng.gen_range(0.0..1.);
+    std * (-2. * u1.ln()).sqrt() * (TAU * u2).cos()
+}
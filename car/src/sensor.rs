@@ -1,25 +1,55 @@
-use crate::{CarRes, CarSize};
+use crate::{gaussian, CarSize, SimRng};
 use bevy::prelude::*;
 use bevy_rapier3d::prelude::*;
-use std::f32::consts::{FRAC_PI_2, FRAC_PI_4, FRAC_PI_8, PI};
+use rand::{Rng, RngCore};
+use serde::{Deserialize, Serialize};
+use std::{
+    f32::consts::{FRAC_PI_2, FRAC_PI_4, FRAC_PI_8, PI},
+    fs, io,
+    path::Path,
+};
 
 pub const FRAC_PI_16: f32 = FRAC_PI_8 / 2.;
+// rays count of the default layout
 pub const SENSOR_COUNT: usize = 31;
 
-#[derive(Component, Debug)]
-pub struct CarSensors {
-    pub max_toi: f32,
-    pub sensor_config: [(Vec3, Quat); SENSOR_COUNT],
-    pub sensor_inputs: Vec<f32>,
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SensorRay {
+    // car local position
+    pub origin: [f32; 3],
+    // rotation around car up axis, 0 - forward
+    pub yaw: f32,
+    // rotation up from car horizontal plane, 0 - ray is kept horizontal in world space
+    #[serde(default)]
+    pub pitch: f32,
+    pub range: f32,
 }
 
-impl CarSensors {
+#[derive(Resource, Debug, Clone, Serialize, Deserialize)]
+pub struct SensorLayout {
+    pub rays: Vec<SensorRay>,
+    // collision group bits, hits only colliders matching both ways
+    #[serde(default = "all_groups")]
+    pub memberships: u32,
+    #[serde(default = "all_groups")]
+    pub filters: u32,
+    // gaussian noise standard deviation added to normalized inputs
+    #[serde(default)]
+    pub noise_std: f32,
+    // probability of a ray reporting no hit
+    #[serde(default)]
+    pub dropout: f32,
+}
+
+fn all_groups() -> u32 {
+    Group::ALL.bits()
+}
+
+impl SensorLayout {
     pub fn new(car_size: &CarSize) -> Self {
         let (hw, hl) = (car_size.hw, car_size.hl);
         Self {
-            max_toi: 100.,
-            sensor_inputs: vec![0.; SENSOR_COUNT],
-            sensor_config: [
+            rays: [
                 // front
                 (hw, hl, 0.),
                 (0., hl, 0.),
@@ -56,52 +86,131 @@ impl CarSensors {
                 (hw, -hl, PI - FRAC_PI_2),
                 (-hw, -hl, PI + FRAC_PI_2),
             ]
-            .map(|(w, l, r)| (Vec3::new(w, -0.1, l), Quat::from_rotation_y(r))),
+            .map(|(w, l, yaw)| SensorRay {
+                origin: [w, -0.1, l],
+                yaw,
+                pitch: 0.,
+                range: 100.,
+            })
+            .to_vec(),
+            memberships: all_groups(),
+            filters: all_groups(),
+            noise_std: 0.,
+            dropout: 0.,
         }
     }
+    pub fn load(path: &Path) -> io::Result<Self> {
+        ron::from_str(&fs::read_to_string(path)?)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let text = ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        fs::write(path, text)
+    }
+}
+
+// CAR_SENSOR_LAYOUT=path/to/layout.ron replaces the default layout for spawned cars
+pub fn sensor_layout_start_system(mut cmd: Commands) {
+    let Ok(path) = std::env::var("CAR_SENSOR_LAYOUT") else {
+        return;
+    };
+    match SensorLayout::load(Path::new(&path)) {
+        Ok(layout) => {
+            println!("sensor layout {path}: {} rays", layout.rays.len());
+            cmd.insert_resource(layout);
+        }
+        Err(e) => println!("sensor layout not loaded, {path}: {e}"),
+    }
+}
+
+#[derive(Component, Debug)]
+pub struct CarSensors {
+    pub layout: SensorLayout,
+    // normalized 1. - toi / range per ray, 0. when nothing is hit
+    pub sensor_inputs: Vec<f32>,
+    pub hit_points: Vec<Option<Vec3>>,
+}
+
+impl CarSensors {
+    pub fn new(car_size: &CarSize) -> Self {
+        Self::from_layout(SensorLayout::new(car_size))
+    }
+    pub fn from_layout(layout: SensorLayout) -> Self {
+        let count = layout.rays.len();
+        Self {
+            layout,
+            sensor_inputs: vec![0.; count],
+            hit_points: vec![None; count],
+        }
+    }
+    pub fn len(&self) -> usize {
+        self.sensor_inputs.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.sensor_inputs.is_empty()
+    }
 }
 
 pub fn sensor_system(
     rapier_context: Res<RapierContext>,
-    config: Res<CarRes>,
+    #[cfg(feature = "graphics")] config: Res<crate::CarRes>,
     mut q_car: Query<(&mut CarSensors, &Transform)>,
-    mut gizmos: Gizmos,
+    mut sim_rng: Option<ResMut<SimRng>>,
+    #[cfg(feature = "graphics")] mut gizmos: Gizmos,
 ) {
-    let sensor_filter = QueryFilter::<'_>::exclude_dynamic().exclude_sensors();
+    // noise and dropout are reproducible in seeded sessions
+    let mut thread_rng = rand::thread_rng();
+    let rng: &mut dyn RngCore = match sim_rng.as_mut() {
+        Some(sim_rng) => &mut sim_rng.0,
+        None => &mut thread_rng,
+    };
     for (mut car, t) in q_car.iter_mut() {
-        let dir = Vec3::Z * car.max_toi;
-        let mut origins: Vec<Vec3> = Vec::new();
-        let mut dirs: Vec<Vec3> = Vec::new();
-        for a in 0..SENSOR_COUNT {
-            let (pos, far_quat) = car.sensor_config[a];
-            let origin = t.translation + t.rotation.mul_vec3(pos);
-            origins.push(origin);
-            let mut dir_vec = t.rotation.mul_vec3(far_quat.mul_vec3(dir));
-            dir_vec.y = 0.;
-            dirs.push(origin + dir_vec);
-        }
+        let car = car.as_mut();
+        let layout = &car.layout;
+        let sensor_filter = QueryFilter::<'_>::exclude_dynamic()
+            .exclude_sensors()
+            .groups(CollisionGroups::new(
+                Group::from_bits_truncate(layout.memberships),
+                Group::from_bits_truncate(layout.filters),
+            ));
+        car.sensor_inputs.resize(layout.rays.len(), 0.);
+        car.hit_points.resize(layout.rays.len(), None);
 
-        let mut inputs: Vec<f32> = vec![0.; SENSOR_COUNT];
-        let mut hit_points: Vec<Vec3> = vec![Vec3::ZERO; SENSOR_COUNT];
-        for (i, &ray_dir_pos) in dirs.iter().enumerate() {
-            let ray_pos = origins[i];
-            let ray_dir = (ray_dir_pos - ray_pos).normalize();
+        for (i, ray) in layout.rays.iter().enumerate() {
+            let ray_pos = t.translation + t.rotation.mul_vec3(Vec3::from(ray.origin));
+            let local_dir = Quat::from_rotation_y(ray.yaw)
+                .mul_quat(Quat::from_rotation_x(-ray.pitch))
+                .mul_vec3(Vec3::Z);
+            let mut ray_dir = t.rotation.mul_vec3(local_dir);
+            if ray.pitch == 0. {
+                ray_dir.y = 0.;
+            }
+            let ray_dir = ray_dir.normalize();
 
+            car.hit_points[i] = None;
+            car.sensor_inputs[i] = 0.;
+            if layout.dropout > 0. && rng.gen_bool(layout.dropout.min(1.) as f64) {
+                continue;
+            }
             if let Some((_e, toi)) =
-                rapier_context.cast_ray(ray_pos, ray_dir, car.max_toi, false, sensor_filter)
+                rapier_context.cast_ray(ray_pos, ray_dir, ray.range, false, sensor_filter)
             {
-                hit_points[i] = ray_pos + ray_dir * toi;
                 if toi > 0. {
-                    inputs[i] = 1. - toi / car.max_toi;
+                    let hit_point = ray_pos + ray_dir * toi;
+                    car.hit_points[i] = Some(hit_point);
+                    let mut input = 1. - toi / ray.range;
+                    if layout.noise_std > 0. {
+                        input += gaussian(rng, layout.noise_std);
+                    }
+                    car.sensor_inputs[i] = input.clamp(0., 1.);
+                    #[cfg(feature = "graphics")]
                     if config.show_rays {
-                        gizmos.line(ray_pos, hit_points[i], Color::srgba(0.5, 0.3, 0.3, 0.5));
+                        gizmos.line(ray_pos, hit_point, Color::srgba(0.5, 0.3, 0.3, 0.5));
                     }
-                } else {
-                    inputs[i] = 0.;
                 }
             }
         }
-        car.sensor_inputs = inputs;
         // println!("inputs {:#?}", car.sensor_inputs);
     }
 }
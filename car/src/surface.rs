@@ -0,0 +1,30 @@
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+// what a collider is made of, reported by sensors hitting it
+#[derive(Component, Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
+pub enum SurfaceKind {
+    Asphalt,
+    Kerb,
+    Wall,
+    Ground,
+    Car,
+    Wheel,
+    #[default]
+    Unknown,
+}
+
+impl SurfaceKind {
+    // stable label for point cloud files
+    pub fn label(&self) -> u8 {
+        match self {
+            SurfaceKind::Unknown => 0,
+            SurfaceKind::Asphalt => 1,
+            SurfaceKind::Kerb => 2,
+            SurfaceKind::Wall => 3,
+            SurfaceKind::Ground => 4,
+            SurfaceKind::Car => 5,
+            SurfaceKind::Wheel => 6,
+        }
+    }
+}
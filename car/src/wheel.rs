@@ -1,4 +1,4 @@
-use crate::{WheelMount, CAR_TRAINING_GROUP, STATIC_GROUP};
+use crate::{SurfaceKind, WheelMount, CAR_TRAINING_GROUP, STATIC_GROUP};
 use bevy::prelude::*;
 use bevy_rapier3d::prelude::*;
 use std::f32::consts::PI;
@@ -60,6 +60,7 @@ pub fn spawn_wheel(
     cmd.spawn((
         Name::new("wheel"),
         wheel,
+        SurfaceKind::Wheel,
         joint,
         #[cfg(feature = "graphics")]
         SceneBundle {
@@ -0,0 +1,27 @@
+// shared by the renet game server and client and the api token service
+
+pub const PROTOCOL_ID: u64 = 7;
+// renet::transport::NETCODE_KEY_BYTES
+pub const NETCODE_KEY_BYTES: usize = 32;
+
+// NETCODE_PRIVATE_KEY=64 hex characters shared by the game server and the token service
+pub fn private_key_from_env() -> Option<[u8; NETCODE_KEY_BYTES]> {
+    let hex = std::env::var("NETCODE_PRIVATE_KEY").ok()?;
+    Some(parse_private_key(&hex).expect("NETCODE_PRIVATE_KEY must be 64 hex characters"))
+}
+
+pub fn parse_private_key(hex: &str) -> Option<[u8; NETCODE_KEY_BYTES]> {
+    if hex.len() != NETCODE_KEY_BYTES * 2 {
+        return None;
+    }
+    let mut key = [0; NETCODE_KEY_BYTES];
+    for (i, byte) in key.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(hex.get(2 * i..2 * i + 2)?, 16).ok()?;
+    }
+    Some(key)
+}
+
+// RENET_UNSECURE=1 allows connections without a connect token, local games only
+pub fn unsecure_from_env() -> bool {
+    std::env::var("RENET_UNSECURE").is_ok_and(|v| v == "1")
+}
@@ -0,0 +1,186 @@
+use crate::params::*;
+use bevy::prelude::*;
+use bevy_garage_car::{gaussian, sensor::CarSensors, sim_rng_seed, Car, CarSpec, CarWheels, Wheel};
+use bevy_garage_track::TrackConfig;
+use bevy_rapier3d::prelude::*;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use std::collections::VecDeque;
+
+// randomisation applied to a car for one episode, ranges grow with difficulty
+#[derive(Component, Debug, Clone)]
+pub struct DomainRandomization {
+    pub heading: f32,
+    pub speed: f32,
+    pub friction_scale: f32,
+    pub mass_scale: f32,
+    pub torque_scale: f32,
+    pub sensor_noise: f32,
+}
+
+#[derive(Resource)]
+pub struct Curriculum {
+    pub seed: u64,
+    pub rng: StdRng,
+    // 0. - fixed start and default physics, 1. - full randomisation ranges
+    pub difficulty: f32,
+    pub outcomes: VecDeque<bool>,
+}
+
+impl Curriculum {
+    pub fn new(seed: u64, difficulty: f32) -> Self {
+        Self {
+            seed,
+            rng: StdRng::seed_from_u64(seed),
+            difficulty: difficulty.clamp(0., 1.),
+            outcomes: VecDeque::with_capacity(CURRICULUM_WINDOW),
+        }
+    }
+    // enabled with NN_CURRICULUM=1, NN_CURRICULUM_SEED and NN_CURRICULUM_DIFFICULTY are optional
+    pub fn from_env() -> Option<Self> {
+        if std::env::var("NN_CURRICULUM").map_or(true, |v| v != "1") {
+            return None;
+        }
+        let seed = std::env::var("NN_CURRICULUM_SEED")
+            .ok()
+            .and_then(|v| v.parse::<u64>().ok())
+            .unwrap_or(0);
+        let difficulty = std::env::var("NN_CURRICULUM_DIFFICULTY")
+            .ok()
+            .and_then(|v| v.parse::<f32>().ok())
+            .unwrap_or(0.);
+        Some(Self::new(seed, difficulty))
+    }
+    pub fn success_rate(&self) -> f32 {
+        let successes = self.outcomes.iter().filter(|s| **s).count();
+        successes as f32 / self.outcomes.len().max(1) as f32
+    }
+    // returns true when difficulty has changed
+    pub fn record_episode(&mut self, distance: f32) -> bool {
+        self.outcomes
+            .push_back(distance >= CURRICULUM_SUCCESS_METERS);
+        if self.outcomes.len() < CURRICULUM_WINDOW {
+            return false;
+        }
+        let success_rate = self.success_rate();
+        let difficulty = if success_rate >= CURRICULUM_ADVANCE_RATE {
+            (self.difficulty + CURRICULUM_STEP).min(1.)
+        } else if success_rate <= CURRICULUM_RETREAT_RATE {
+            (self.difficulty - CURRICULUM_STEP).max(0.)
+        } else {
+            self.outcomes.pop_front();
+            return false;
+        };
+        self.outcomes.clear();
+        if difficulty == self.difficulty {
+            return false;
+        }
+        println!(
+            "curriculum: success rate {success_rate:.2}, difficulty {:.2} -> {difficulty:.2}",
+            self.difficulty
+        );
+        self.difficulty = difficulty;
+        true
+    }
+    pub fn spawn_meters(&mut self, track_config: &TrackConfig) -> f32 {
+        let max_meters = self.difficulty * track_config.track_length;
+        let (_, meters) = track_config.get_transform_random_with(&mut self.rng, max_meters);
+        meters
+    }
+    fn range(&mut self, max: f32) -> f32 {
+        let max = max * self.difficulty;
+        if max <= 0. {
+            return 0.;
+        }
+        self.rng.gen_range(-max..max)
+    }
+    pub fn sample(&mut self) -> DomainRandomization {
+        DomainRandomization {
+            heading: self.range(CURRICULUM_MAX_HEADING),
+            speed: self.range(CURRICULUM_MAX_SPEED).abs(),
+            friction_scale: 1. + self.range(CURRICULUM_MAX_FRICTION),
+            mass_scale: 1. + self.range(CURRICULUM_MAX_MASS),
+            torque_scale: 1. + self.range(CURRICULUM_MAX_TORQUE),
+            sensor_noise: self.range(CURRICULUM_MAX_SENSOR_NOISE).abs(),
+        }
+    }
+}
+
+pub fn curriculum_spawned_car_system(
+    mut curriculum: ResMut<Curriculum>,
+    mut q_car: Query<
+        (
+            Entity,
+            &mut Transform,
+            &mut Velocity,
+            &mut CarSpec,
+            &mut ColliderMassProperties,
+            &CarWheels,
+        ),
+        Added<Car>,
+    >,
+    mut q_wheel: Query<(&mut Transform, &mut Velocity, &mut Friction), (With<Wheel>, Without<Car>)>,
+    mut cmd: Commands,
+) {
+    for (e, mut tr, mut v, mut spec, mut mass_properties, wheels) in q_car.iter_mut() {
+        let randomization = curriculum.sample();
+        let heading = Quat::from_rotation_y(randomization.heading);
+        let origin = tr.translation;
+        tr.rotation = heading * tr.rotation;
+        v.linvel = tr.rotation.mul_vec3(Vec3::Z) * randomization.speed;
+        spec.wheel_max_torque *= randomization.torque_scale;
+        if let ColliderMassProperties::MassProperties(mp) = mass_properties.as_mut() {
+            mp.mass *= randomization.mass_scale;
+            mp.principal_inertia *= randomization.mass_scale;
+        }
+        for wheel_entity in wheels.entities.iter() {
+            if let Ok((mut wheel_tr, mut wheel_v, mut friction)) = q_wheel.get_mut(*wheel_entity) {
+                wheel_tr.translation = origin + heading.mul_vec3(wheel_tr.translation - origin);
+                wheel_tr.rotation = heading * wheel_tr.rotation;
+                wheel_v.linvel = v.linvel;
+                friction.coefficient *= randomization.friction_scale;
+            }
+        }
+        cmd.entity(e).insert(randomization);
+    }
+}
+
+pub fn sensor_noise_system(
+    mut curriculum: ResMut<Curriculum>,
+    mut q_car: Query<(&mut CarSensors, &DomainRandomization)>,
+) {
+    for (mut car_sensors, randomization) in q_car.iter_mut() {
+        if randomization.sensor_noise <= 0. {
+            continue;
+        }
+        for input in car_sensors.sensor_inputs.iter_mut() {
+            *input =
+                (*input + gaussian(&mut curriculum.rng, randomization.sensor_noise)).clamp(0., 1.);
+        }
+    }
+}
+
+pub struct CurriculumPlugin;
+
+impl Plugin for CurriculumPlugin {
+    fn build(&self, app: &mut App) {
+        let Some(curriculum) = Curriculum::from_env() else {
+            return;
+        };
+        println!(
+            "curriculum: seed {}, difficulty {:.2}",
+            curriculum.seed, curriculum.difficulty
+        );
+        // sensor noise follows the curriculum seed
+        app.insert_resource(sim_rng_seed(curriculum.seed))
+            .insert_resource(curriculum)
+            .add_systems(
+                Update,
+                (
+                    curriculum_spawned_car_system,
+                    sensor_noise_system
+                        .in_set(bevy_garage_car::CarSet::Input)
+                        .after(bevy_garage_car::sensor::sensor_system),
+                ),
+            );
+    }
+}
@@ -1,10 +1,13 @@
-use crate::{dqn_bevy::*, gradient::get_sgd, params::*, util::*};
+use crate::{
+    curriculum::Curriculum, dqn_bevy::*, gradient::get_sgd, metrics::*, params::*,
+    reward::RewardInput, util::*,
+};
 use bevy::prelude::*;
 use bevy_garage_car::{
     sensor::CarSensors,
     CarWheels, {Car, Player},
 };
-use bevy_garage_track::{CarTrack, SpawnCarOnTrackEvent};
+use bevy_garage_track::{CarTrack, SpawnCarOnTrackEvent, TrackConfig};
 use bevy_rapier3d::prelude::*;
 use dfdx::prelude::*;
 use rand::Rng;
@@ -28,6 +31,9 @@ pub type Observation = [f32; STATE_SIZE];
 pub fn dqn_system(
     time: Res<Time>,
     mut dqn: ResMut<DqnResource>,
+    mut reward_metrics: ResMut<RewardMetrics>,
+    mut metrics_writer: Option<ResMut<MetricsWriter>>,
+    track_config: Res<TrackConfig>,
     mut cars_dqn: NonSendMut<CarsDqnResource>,
     dqn_tx: Res<DqnTx>,
     mut q_car: Query<(
@@ -42,8 +48,10 @@ pub fn dqn_system(
         &mut CarWheels,
     )>,
     q_colliding_entities: Query<&CollidingEntities, With<CollidingEntities>>,
+    q_sensor: Query<(), With<Sensor>>,
     mut cmd: Commands,
     mut car_spawn_events: EventWriter<SpawnCarOnTrackEvent>,
+    mut curriculum: Option<ResMut<Curriculum>>,
     #[cfg(feature = "api")] api: Res<crate::api_client::ApiClient>,
 ) {
     let seconds = time.elapsed_seconds_f64();
@@ -51,7 +59,10 @@ pub fn dqn_system(
         car_spawn_events.send(SpawnCarOnTrackEvent {
             player: dqn.respawn_player,
             index: dqn.respawn_index,
-            position: None,
+            position: curriculum
+                .as_mut()
+                .map(|curriculum| curriculum.spawn_meters(&track_config)),
+            slot: None,
         });
         dqn.respawn_in = 0.;
         dqn.respawn_player = false;
@@ -69,72 +80,40 @@ pub fn dqn_system(
         q_car.iter_mut()
     {
         let player = hid.is_some();
-        let mut crash: bool = false;
+        let crash = crashed(q_colliding_entities.get(e).ok(), &q_sensor);
 
-        let colliding_entities = q_colliding_entities.get(e);
-        if let Ok(colliding_entities) = colliding_entities {
-            for _ in colliding_entities.iter() {
-                crash = true;
-            }
-        }
-
-        let mut vel_angle = car_track.line_dir.angle_between(v.linvel);
-        if vel_angle.is_nan() {
-            vel_angle = 0.;
-        }
-        let pos_dir = tr.rotation.mul_vec3(Vec3::Z);
-        let mut pos_angle = car_track.line_dir.angle_between(pos_dir);
-        if pos_angle.is_nan() {
-            pos_angle = 0.;
-        }
-        let vel_cos = vel_angle.cos();
-        let pos_cos = pos_angle.cos();
-        let mut d_from_center = car_track.line_pos - tr.translation;
-        d_from_center.y = 0.;
-        let d = d_from_center.length();
-        let d_norm = d / 4.;
-
-        let velocity = v.linvel.length();
-        let mut velocity_reward = velocity / car_dqn.max_speed;
-        if velocity_reward > 1. {
-            // reduce reward when it's over desired speed
-            velocity_reward = 1. - (velocity_reward - 1.) / velocity_reward;
-        }
-        let shape_reward = || -> f32 {
-            if crash {
-                return -1.;
-            }
-            // https://team.inria.fr/rits/files/2018/02/ICRA18_EndToEndDriving_CameraReady.pdf
-            // In [13] the reward is computed as a function of the difference of angle α between the road and car’s heading and the speed v.
-            // R = v(cos α − d)
-            let mut reward = velocity_reward * (vel_cos - d_norm);
-            if vel_cos.is_sign_positive() && pos_cos.is_sign_negative() && reward.is_sign_positive()
-            {
-                // going backward
-                reward = -reward;
-            }
-            if reward.is_nan() {
-                return 0.;
-            }
-            return reward;
+        let obs = observe(&car_track, &car_sensors, v, tr);
+        let (velocity, d_norm, vel_cos, pos_cos) = (obs[0], obs[2], obs[3], obs[4]);
+        let reward_input = RewardInput {
+            crash,
+            velocity,
+            max_speed: car_dqn.max_speed,
+            vel_cos,
+            pos_cos,
+            d_norm,
+            track_length: track_config.track_length,
+            ride_distance: car_track.ride_distance,
+            prev_ride_distance: car_dqn.prev_ride_distance,
+            lap: car_track.lap,
+            prev_lap: car_dqn.prev_lap,
+            lap_seconds: seconds - car_dqn.lap_start_seconds,
+            steering: car.steering,
+            prev_steering: car_dqn.prev_steering,
+            step_seconds: STEP_DURATION,
         };
-        let reward = shape_reward();
-        let mut obs: Observation = [0.; STATE_SIZE];
-        for i in 0..STATE_SIZE {
-            obs[i] = match i {
-                0 => velocity,
-                1 => v.angvel.y,
-                2 => d_norm,
-                3 => vel_cos,
-                4 => pos_cos,
-                STATE_SIZE_BASE..=STATE_SIZE => car_sensors.sensor_inputs[i - STATE_SIZE_BASE],
-                _ => panic!("unknown observation record"),
-            };
-        }
+        let mut reward_terms = RewardTerms::default();
+        let reward = dqn.reward_fn.reward(&reward_input, &mut reward_terms);
 
         let (prev_action, prev_obs) = (car_dqn.prev_action, car_dqn.prev_obs);
         if dqn.use_nn && (should_act || crash) && !prev_obs.iter().all(|&x| x == 0.) {
             dqn.rb.store(prev_obs, prev_action, reward, obs, crash);
+            reward_metrics.extend(&reward_terms);
+            car_dqn.episode_return += reward;
+            car_dqn.episode_steps += 1;
+            if let Some(writer) = metrics_writer.as_mut() {
+                writer.step = dqn.step;
+                writer.record(METRIC_STEP_REWARD, reward);
+            }
             #[cfg(feature = "api")]
             if dqn.rb.i % crate::api_client::PERSIST_BATCH_SIZE == 0 {
                 api.save_replay_buffer(crate::api_client::get_replay_buffer_to_persist(&dqn.rb));
@@ -146,12 +125,34 @@ pub fn dqn_system(
             car_dqn.prev_obs = obs;
             car_dqn.prev_action = action;
             car_dqn.prev_reward = reward;
+            car_dqn.prev_ride_distance = car_track.ride_distance;
+            car_dqn.prev_steering = car.steering;
+            if reward_input.lap_completed() {
+                car_dqn.lap_start_seconds = seconds;
+            }
+            car_dqn.prev_lap = car_track.lap;
         }
         if !dqn.use_nn {
             return;
         }
         if crash {
             dqn.crashes += 1;
+            if let Some(writer) = metrics_writer.as_mut() {
+                writer.step = dqn.step;
+                writer.record(METRIC_EPISODE_RETURN, car_dqn.episode_return);
+                writer.record(METRIC_EPISODE_LENGTH, car_dqn.episode_steps as f32);
+                writer.record(METRIC_EPISODE_LAPS, car_track.lap.max(0) as f32);
+                writer.record(METRIC_CRASHES, dqn.crashes as f32);
+            }
+            if let Some(curriculum) = curriculum.as_mut() {
+                let distance =
+                    car_track.lap as f32 * track_config.track_length + car_track.ride_distance;
+                curriculum.record_episode(distance);
+                if let Some(writer) = metrics_writer.as_mut() {
+                    writer.record(METRIC_CURRICULUM_DIFFICULTY, curriculum.difficulty);
+                    writer.record(METRIC_CURRICULUM_SUCCESS_RATE, curriculum.success_rate());
+                }
+            }
             dqn.respawn_in = seconds;
             dqn.respawn_player = player;
             dqn.respawn_index = car_track.index;
@@ -183,6 +184,7 @@ pub fn dqn_system(
                 #[cfg(target_arch = "wasm32")]
                 {
                     let mut loss_string: String = String::from("");
+                    let mut loss_sum: f32 = 0.;
                     let mut sgd = get_sgd(&qn);
                     for _i_epoch in 0..EPOCHS {
                         let next_q_values: Tensor2D<BATCH_SIZE, ACTIONS> = tqn.forward(sn.clone());
@@ -195,6 +197,7 @@ pub fn dqn_system(
 
                         let loss = huber_loss(action_qs, target_q, 1.);
                         let loss_v = loss.array();
+                        loss_sum += loss_v;
                         // run backprop
                         let gradients = loss.backward();
                         sgd.update(&mut qn, &gradients).expect("Unused params");
@@ -205,8 +208,10 @@ pub fn dqn_system(
                     dqn_tx
                         .send(DqnX {
                             loss_string,
+                            loss: loss_sum / EPOCHS as f32,
                             qn,
                             duration_string: "-".to_string(),
+                            duration: None,
                         })
                         .unwrap();
                 }
@@ -216,6 +221,7 @@ pub fn dqn_system(
                     std::thread::spawn(move || {
                         let start = std::time::Instant::now();
                         let mut loss_string: String = String::from("");
+                        let mut loss_sum: f32 = 0.;
                         let mut sgd = get_sgd(&qn);
                         for _i_epoch in 0..EPOCHS {
                             let next_q_values: Tensor2D<BATCH_SIZE, ACTIONS> =
@@ -229,6 +235,7 @@ pub fn dqn_system(
 
                             let loss = huber_loss(action_qs, target_q, 1.);
                             let loss_v = loss.array();
+                            loss_sum += loss_v;
                             // run backprop
                             let gradients = loss.backward();
                             sgd.update(&mut qn, &gradients).expect("Unused params");
@@ -236,12 +243,15 @@ pub fn dqn_system(
                                 loss_string.push_str(format!("{:.2} ", loss_v).as_str());
                             }
                         }
-                        let duration_string = start.elapsed().as_millis().to_string() + "ms";
+                        let duration = start.elapsed();
+                        let duration_string = duration.as_millis().to_string() + "ms";
                         dqn_tx
                             .send(DqnX {
                                 loss_string,
+                                loss: loss_sum / EPOCHS as f32,
                                 qn,
                                 duration_string,
+                                duration: Some(duration),
                             })
                             .unwrap();
                     });
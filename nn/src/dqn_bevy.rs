@@ -2,8 +2,10 @@ use crate::{
     dash::{TrainerEpsilonText, TrainerGenerationText, TrainerRewardsText},
     dqn::*,
     gradient::get_sgd,
+    metrics::*,
     params::*,
     replay::ReplayBuffer,
+    reward::{reward_from_env, RewardFn},
 };
 use bevy::prelude::*;
 use crossbeam_channel::{bounded, Receiver, Sender};
@@ -16,6 +18,12 @@ pub struct CarDqn {
     pub prev_obs: Observation,
     pub prev_action: usize,
     pub prev_reward: f32,
+    pub prev_ride_distance: f32,
+    pub prev_lap: i32,
+    pub prev_steering: f32,
+    pub lap_start_seconds: f64,
+    pub episode_return: f32,
+    pub episode_steps: usize,
 }
 
 impl CarDqn {
@@ -25,6 +33,12 @@ impl CarDqn {
             prev_obs: [0.; STATE_SIZE],
             prev_action: 0,
             prev_reward: 0.,
+            prev_ride_distance: 0.,
+            prev_lap: 0,
+            prev_steering: 0.,
+            lap_start_seconds: 0.,
+            episode_return: 0.,
+            episode_steps: 0,
         }
     }
 }
@@ -87,6 +101,7 @@ pub struct DqnResource {
     pub max_eps: f32,
     pub min_eps: f32,
     pub done: f32,
+    pub reward_fn: Box<dyn RewardFn>,
 
     pub respawn_in: f64,
     pub respawn_player: bool,
@@ -104,6 +119,7 @@ impl DqnResource {
             max_eps: 1.,
             min_eps: 0.01,
             done: 0.,
+            reward_fn: reward_from_env(),
 
             respawn_in: 0.,
             respawn_player: false,
@@ -125,6 +141,8 @@ impl SgdResource {
 pub struct DqnX {
     pub loss_string: String,
     pub duration_string: String,
+    pub loss: f32,
+    pub duration: Option<std::time::Duration>,
     pub qn: QNetworkBuilt,
 }
 
@@ -155,6 +173,9 @@ pub fn dqn_rx_to_bevy_event_system(receiver: Res<DqnRx>, mut events: EventWriter
 pub fn dqn_event_reader_system(
     mut reader: EventReader<DqnEvent>,
     mut cars_dqn: NonSendMut<CarsDqnResource>,
+    mut reward_metrics: ResMut<RewardMetrics>,
+    mut metrics_writer: Option<ResMut<MetricsWriter>>,
+    dqn: Res<DqnResource>,
 ) {
     for event in reader.read() {
         // dbg!((&event.0.duration_string, &event.0.loss_string));
@@ -162,6 +183,24 @@ pub fn dqn_event_reader_system(
             "dqn_event:{}:{}",
             &event.0.duration_string, &event.0.loss_string
         );
+        println!("reward_terms:{}", reward_metrics.means_string());
+        if let Some(writer) = metrics_writer.as_mut() {
+            writer.step = dqn.step;
+            writer.record(METRIC_HUBER_LOSS, event.0.loss);
+            writer.record(METRIC_EPSILON, dqn.eps);
+            if let Some(duration) = event.0.duration {
+                writer.record(METRIC_TRAINING_MS, duration.as_secs_f32() * 1000.);
+            }
+            for key in reward_metrics.terms.keys() {
+                if let Some(mean) = reward_metrics.mean(key) {
+                    writer.record(*key, mean);
+                }
+            }
+            if let Err(e) = writer.flush() {
+                println!("metrics flush error: {e}");
+            }
+        }
+        reward_metrics.clear();
         cars_dqn.qn = event.0.qn.clone();
         cars_dqn.processing = false;
     }
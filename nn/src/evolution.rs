@@ -0,0 +1,162 @@
+use crate::{dqn::Observation, params::*};
+use rand::Rng;
+use std::{fs, io, path::Path};
+
+// fully connected STATE_SIZE -> EVOLUTION_HIDDEN_SIZE (relu) -> ACTIONS
+pub const GENOME_SIZE: usize = STATE_SIZE * EVOLUTION_HIDDEN_SIZE
+    + EVOLUTION_HIDDEN_SIZE
+    + EVOLUTION_HIDDEN_SIZE * ACTIONS
+    + ACTIONS;
+
+#[derive(Debug, Clone)]
+pub struct Genome {
+    pub weights: Vec<f32>,
+}
+
+impl Genome {
+    pub fn random() -> Self {
+        let mut rng = rand::thread_rng();
+        Self {
+            weights: (0..GENOME_SIZE).map(|_| rng.gen_range(-1.0..1.0)).collect(),
+        }
+    }
+    pub fn act(&self, obs: &Observation) -> usize {
+        let (w1, rest) = self.weights.split_at(STATE_SIZE * EVOLUTION_HIDDEN_SIZE);
+        let (b1, rest) = rest.split_at(EVOLUTION_HIDDEN_SIZE);
+        let (w2, b2) = rest.split_at(EVOLUTION_HIDDEN_SIZE * ACTIONS);
+
+        let mut hidden = [0.; EVOLUTION_HIDDEN_SIZE];
+        for (h, hidden_value) in hidden.iter_mut().enumerate() {
+            let row = &w1[h * STATE_SIZE..(h + 1) * STATE_SIZE];
+            let sum: f32 = row.iter().zip(obs.iter()).map(|(w, x)| w * x).sum();
+            *hidden_value = (sum + b1[h]).max(0.);
+        }
+        let mut action = 0;
+        let mut max_q = f32::MIN;
+        for a in 0..ACTIONS {
+            let row = &w2[a * EVOLUTION_HIDDEN_SIZE..(a + 1) * EVOLUTION_HIDDEN_SIZE];
+            let q: f32 = row
+                .iter()
+                .zip(hidden.iter())
+                .map(|(w, x)| w * x)
+                .sum::<f32>()
+                + b2[a];
+            if q > max_q {
+                max_q = q;
+                action = a;
+            }
+        }
+        action
+    }
+    // uniform crossover
+    pub fn crossover(&self, other: &Genome) -> Genome {
+        let mut rng = rand::thread_rng();
+        Genome {
+            weights: self
+                .weights
+                .iter()
+                .zip(other.weights.iter())
+                .map(|(a, b)| if rng.gen_bool(0.5) { *a } else { *b })
+                .collect(),
+        }
+    }
+    pub fn mutate(&mut self) {
+        let mut rng = rand::thread_rng();
+        for w in self.weights.iter_mut() {
+            if rng.gen_range(0.0..1.0) < MUTATION_RATE {
+                *w += rng.gen_range(-MUTATION_POWER..MUTATION_POWER);
+            }
+        }
+    }
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let text: Vec<String> = self.weights.iter().map(|w| w.to_string()).collect();
+        fs::write(path, text.join("\n"))
+    }
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let weights: Vec<f32> = fs::read_to_string(path)?
+            .lines()
+            .filter_map(|line| line.trim().parse::<f32>().ok())
+            .collect();
+        if weights.len() != GENOME_SIZE {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("genome size {} != {GENOME_SIZE}", weights.len()),
+            ));
+        }
+        Ok(Self { weights })
+    }
+}
+
+pub struct Population {
+    pub generation: usize,
+    pub genomes: Vec<Genome>,
+    pub fitness: Vec<f32>,
+    pub best: Option<(Genome, f32)>,
+}
+
+impl Population {
+    pub fn new(size: usize) -> Self {
+        Self {
+            generation: 0,
+            genomes: (0..size).map(|_| Genome::random()).collect(),
+            fitness: vec![0.; size],
+            best: None,
+        }
+    }
+    pub fn len(&self) -> usize {
+        self.genomes.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.genomes.is_empty()
+    }
+    pub fn mean_fitness(&self) -> f32 {
+        self.fitness.iter().sum::<f32>() / self.len().max(1) as f32
+    }
+    fn tournament(&self) -> usize {
+        let mut rng = rand::thread_rng();
+        let mut winner = rng.gen_range(0..self.len());
+        for _ in 1..TOURNAMENT_SIZE {
+            let i = rng.gen_range(0..self.len());
+            if self.fitness[i] > self.fitness[winner] {
+                winner = i;
+            }
+        }
+        winner
+    }
+    // elitism + tournament selection, uniform crossover and mutation
+    pub fn next_generation(&mut self) {
+        let mut ranked: Vec<usize> = (0..self.len()).collect();
+        ranked.sort_by(|a, b| self.fitness[*b].total_cmp(&self.fitness[*a]));
+
+        let leader = ranked[0];
+        let is_new_best = match &self.best {
+            Some((_, best_fitness)) => self.fitness[leader] > *best_fitness,
+            None => true,
+        };
+        if is_new_best {
+            self.best = Some((self.genomes[leader].clone(), self.fitness[leader]));
+        }
+
+        let mut genomes: Vec<Genome> = ranked
+            .iter()
+            .take(ELITE_COUNT.min(self.len()))
+            .map(|i| self.genomes[*i].clone())
+            .collect();
+        if !is_new_best {
+            if let Some((best, _)) = &self.best {
+                // all time best survives a bad generation
+                genomes.insert(0, best.clone());
+                genomes.truncate(self.len());
+            }
+        }
+        while genomes.len() < self.len() {
+            let (a, b) = (self.tournament(), self.tournament());
+            let mut child = self.genomes[a].crossover(&self.genomes[b]);
+            child.mutate();
+            genomes.push(child);
+        }
+        self.genomes = genomes;
+        self.fitness = vec![0.; self.len()];
+        self.generation += 1;
+    }
+}
@@ -0,0 +1,251 @@
+use crate::{
+    curriculum::Curriculum,
+    dash::{TrainerEpsilonText, TrainerGenerationText, TrainerRewardsText},
+    evolution::*,
+    params::*,
+    spawn::car_sensors,
+    util::*,
+};
+use bevy::prelude::*;
+use bevy_garage_car::{
+    sensor::{CarSensors, SensorLayout},
+    Car, CarSpec, CarWheels,
+};
+use bevy_garage_track::{CarTrack, SpawnCarOnTrackEvent, TrackConfig};
+use bevy_rapier3d::prelude::*;
+use std::path::PathBuf;
+
+#[derive(Component, Debug)]
+pub struct CarGenome {
+    pub index: usize,
+    pub lap: i32,
+    pub lap_start_seconds: f64,
+    pub best_lap_seconds: Option<f64>,
+    pub best_distance: f32,
+    pub best_distance_seconds: f64,
+}
+
+impl CarGenome {
+    pub fn new(index: usize, seconds: f64) -> Self {
+        Self {
+            index,
+            lap: 0,
+            lap_start_seconds: seconds,
+            best_lap_seconds: None,
+            best_distance: 0.,
+            best_distance_seconds: seconds,
+        }
+    }
+    // meters ridden plus a bonus for a lap faster than LAP_TIME_TARGET_SECONDS
+    pub fn fitness(&self, car_track: &CarTrack, track_length: f32) -> f32 {
+        let distance = car_track.lap as f32 * track_length + car_track.ride_distance;
+        let lap_bonus = match self.best_lap_seconds {
+            Some(lap_seconds) => track_length * (LAP_TIME_TARGET_SECONDS / lap_seconds) as f32,
+            None => 0.,
+        };
+        distance.max(0.) + lap_bonus
+    }
+}
+
+#[derive(Resource)]
+pub struct EvolutionResource {
+    pub population: Population,
+    pub generation_start_seconds: f64,
+    pub alive: usize,
+    pub best_path: Option<PathBuf>,
+}
+
+impl Default for EvolutionResource {
+    fn default() -> Self {
+        let mut population = Population::new(POPULATION_SIZE);
+        let best_path = std::env::var("NN_EVOLUTION_BEST").ok().map(PathBuf::from);
+        if let Some(path) = best_path.as_ref() {
+            match Genome::load(path) {
+                Ok(genome) => population.genomes[0] = genome,
+                Err(e) => println!("best genome not loaded, {:?}: {e}", path),
+            }
+        }
+        Self {
+            population,
+            generation_start_seconds: 0.,
+            alive: POPULATION_SIZE,
+            best_path,
+        }
+    }
+}
+
+// car index 0 is the Player car spawned by the app, the rest of population is spawned here
+pub fn evolution_start_system(
+    mut car_spawn_events: EventWriter<SpawnCarOnTrackEvent>,
+    track_config: Res<TrackConfig>,
+    mut curriculum: Option<ResMut<Curriculum>>,
+) {
+    for index in 1..POPULATION_SIZE {
+        car_spawn_events.send(SpawnCarOnTrackEvent {
+            player: false,
+            index,
+            position: Some(spawn_meters(&mut curriculum, &track_config)),
+            slot: None,
+        });
+    }
+}
+
+fn spawn_meters(curriculum: &mut Option<ResMut<Curriculum>>, track_config: &TrackConfig) -> f32 {
+    match curriculum.as_mut() {
+        Some(curriculum) => curriculum.spawn_meters(track_config),
+        None => 0.,
+    }
+}
+
+pub fn add_genome_on_spawned_car_system(
+    query: Query<(Entity, &CarSpec, &CarTrack), Added<Car>>,
+    mut cmd: Commands,
+    time: Res<Time>,
+    layout: Option<Res<SensorLayout>>,
+) {
+    for (car_entity, spec, car_track) in &query {
+        cmd.entity(car_entity)
+            .insert(CarGenome::new(car_track.index, time.elapsed_seconds_f64()))
+            .insert(car_sensors(&layout, spec));
+    }
+}
+
+pub fn evolution_system(
+    time: Res<Time>,
+    mut evo: ResMut<EvolutionResource>,
+    track_config: Res<TrackConfig>,
+    mut q_car: Query<(
+        &mut Car,
+        &CarTrack,
+        &CarSensors,
+        &Velocity,
+        &Transform,
+        Entity,
+        &mut CarGenome,
+        &mut CarWheels,
+    )>,
+    q_colliding_entities: Query<&CollidingEntities, With<CollidingEntities>>,
+    q_sensor: Query<(), With<Sensor>>,
+    mut cmd: Commands,
+    mut car_spawn_events: EventWriter<SpawnCarOnTrackEvent>,
+    mut curriculum: Option<ResMut<Curriculum>>,
+) {
+    let seconds = time.elapsed_seconds_f64();
+    let track_length = track_config.track_length;
+    let mut finished: Vec<Entity> = vec![];
+    for (mut car, car_track, car_sensors, v, tr, e, mut car_genome, mut wheels) in q_car.iter_mut()
+    {
+        if car_track.lap > car_genome.lap {
+            let lap_seconds = seconds - car_genome.lap_start_seconds;
+            if car_genome
+                .best_lap_seconds
+                .map_or(true, |best| lap_seconds < best)
+            {
+                car_genome.best_lap_seconds = Some(lap_seconds);
+            }
+            car_genome.lap_start_seconds = seconds;
+        }
+        car_genome.lap = car_track.lap;
+
+        let distance = car_track.lap as f32 * track_length + car_track.ride_distance;
+        if distance > car_genome.best_distance + 1. {
+            car_genome.best_distance = distance;
+            car_genome.best_distance_seconds = seconds;
+        }
+        let stall = seconds - car_genome.best_distance_seconds > STALL_SECONDS;
+        let crash = crashed(q_colliding_entities.get(e).ok(), &q_sensor);
+
+        if crash || stall {
+            if let Some(fitness) = evo.population.fitness.get_mut(car_genome.index) {
+                *fitness = car_genome.fitness(car_track, track_length);
+            }
+            if let Some(curriculum) = curriculum.as_mut() {
+                curriculum.record_episode(distance);
+            }
+            evo.alive = evo.alive.saturating_sub(1);
+            finished.push(e);
+            cmd.entity(e).despawn_recursive();
+            wheels.despawn(&mut cmd);
+            continue;
+        }
+
+        let Some(genome) = evo.population.genomes.get(car_genome.index) else {
+            continue;
+        };
+        let obs = observe(car_track, car_sensors, v, tr);
+        let (gas, brake, left, right) = map_action_to_car(genome.act(&obs));
+        car.gas = gas;
+        car.brake = brake;
+        car.steering = -left + right;
+    }
+
+    let timeout = seconds - evo.generation_start_seconds > GENERATION_SECONDS;
+    if evo.alive > 0 && !timeout {
+        return;
+    }
+    for (_, car_track, _, _, _, e, car_genome, mut wheels) in q_car.iter_mut() {
+        if finished.contains(&e) {
+            continue;
+        }
+        if let Some(fitness) = evo.population.fitness.get_mut(car_genome.index) {
+            *fitness = car_genome.fitness(car_track, track_length);
+        }
+        if let Some(curriculum) = curriculum.as_mut() {
+            curriculum.record_episode(car_genome.best_distance);
+        }
+        cmd.entity(e).despawn_recursive();
+        wheels.despawn(&mut cmd);
+    }
+    println!(
+        "generation {} mean fitness {:.1}",
+        evo.population.generation,
+        evo.population.mean_fitness()
+    );
+    evo.population.next_generation();
+    if let (Some((best, best_fitness)), Some(path)) = (&evo.population.best, &evo.best_path) {
+        match best.save(path) {
+            Ok(_) => println!("best genome {best_fitness:.1} saved to {:?}", path),
+            Err(e) => println!("best genome not saved, {:?}: {e}", path),
+        }
+    }
+    for index in 0..evo.population.len() {
+        car_spawn_events.send(SpawnCarOnTrackEvent {
+            player: index == 0,
+            index,
+            position: Some(spawn_meters(&mut curriculum, &track_config)),
+            slot: None,
+        });
+    }
+    evo.alive = evo.population.len();
+    evo.generation_start_seconds = seconds;
+}
+
+pub fn evolution_dash_update_system(
+    mut dash_set: ParamSet<(
+        Query<&mut Text, With<TrainerEpsilonText>>,
+        Query<&mut Text, With<TrainerGenerationText>>,
+        Query<&mut Text, With<TrainerRewardsText>>,
+    )>,
+    evo: Res<EvolutionResource>,
+) {
+    let mut q_generation_text = dash_set.p1();
+    let mut generation_text = q_generation_text.single_mut();
+    generation_text.sections[0].value = format!(
+        "generation {:?}, alive {:?}/{:?}",
+        evo.population.generation,
+        evo.alive,
+        evo.population.len()
+    );
+
+    let best_fitness = match &evo.population.best {
+        Some((_, fitness)) => *fitness,
+        None => 0.,
+    };
+    let mut q_best_text = dash_set.p0();
+    let mut best_text = q_best_text.single_mut();
+    best_text.sections[0].value = format!("best {:.1}", best_fitness);
+
+    let mut q_fitness_text = dash_set.p2();
+    let mut fitness_text = q_fitness_text.single_mut();
+    fitness_text.sections[0].value = format!("mean {:.1}", evo.population.mean_fitness());
+}
@@ -0,0 +1,262 @@
+use crate::{dqn::*, dqn_bevy::*, gradient::get_sgd, params::*, replay::ReplayBuffer, util::*};
+use bevy::prelude::*;
+use bevy_garage_car::{sensor::CarSensors, Car, Player};
+use bevy_garage_track::CarTrack;
+use bevy_rapier3d::prelude::*;
+use dfdx::prelude::*;
+use rand::Rng;
+use std::{
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+// human driving sample taken every STEP_DURATION
+#[derive(Debug, Clone)]
+pub struct Demonstration {
+    pub obs: Observation,
+    pub gas: f32,
+    pub brake: f32,
+    pub steering: f32,
+    pub action: usize,
+    pub reward: f32,
+    pub done: bool,
+}
+
+#[derive(Resource)]
+pub struct DemonstrationRecorder {
+    pub recording: bool,
+    pub seconds: f64,
+    pub demos: Vec<Demonstration>,
+    pub path: PathBuf,
+    pub pretrain: bool,
+    pub seed_replay_buffer: bool,
+}
+
+impl Default for DemonstrationRecorder {
+    fn default() -> Self {
+        let env_flag = |name: &str| std::env::var(name).map_or(false, |v| v == "1");
+        Self {
+            recording: false,
+            seconds: 0.,
+            demos: vec![],
+            path: PathBuf::from(
+                std::env::var("NN_DEMOS").unwrap_or_else(|_| "demos.csv".to_string()),
+            ),
+            pretrain: env_flag("NN_DEMOS_PRETRAIN"),
+            seed_replay_buffer: env_flag("NN_DEMOS_SEED_RB"),
+        }
+    }
+}
+
+// csv: STATE_SIZE observation values, gas, brake, steering, action, reward, done
+pub fn save_demos(path: &Path, demos: &[Demonstration]) -> io::Result<()> {
+    let mut file = io::BufWriter::new(fs::File::create(path)?);
+    for d in demos.iter() {
+        let obs: Vec<String> = d.obs.iter().map(|x| x.to_string()).collect();
+        writeln!(
+            file,
+            "{},{},{},{},{},{},{}",
+            obs.join(","),
+            d.gas,
+            d.brake,
+            d.steering,
+            d.action,
+            d.reward,
+            d.done as u8
+        )?;
+    }
+    file.flush()
+}
+
+pub fn load_demos(path: &Path) -> io::Result<Vec<Demonstration>> {
+    let invalid = |line: usize| io::Error::new(io::ErrorKind::InvalidData, format!("line {line}"));
+    let mut demos: Vec<Demonstration> = vec![];
+    for (i, line) in fs::read_to_string(path)?.lines().enumerate() {
+        let values: Vec<f32> = line
+            .split(',')
+            .map(|x| x.parse::<f32>().map_err(|_| invalid(i)))
+            .collect::<io::Result<Vec<f32>>>()?;
+        if values.len() != STATE_SIZE + 6 {
+            return Err(invalid(i));
+        }
+        let mut obs: Observation = [0.; STATE_SIZE];
+        obs.copy_from_slice(&values[..STATE_SIZE]);
+        let tail = &values[STATE_SIZE..];
+        // the action indexes the q values
+        if tail[3] < 0. || tail[3] >= ACTIONS as f32 || tail[3].fract() != 0. {
+            return Err(invalid(i));
+        }
+        demos.push(Demonstration {
+            obs,
+            gas: tail[0],
+            brake: tail[1],
+            steering: tail[2],
+            action: tail[3] as usize,
+            reward: tail[4],
+            done: tail[5] == 1.,
+        });
+    }
+    Ok(demos)
+}
+
+// behavioural cloning, q values are trained as logits of the human action
+pub fn pretrain(qn: &mut QNetworkBuilt, demos: &[Demonstration], device: &AutoDevice) -> f32 {
+    let mut sgd = get_sgd(&*qn);
+    let gradients = qn.alloc_grads();
+    let mut rng = rand::thread_rng();
+    let mut loss_v: f32 = 0.;
+    for _i_epoch in 0..PRETRAIN_EPOCHS {
+        let mut states: [[f32; STATE_SIZE]; BATCH_SIZE] = [[0.; STATE_SIZE]; BATCH_SIZE];
+        let mut targets: [[f32; ACTIONS]; BATCH_SIZE] = [[0.; ACTIONS]; BATCH_SIZE];
+        for i in 0..BATCH_SIZE {
+            let demo = &demos[rng.gen_range(0..demos.len())];
+            states[i] = demo.obs;
+            targets[i][demo.action] = 1.;
+        }
+        let s: Tensor2D<BATCH_SIZE, STATE_SIZE> = device.tensor_from_vec(
+            states.flatten().to_vec(),
+            (Const::<BATCH_SIZE>, Const::<STATE_SIZE>),
+        );
+        let t: Tensor2D<BATCH_SIZE, ACTIONS> = device.tensor_from_vec(
+            targets.flatten().to_vec(),
+            (Const::<BATCH_SIZE>, Const::<ACTIONS>),
+        );
+        let logits = qn.forward(s.trace(gradients.clone()));
+        let loss = cross_entropy_with_logits_loss(logits, t);
+        loss_v = loss.array();
+        let gradients = loss.backward();
+        sgd.update(qn, &gradients).expect("Unused params");
+    }
+    loss_v
+}
+
+// DQfD style, demonstrations become regular transitions in the replay buffer
+pub fn seed_replay_buffer(rb: &mut ReplayBuffer, demos: &[Demonstration]) {
+    for pair in demos.windows(2) {
+        let (d, dn) = (&pair[0], &pair[1]);
+        if d.done {
+            continue;
+        }
+        rb.store(d.obs, d.action, dn.reward, dn.obs, dn.done);
+    }
+}
+
+fn apply_demos(
+    demos: &[Demonstration],
+    pretrain_qn: bool,
+    seed_rb: bool,
+    dqn: &mut DqnResource,
+    cars_dqn: &mut CarsDqnResource,
+) {
+    if demos.len() < 2 {
+        println!("demos: not enough records {}", demos.len());
+        return;
+    }
+    if pretrain_qn {
+        let mut qn = cars_dqn.qn.clone();
+        let loss = pretrain(&mut qn, demos, &cars_dqn.device);
+        println!(
+            "demos: pretrained on {} records, loss {loss:.3}",
+            demos.len()
+        );
+        cars_dqn.qn = qn.clone();
+        cars_dqn.tqn = qn;
+    }
+    if seed_rb {
+        seed_replay_buffer(&mut dqn.rb, demos);
+        println!("demos: replay buffer seeded, rb {}", dqn.rb.len());
+    }
+}
+
+pub fn imitation_start_system(
+    recorder: Res<DemonstrationRecorder>,
+    mut dqn: ResMut<DqnResource>,
+    mut cars_dqn: NonSendMut<CarsDqnResource>,
+) {
+    if !recorder.pretrain && !recorder.seed_replay_buffer {
+        return;
+    }
+    match load_demos(&recorder.path) {
+        Ok(demos) => apply_demos(
+            &demos,
+            recorder.pretrain,
+            recorder.seed_replay_buffer,
+            &mut dqn,
+            &mut cars_dqn,
+        ),
+        Err(e) => println!("demos not loaded, {:?}: {e}", &recorder.path),
+    }
+}
+
+// F5 - start/stop recording (saved on stop), F6 - pretrain and seed from recorded demos
+pub fn imitation_input_system(
+    input: Res<ButtonInput<KeyCode>>,
+    mut recorder: ResMut<DemonstrationRecorder>,
+    mut dqn: ResMut<DqnResource>,
+    mut cars_dqn: NonSendMut<CarsDqnResource>,
+) {
+    if input.just_pressed(KeyCode::F5) {
+        recorder.recording = !recorder.recording;
+        if recorder.recording {
+            recorder.demos.clear();
+            println!("demos: recording");
+        } else {
+            match save_demos(&recorder.path, &recorder.demos) {
+                Ok(_) => println!(
+                    "demos: {} records saved to {:?}",
+                    recorder.demos.len(),
+                    &recorder.path
+                ),
+                Err(e) => println!("demos not saved, {:?}: {e}", &recorder.path),
+            }
+        }
+    }
+    if input.just_pressed(KeyCode::F6) {
+        let demos = if recorder.demos.is_empty() {
+            load_demos(&recorder.path).unwrap_or_default()
+        } else {
+            recorder.demos.clone()
+        };
+        let seed_rb = recorder.seed_replay_buffer;
+        apply_demos(&demos, true, seed_rb, &mut dqn, &mut cars_dqn);
+    }
+}
+
+pub fn imitation_record_system(
+    time: Res<Time>,
+    mut recorder: ResMut<DemonstrationRecorder>,
+    dqn: Res<DqnResource>,
+    q_car: Query<
+        (
+            &Car,
+            &CarTrack,
+            &CarSensors,
+            &Velocity,
+            &Transform,
+            &CarDqn,
+            Entity,
+        ),
+        With<Player>,
+    >,
+    q_colliding_entities: Query<&CollidingEntities, With<CollidingEntities>>,
+    q_sensor: Query<(), With<Sensor>>,
+) {
+    let seconds = time.elapsed_seconds_f64();
+    if !recorder.recording || dqn.use_nn || seconds < recorder.seconds {
+        return;
+    }
+    recorder.seconds = seconds + STEP_DURATION;
+    for (car, car_track, car_sensors, v, tr, car_dqn, e) in q_car.iter() {
+        let done = crashed(q_colliding_entities.get(e).ok(), &q_sensor);
+        recorder.demos.push(Demonstration {
+            obs: observe(car_track, car_sensors, v, tr),
+            gas: car.gas,
+            brake: car.brake,
+            steering: car.steering,
+            action: map_car_to_action(car.gas, car.brake, car.steering),
+            reward: car_dqn.prev_reward,
+            done,
+        });
+    }
+}
@@ -3,27 +3,49 @@
 #[cfg(feature = "api")]
 pub mod api_client;
 
+pub mod curriculum;
 pub mod dash;
 pub mod dqn;
 pub mod dqn_bevy;
+pub mod evolution;
+pub mod evolution_bevy;
 pub mod gradient;
+pub mod imitation;
+pub mod metrics;
 pub mod params;
 pub mod replay;
+pub mod reward;
 pub mod spawn;
+pub mod tensorboard;
 pub mod util;
 
-use crate::{dqn::dqn_system, dqn_bevy::*, spawn::*};
+use crate::{
+    curriculum::CurriculumPlugin, dqn::dqn_system, dqn_bevy::*, evolution_bevy::*, imitation::*,
+    metrics::*, spawn::*,
+};
 use bevy::prelude::{App, IntoSystemConfigs, Plugin, Startup, Update};
-use bevy_garage_car::CarSet;
+use bevy_garage_car::{sensor::sensor_layout_start_system, CarSet};
 pub use dqn_bevy::DqnResource;
+pub use evolution_bevy::EvolutionResource;
 
 pub struct NeuralNetworkPlugin;
 
 impl Plugin for NeuralNetworkPlugin {
     fn build(&self, app: &mut App) {
         app.insert_resource(DqnResource::default())
+            .add_plugins(CurriculumPlugin)
+            .init_resource::<RewardMetrics>()
+            .init_resource::<DemonstrationRecorder>()
             .add_event::<DqnEvent>()
-            .add_systems(Startup, (dqn_start_system, dqn_x_start_system))
+            .add_systems(
+                Startup,
+                (
+                    dqn_start_system,
+                    dqn_x_start_system,
+                    imitation_start_system.after(dqn_start_system),
+                    sensor_layout_start_system,
+                ),
+            )
             .add_systems(
                 Update,
                 (
@@ -35,9 +57,16 @@ impl Plugin for NeuralNetworkPlugin {
                         .in_set(CarSet::NeuralNetwork)
                         .after(CarSet::Input),
                     dqn_dash_update_system,
+                    imitation_input_system,
+                    imitation_record_system
+                        .in_set(CarSet::NeuralNetwork)
+                        .after(dqn_system),
                 ),
             );
 
+        #[cfg(not(target_arch = "wasm32"))]
+        app.add_systems(Startup, metrics_start_system);
+
         #[cfg(feature = "api")]
         {
             use crate::api_client::*;
@@ -50,3 +79,28 @@ impl Plugin for NeuralNetworkPlugin {
         }
     }
 }
+
+// population based alternative to NeuralNetworkPlugin, use one of them
+pub struct NeuroEvolutionPlugin;
+
+impl Plugin for NeuroEvolutionPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<EvolutionResource>()
+            .add_plugins(CurriculumPlugin)
+            .add_systems(
+                Startup,
+                (evolution_start_system, sensor_layout_start_system),
+            )
+            .add_systems(
+                Update,
+                (
+                    add_genome_on_spawned_car_system,
+                    bevy_garage_car::sensor::sensor_system.in_set(CarSet::Input),
+                    evolution_system
+                        .in_set(CarSet::NeuralNetwork)
+                        .after(CarSet::Input),
+                    evolution_dash_update_system,
+                ),
+            );
+    }
+}
@@ -0,0 +1,168 @@
+use crate::tensorboard::TensorBoardWriter;
+use bevy::prelude::*;
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+pub const METRIC_STEP_REWARD: &str = "step/reward";
+pub const METRIC_EPISODE_RETURN: &str = "episode/return";
+pub const METRIC_EPISODE_LENGTH: &str = "episode/length";
+pub const METRIC_EPISODE_LAPS: &str = "episode/laps";
+pub const METRIC_CRASHES: &str = "train/crashes";
+pub const METRIC_HUBER_LOSS: &str = "train/huber_loss";
+pub const METRIC_EPSILON: &str = "train/epsilon";
+pub const METRIC_TRAINING_MS: &str = "train/duration_ms";
+pub const METRIC_CURRICULUM_DIFFICULTY: &str = "curriculum/difficulty";
+pub const METRIC_CURRICULUM_SUCCESS_RATE: &str = "curriculum/success_rate";
+
+pub trait MetricsSink {
+    fn record(&mut self, key: &'static str, value: f32);
+}
+
+// per-step terms of a single reward calculation
+#[derive(Debug, Default)]
+pub struct RewardTerms(pub Vec<(&'static str, f32)>);
+
+impl MetricsSink for RewardTerms {
+    fn record(&mut self, key: &'static str, value: f32) {
+        self.0.push((key, value));
+    }
+}
+
+// running sum and count per reward term, flushed on every training round
+#[derive(Resource, Debug, Default)]
+pub struct RewardMetrics {
+    pub terms: BTreeMap<&'static str, (f32, usize)>,
+}
+
+impl MetricsSink for RewardMetrics {
+    fn record(&mut self, key: &'static str, value: f32) {
+        let (sum, count) = self.terms.entry(key).or_insert((0., 0));
+        *sum += value;
+        *count += 1;
+    }
+}
+
+impl RewardMetrics {
+    pub fn extend(&mut self, terms: &RewardTerms) {
+        for (key, value) in terms.0.iter() {
+            self.record(key, *value);
+        }
+    }
+    pub fn mean(&self, key: &str) -> Option<f32> {
+        self.terms
+            .get(key)
+            .filter(|(_, count)| *count > 0)
+            .map(|(sum, count)| sum / *count as f32)
+    }
+    pub fn means_string(&self) -> String {
+        self.terms
+            .iter()
+            .filter(|(_, (_, count))| *count > 0)
+            .map(|(key, (sum, count))| format!("{key}={:.3}", sum / *count as f32))
+            .collect::<Vec<String>>()
+            .join(" ")
+    }
+    pub fn clear(&mut self) {
+        self.terms.clear();
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MetricsFormat {
+    Csv,
+    TensorBoard,
+    All,
+}
+
+// writes scalars as csv rows (wall_time,step,key,value) and/or tensorboard events
+#[derive(Resource)]
+pub struct MetricsWriter {
+    pub step: usize,
+    pub dir: PathBuf,
+    csv: Option<BufWriter<File>>,
+    tensorboard: Option<TensorBoardWriter>,
+}
+
+impl MetricsWriter {
+    pub fn new(dir: &Path, format: MetricsFormat) -> io::Result<Self> {
+        std::fs::create_dir_all(dir)?;
+        let csv = match format {
+            MetricsFormat::Csv | MetricsFormat::All => {
+                let mut csv = BufWriter::new(File::create(dir.join("metrics.csv"))?);
+                writeln!(csv, "wall_time,step,key,value")?;
+                Some(csv)
+            }
+            MetricsFormat::TensorBoard => None,
+        };
+        let tensorboard = match format {
+            MetricsFormat::TensorBoard | MetricsFormat::All => Some(TensorBoardWriter::new(dir)?),
+            MetricsFormat::Csv => None,
+        };
+        Ok(Self {
+            step: 0,
+            dir: dir.to_path_buf(),
+            csv,
+            tensorboard,
+        })
+    }
+    pub fn write(&mut self, key: &str, value: f32) -> io::Result<()> {
+        if let Some(csv) = self.csv.as_mut() {
+            let wall_time = SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap()
+                .as_secs_f64();
+            writeln!(csv, "{wall_time:.3},{},{key},{value}", self.step)?;
+        }
+        if let Some(tensorboard) = self.tensorboard.as_mut() {
+            tensorboard.add_scalar(key, value, self.step)?;
+        }
+        Ok(())
+    }
+    pub fn flush(&mut self) -> io::Result<()> {
+        if let Some(csv) = self.csv.as_mut() {
+            csv.flush()?;
+        }
+        if let Some(tensorboard) = self.tensorboard.as_mut() {
+            tensorboard.flush()?;
+        }
+        Ok(())
+    }
+}
+
+impl MetricsSink for MetricsWriter {
+    fn record(&mut self, key: &'static str, value: f32) {
+        if let Err(e) = self.write(key, value) {
+            println!("metrics write error: {e}");
+        }
+    }
+}
+
+pub fn metrics_start_system(mut cmd: Commands) {
+    let dir = if let Ok(dir) = std::env::var("NN_METRICS_DIR") {
+        PathBuf::from(dir)
+    } else {
+        let seconds = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        let default = PathBuf::from(format!("runs/{seconds}"));
+        println!("NN_METRICS_DIR not set, setting default: {:?}", &default);
+        default
+    };
+    let format = match std::env::var("NN_METRICS_FORMAT").as_deref() {
+        Ok("csv") => MetricsFormat::Csv,
+        Ok("tensorboard") => MetricsFormat::TensorBoard,
+        _ => MetricsFormat::All,
+    };
+    match MetricsWriter::new(&dir, format) {
+        Ok(writer) => {
+            cmd.insert_resource(writer);
+        }
+        Err(e) => println!("metrics writer disabled, {:?}: {e}", &dir),
+    }
+}
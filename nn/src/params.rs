@@ -32,3 +32,31 @@ pub const BUFFER_SIZE: usize = 10_000_000;
 pub const STATE_SIZE_BASE: usize = 5;
 pub const STATE_SIZE: usize = STATE_SIZE_BASE + SENSOR_COUNT;
 pub const ACTIONS: usize = 9; //
+
+#[cfg(target_arch = "wasm32")]
+pub const POPULATION_SIZE: usize = 8;
+#[cfg(not(target_arch = "wasm32"))]
+pub const POPULATION_SIZE: usize = 32;
+
+pub const EVOLUTION_HIDDEN_SIZE: usize = 16;
+pub const ELITE_COUNT: usize = 2;
+pub const TOURNAMENT_SIZE: usize = 3;
+pub const MUTATION_RATE: f32 = 0.05;
+pub const MUTATION_POWER: f32 = 0.3;
+pub const GENERATION_SECONDS: f64 = 60.;
+pub const STALL_SECONDS: f64 = 5.;
+pub const LAP_TIME_TARGET_SECONDS: f64 = 120.;
+
+pub const PRETRAIN_EPOCHS: usize = 500;
+
+pub const CURRICULUM_WINDOW: usize = 20;
+pub const CURRICULUM_SUCCESS_METERS: f32 = 500.;
+pub const CURRICULUM_ADVANCE_RATE: f32 = 0.7;
+pub const CURRICULUM_RETREAT_RATE: f32 = 0.2;
+pub const CURRICULUM_STEP: f32 = 0.1;
+pub const CURRICULUM_MAX_HEADING: f32 = 0.3;
+pub const CURRICULUM_MAX_SPEED: f32 = 20.;
+pub const CURRICULUM_MAX_FRICTION: f32 = 0.3;
+pub const CURRICULUM_MAX_MASS: f32 = 0.2;
+pub const CURRICULUM_MAX_TORQUE: f32 = 0.2;
+pub const CURRICULUM_MAX_SENSOR_NOISE: f32 = 0.05;
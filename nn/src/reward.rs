@@ -0,0 +1,186 @@
+use crate::metrics::MetricsSink;
+
+pub const REWARD_SHAPED: &str = "reward/shaped";
+pub const REWARD_CRASH: &str = "reward/crash";
+pub const REWARD_PROGRESS: &str = "reward/progress";
+pub const REWARD_LAP_TIME: &str = "reward/lap_time";
+pub const REWARD_STEERING: &str = "reward/steering";
+pub const REWARD_TOTAL: &str = "reward/total";
+
+#[derive(Debug, Clone, Default)]
+pub struct RewardInput {
+    pub crash: bool,
+    pub velocity: f32,
+    pub max_speed: f32,
+    pub vel_cos: f32,
+    pub pos_cos: f32,
+    pub d_norm: f32,
+    pub track_length: f32,
+    pub ride_distance: f32,
+    pub prev_ride_distance: f32,
+    pub lap: i32,
+    pub prev_lap: i32,
+    pub lap_seconds: f64,
+    pub steering: f32,
+    pub prev_steering: f32,
+    pub step_seconds: f64,
+}
+
+impl RewardInput {
+    // ride_distance wraps every lap, so lap count is added to keep it continuous
+    pub fn progress(&self) -> f32 {
+        let total = self.lap as f32 * self.track_length + self.ride_distance;
+        let prev_total = self.prev_lap as f32 * self.track_length + self.prev_ride_distance;
+        total - prev_total
+    }
+    pub fn lap_completed(&self) -> bool {
+        self.lap > self.prev_lap
+    }
+}
+
+pub trait RewardFn: Send + Sync {
+    fn reward(&self, input: &RewardInput, sink: &mut dyn MetricsSink) -> f32;
+}
+
+// https://team.inria.fr/rits/files/2018/02/ICRA18_EndToEndDriving_CameraReady.pdf
+// In [13] the reward is computed as a function of the difference of angle α between the road and car’s heading and the speed v.
+// R = v(cos α − d)
+pub struct ShapedReward;
+
+impl RewardFn for ShapedReward {
+    fn reward(&self, input: &RewardInput, sink: &mut dyn MetricsSink) -> f32 {
+        if input.crash {
+            sink.record(REWARD_CRASH, -1.);
+            return -1.;
+        }
+        let mut velocity_reward = input.velocity / input.max_speed;
+        if velocity_reward > 1. {
+            // reduce reward when it's over desired speed
+            velocity_reward = 1. - (velocity_reward - 1.) / velocity_reward;
+        }
+        let mut reward = velocity_reward * (input.vel_cos - input.d_norm);
+        if input.vel_cos.is_sign_positive()
+            && input.pos_cos.is_sign_negative()
+            && reward.is_sign_positive()
+        {
+            // going backward
+            reward = -reward;
+        }
+        if reward.is_nan() {
+            reward = 0.;
+        }
+        sink.record(REWARD_SHAPED, reward);
+        reward
+    }
+}
+
+// meters gained along the track per step, normalized by max_speed
+pub struct ProgressReward {
+    pub weight: f32,
+}
+
+impl Default for ProgressReward {
+    fn default() -> Self {
+        Self { weight: 1. }
+    }
+}
+
+impl RewardFn for ProgressReward {
+    fn reward(&self, input: &RewardInput, sink: &mut dyn MetricsSink) -> f32 {
+        if input.crash {
+            sink.record(REWARD_CRASH, -1.);
+            return -1.;
+        }
+        let max_progress = input.max_speed * input.step_seconds as f32;
+        let mut reward = match max_progress {
+            x if x > 0. => (input.progress() / x).clamp(-1., 1.) * self.weight,
+            _ => 0.,
+        };
+        if reward.is_nan() {
+            reward = 0.;
+        }
+        sink.record(REWARD_PROGRESS, reward);
+        reward
+    }
+}
+
+// bonus on lap completion, bigger for laps faster than target_seconds
+pub struct LapTimeReward {
+    pub weight: f32,
+    pub target_seconds: f64,
+}
+
+impl Default for LapTimeReward {
+    fn default() -> Self {
+        Self {
+            weight: 10.,
+            target_seconds: 120.,
+        }
+    }
+}
+
+impl RewardFn for LapTimeReward {
+    fn reward(&self, input: &RewardInput, sink: &mut dyn MetricsSink) -> f32 {
+        if !input.lap_completed() || input.lap_seconds <= 0. {
+            return 0.;
+        }
+        let reward = (self.target_seconds / input.lap_seconds) as f32 * self.weight;
+        sink.record(REWARD_LAP_TIME, reward);
+        reward
+    }
+}
+
+pub struct SteeringSmoothnessPenalty {
+    pub weight: f32,
+}
+
+impl Default for SteeringSmoothnessPenalty {
+    fn default() -> Self {
+        Self { weight: 0.1 }
+    }
+}
+
+impl RewardFn for SteeringSmoothnessPenalty {
+    fn reward(&self, input: &RewardInput, sink: &mut dyn MetricsSink) -> f32 {
+        let reward = -(input.steering - input.prev_steering).abs() * self.weight;
+        sink.record(REWARD_STEERING, reward);
+        reward
+    }
+}
+
+pub struct RewardSum(pub Vec<Box<dyn RewardFn>>);
+
+impl RewardFn for RewardSum {
+    fn reward(&self, input: &RewardInput, sink: &mut dyn MetricsSink) -> f32 {
+        // terms penalise a crash on their own, the sum does it once
+        if input.crash {
+            sink.record(REWARD_CRASH, -1.);
+            sink.record(REWARD_TOTAL, -1.);
+            return -1.;
+        }
+        let reward = self.0.iter().map(|f| f.reward(input, sink)).sum();
+        sink.record(REWARD_TOTAL, reward);
+        reward
+    }
+}
+
+// NN_REWARD=terms joined with + out of shaped, progress, lap_time, smooth, shaped by default
+pub fn reward_from_env() -> Box<dyn RewardFn> {
+    let names = std::env::var("NN_REWARD").unwrap_or("shaped".to_string());
+    let mut terms: Vec<Box<dyn RewardFn>> = vec![];
+    for name in names.split('+').map(str::trim) {
+        match name {
+            "shaped" => terms.push(Box::new(ShapedReward)),
+            "progress" => terms.push(Box::<ProgressReward>::default()),
+            "lap_time" => terms.push(Box::<LapTimeReward>::default()),
+            "smooth" => terms.push(Box::<SteeringSmoothnessPenalty>::default()),
+            _ => println!("unknown reward term {name:?}"),
+        }
+    }
+    println!("reward {names}");
+    match terms.len() {
+        0 => Box::new(ShapedReward),
+        1 => terms.remove(0),
+        _ => Box::new(RewardSum(terms)),
+    }
+}
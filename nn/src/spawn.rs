@@ -1,14 +1,29 @@
 use crate::dqn_bevy::CarDqn;
 use bevy::prelude::*;
-use bevy_garage_car::{sensor::CarSensors, Car, CarSpec};
+use bevy_garage_car::{
+    sensor::{CarSensors, SensorLayout},
+    Car, CarSpec,
+};
 
 pub fn add_dqn_on_spawned_car_system(
     query: Query<(Entity, &CarSpec), Added<Car>>,
     mut cmd: Commands,
+    time: Res<Time>,
+    layout: Option<Res<SensorLayout>>,
 ) {
     for (car_entity, spec) in &query {
         cmd.entity(car_entity)
-            .insert(CarDqn::new())
-            .insert(CarSensors::new(&spec.size));
+            .insert(CarDqn {
+                lap_start_seconds: time.elapsed_seconds_f64(),
+                ..CarDqn::new()
+            })
+            .insert(car_sensors(&layout, spec));
+    }
+}
+
+pub fn car_sensors(layout: &Option<Res<SensorLayout>>, spec: &CarSpec) -> CarSensors {
+    match layout {
+        Some(layout) => CarSensors::from_layout(layout.as_ref().clone()),
+        None => CarSensors::new(&spec.size),
     }
 }
@@ -0,0 +1,110 @@
+// Minimal TensorBoard event file writer, scalars only.
+// Each event is a TFRecord: len u64, masked crc32c(len), data, masked crc32c(data).
+// Data is a serialized tensorflow.Event protobuf, see
+// https://github.com/tensorflow/tensorflow/blob/master/tensorflow/core/util/event.proto
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::Path,
+    time::SystemTime,
+};
+
+pub struct TensorBoardWriter {
+    writer: BufWriter<File>,
+}
+
+impl TensorBoardWriter {
+    pub fn new(dir: &Path) -> io::Result<Self> {
+        let wall_time = wall_time();
+        let file_name = format!("events.out.tfevents.{}.bevy_garage", wall_time as u64);
+        let mut tb = Self {
+            writer: BufWriter::new(File::create(dir.join(file_name))?),
+        };
+        let mut event = event_header(wall_time, 0);
+        // file_version = 3
+        write_bytes_field(&mut event, 3, b"brain.Event:2");
+        tb.write_record(&event)?;
+        Ok(tb)
+    }
+    pub fn add_scalar(&mut self, tag: &str, value: f32, step: usize) -> io::Result<()> {
+        // Summary.Value: tag = 1, simple_value = 2
+        let mut summary_value: Vec<u8> = vec![];
+        write_bytes_field(&mut summary_value, 1, tag.as_bytes());
+        write_key(&mut summary_value, 2, 5);
+        summary_value.extend(value.to_le_bytes());
+        // Summary: repeated Value value = 1
+        let mut summary: Vec<u8> = vec![];
+        write_bytes_field(&mut summary, 1, &summary_value);
+        // Event: summary = 5
+        let mut event = event_header(wall_time(), step as i64);
+        write_bytes_field(&mut event, 5, &summary);
+        self.write_record(&event)
+    }
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+    fn write_record(&mut self, data: &[u8]) -> io::Result<()> {
+        let len = (data.len() as u64).to_le_bytes();
+        self.writer.write_all(&len)?;
+        self.writer.write_all(&masked_crc32c(&len).to_le_bytes())?;
+        self.writer.write_all(data)?;
+        self.writer.write_all(&masked_crc32c(data).to_le_bytes())?;
+        Ok(())
+    }
+}
+
+fn wall_time() -> f64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap()
+        .as_secs_f64()
+}
+
+// Event: wall_time = 1 (double), step = 2 (int64)
+fn event_header(wall_time: f64, step: i64) -> Vec<u8> {
+    let mut event: Vec<u8> = vec![];
+    write_key(&mut event, 1, 1);
+    event.extend(wall_time.to_le_bytes());
+    write_key(&mut event, 2, 0);
+    write_varint(&mut event, step as u64);
+    event
+}
+
+fn write_key(buf: &mut Vec<u8>, field: u64, wire_type: u64) {
+    write_varint(buf, field << 3 | wire_type);
+}
+
+fn write_bytes_field(buf: &mut Vec<u8>, field: u64, bytes: &[u8]) {
+    write_key(buf, field, 2);
+    write_varint(buf, bytes.len() as u64);
+    buf.extend(bytes);
+}
+
+fn write_varint(buf: &mut Vec<u8>, mut v: u64) {
+    while v >= 0x80 {
+        buf.push((v as u8) | 0x80);
+        v >>= 7;
+    }
+    buf.push(v as u8);
+}
+
+fn crc32c(data: &[u8]) -> u32 {
+    const POLY: u32 = 0x82f63b78;
+    let mut crc: u32 = !0;
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ POLY
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+fn masked_crc32c(data: &[u8]) -> u32 {
+    let crc = crc32c(data);
+    ((crc >> 15) | (crc << 17)).wrapping_add(0xa282ead8)
+}
@@ -1,3 +1,9 @@
+use crate::{dqn::Observation, params::*};
+use bevy::prelude::*;
+use bevy_garage_car::sensor::CarSensors;
+use bevy_garage_track::CarTrack;
+use bevy_rapier3d::prelude::{CollidingEntities, Sensor, Velocity};
+
 // pub fn log_training(use_random: bool, action: usize, reward: f32) {
 //     let log = [
 //         "train".to_string(),
@@ -10,6 +16,11 @@
 //     .join("");
 //     println!("{log:?}");
 // }
+// any contact except sensors like the track checkpoints
+pub fn crashed(colliding: Option<&CollidingEntities>, q_sensor: &Query<(), With<Sensor>>) -> bool {
+    colliding.is_some_and(|colliding| colliding.iter().any(|e| !q_sensor.contains(e)))
+}
+
 pub fn log_action_reward(action: usize, reward: f32) {
     let log = [
         action.to_string(),
@@ -51,3 +62,72 @@ pub fn map_action_to_car(a: usize) -> (f32, f32, f32, f32) {
     };
     (gas, brake, left, right)
 }
+
+// 0 - velocity
+// 1 - angular velocity y
+// 2 - normalized distance from center line
+// 3 - cos of velocity to center line
+// 4 - cos of heading to center line
+// 5.. - sensors
+pub fn observe(
+    car_track: &CarTrack,
+    car_sensors: &CarSensors,
+    v: &Velocity,
+    tr: &Transform,
+) -> Observation {
+    let mut vel_angle = car_track.line_dir.angle_between(v.linvel);
+    if vel_angle.is_nan() {
+        vel_angle = 0.;
+    }
+    let pos_dir = tr.rotation.mul_vec3(Vec3::Z);
+    let mut pos_angle = car_track.line_dir.angle_between(pos_dir);
+    if pos_angle.is_nan() {
+        pos_angle = 0.;
+    }
+    let vel_cos = vel_angle.cos();
+    let pos_cos = pos_angle.cos();
+    let mut d_from_center = car_track.line_pos - tr.translation;
+    d_from_center.y = 0.;
+    let d = d_from_center.length();
+    let d_norm = d / 4.;
+
+    let velocity = v.linvel.length();
+    let mut obs: Observation = [0.; STATE_SIZE];
+    for i in 0..STATE_SIZE {
+        obs[i] = match i {
+            0 => velocity,
+            1 => v.angvel.y,
+            2 => d_norm,
+            3 => vel_cos,
+            4 => pos_cos,
+            STATE_SIZE_BASE..=STATE_SIZE => {
+                // the network input size is fixed, other ray counts are padded as nothing hit or truncated
+                car_sensors
+                    .sensor_inputs
+                    .get(i - STATE_SIZE_BASE)
+                    .copied()
+                    .unwrap_or(0.)
+            }
+            _ => panic!("unknown observation record"),
+        };
+    }
+    obs
+}
+
+// inverse of map_action_to_car for recorded human controls
+pub fn map_car_to_action(gas: f32, brake: f32, steering: f32) -> usize {
+    let forward = gas > 0.5 && gas >= brake;
+    let backward = brake > 0.5 && brake > gas;
+    let (left, right) = (steering < -0.3, steering > 0.3);
+    match (forward, backward, left, right) {
+        (true, _, true, _) => 4,
+        (true, _, _, true) => 5,
+        (true, _, _, _) => 0,
+        (_, true, true, _) => 6,
+        (_, true, _, true) => 7,
+        (_, true, _, _) => 1,
+        (_, _, true, _) => 2,
+        (_, _, _, true) => 3,
+        _ => 8,
+    }
+}
@@ -2,26 +2,29 @@ use bevy::{
     diagnostic::{FrameTimeDiagnosticsPlugin, LogDiagnosticsPlugin},
     prelude::*,
 };
-use bevy_egui::{EguiContexts, EguiPlugin};
+use bevy_egui::{egui, EguiContexts, EguiPlugin};
 use bevy_garage_camera::CarCameraPlugin;
-use bevy_garage_car::{CarWheels, Wheel};
+use bevy_garage_car::CarWheels;
 use bevy_garage_renet::{
-    connection_config, setup_level, ClientChannel, NetworkedEntities, PlayerCommand, PlayerInput,
-    ServerChannel, ServerMessages, PROTOCOL_ID,
+    connection_config, rapier_config_start_system, unsecure_from_env, CarChoice, ClientChannel,
+    LobbyCommand, LobbyPlayer, NetworkedEntities, PlayerInput, Predicted, PredictedCarState,
+    Prediction, PredictionPlugin, RaceClassification, RaceStanding, ServerChannel, ServerMessages,
+    SnapshotInterpolation, PROTOCOL_ID,
 };
+use bevy_garage_track::{format_lap_time, RaceLimit, RaceState, TrackPlugin};
 use bevy_renet::{
+    client_connected,
     renet::{
-        transport::{ClientAuthentication, NetcodeClientTransport, NetcodeTransportError},
+        transport::{
+            ClientAuthentication, ConnectToken, NetcodeClientTransport, NetcodeTransportError,
+        },
         RenetClient,
     },
     transport::NetcodeClientPlugin,
     RenetClientPlugin,
 };
 use renet_visualizer::{RenetClientVisualizer, RenetVisualizerStyle};
-use std::{collections::HashMap, net::UdpSocket, time::SystemTime};
-
-#[derive(Component)]
-struct ControlledPlayer;
+use std::{collections::HashMap, io::Read, net::UdpSocket, time::SystemTime};
 
 #[derive(Default, Resource)]
 struct NetworkMapping(HashMap<Entity, Entity>);
@@ -35,6 +38,16 @@ struct PlayerInfo {
 #[derive(Debug, Default, Resource)]
 struct ClientLobby {
     players: HashMap<u64, PlayerInfo>,
+    // lobby players in join order, the first one hosts
+    members: Vec<LobbyPlayer>,
+}
+
+// race state as broadcast by the server
+#[derive(Debug, Default, Resource)]
+struct ClientRace {
+    state: Option<(RaceState, RaceLimit)>,
+    lights: u32,
+    standings: Vec<RaceStanding>,
 }
 
 fn new_renet_client() -> (RenetClient, NetcodeClientTransport) {
@@ -54,12 +67,17 @@ fn new_renet_client() -> (RenetClient, NetcodeClientTransport) {
     let current_time = SystemTime::now()
         .duration_since(SystemTime::UNIX_EPOCH)
         .unwrap();
-    let client_id = current_time.as_millis() as u64;
-    let authentication = ClientAuthentication::Unsecure {
-        client_id,
-        protocol_id: PROTOCOL_ID,
-        server_addr,
-        user_data: None,
+    let authentication = match std::env::var("RENET_TOKEN_URL") {
+        Ok(url) => ClientAuthentication::Secure {
+            connect_token: fetch_connect_token(&url),
+        },
+        Err(_) if unsecure_from_env() => ClientAuthentication::Unsecure {
+            client_id: current_time.as_millis() as u64,
+            protocol_id: PROTOCOL_ID,
+            server_addr,
+            user_data: None,
+        },
+        Err(_) => panic!("RENET_TOKEN_URL is required, or RENET_UNSECURE=1 for local games"),
     };
 
     let transport = NetcodeClientTransport::new(current_time, authentication, socket).unwrap();
@@ -67,6 +85,19 @@ fn new_renet_client() -> (RenetClient, NetcodeClientTransport) {
     (client, transport)
 }
 
+// RENET_TOKEN_URL=http://127.0.0.1:3000/connect, the token carries the server address
+fn fetch_connect_token(url: &str) -> ConnectToken {
+    println!("RENET_TOKEN_URL: {}", url);
+    let mut bytes = vec![];
+    ureq::post(url)
+        .call()
+        .expect("connect token request failed")
+        .into_reader()
+        .read_to_end(&mut bytes)
+        .unwrap();
+    ConnectToken::read(&mut bytes.as_slice()).expect("invalid connect token")
+}
+
 fn main() {
     let mut app = App::new();
     app.insert_resource(bevy_garage_car::CarRes {
@@ -81,26 +112,25 @@ fn main() {
         LogDiagnosticsPlugin::default(),
         EguiPlugin,
         CarCameraPlugin,
+        TrackPlugin,
+        PredictionPlugin,
     ));
-    app.add_event::<PlayerCommand>();
     app.insert_resource(ClientLobby::default());
+    app.insert_resource(ClientRace::default());
     app.insert_resource(PlayerInput::default());
     let (client, transport) = new_renet_client();
     app.insert_resource(client);
     app.insert_resource(transport);
 
     app.insert_resource(NetworkMapping::default());
+    app.insert_resource(SnapshotInterpolation::from_env());
 
     app.add_systems(
         Update,
         (
             player_input,
-            (
-                client_send_input,
-                client_send_player_commands,
-                client_sync_players,
-            )
-                .run_if(bevy_renet::transport::client_connected()),
+            client_sync_players.run_if(client_connected),
+            interpolation_system.after(client_sync_players),
         ),
     );
 
@@ -108,15 +138,29 @@ fn main() {
         RenetVisualizerStyle::default(),
     ));
 
-    app.add_systems(Startup, (setup_level, bevy_garage_car::car_start_system));
-    app.add_systems(Update, (update_visulizer_system, panic_on_error_system));
+    app.add_systems(
+        Startup,
+        (
+            bevy_garage_car::car_start_system,
+            rapier_config_start_system,
+        ),
+    );
+    app.add_systems(
+        Update,
+        (
+            update_visulizer_system,
+            panic_on_error_system,
+            race_window_system,
+            lobby_window_system.run_if(client_connected),
+        ),
+    );
 
     app.run();
 }
 
 // If any error is found we just panic
 fn panic_on_error_system(mut renet_error: EventReader<NetcodeTransportError>) {
-    for e in renet_error.iter() {
+    if let Some(e) = renet_error.read().next() {
         panic!("{}", e);
     }
 }
@@ -126,7 +170,7 @@ fn update_visulizer_system(
     mut visualizer: ResMut<RenetClientVisualizer<200>>,
     client: Res<RenetClient>,
     mut show_visualizer: Local<bool>,
-    keyboard_input: Res<Input<KeyCode>>,
+    keyboard_input: Res<ButtonInput<KeyCode>>,
 ) {
     visualizer.add_network_info(client.network_info());
     if keyboard_input.just_pressed(KeyCode::F1) {
@@ -137,54 +181,88 @@ fn update_visulizer_system(
     }
 }
 
-fn player_input(keyboard_input: Res<Input<KeyCode>>, mut player_input: ResMut<PlayerInput>) {
-    player_input.left = keyboard_input.pressed(KeyCode::Left);
-    player_input.right = keyboard_input.pressed(KeyCode::Right);
-    player_input.up = keyboard_input.pressed(KeyCode::Up);
-    player_input.down = keyboard_input.pressed(KeyCode::Down);
-}
-
-fn client_send_input(player_input: Res<PlayerInput>, mut client: ResMut<RenetClient>) {
-    let input_message = bincode::serialize(&*player_input).unwrap();
-    client.send_message(ClientChannel::Input, input_message);
-}
-
-fn client_send_player_commands(
-    mut player_commands: EventReader<PlayerCommand>,
-    mut client: ResMut<RenetClient>,
+// arrows or gamepad left stick steering, right stick and north/south buttons for pedals
+fn player_input(
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    gamepads: Res<Gamepads>,
+    axes: Res<Axis<GamepadAxis>>,
+    buttons: Res<ButtonInput<GamepadButton>>,
+    mut player_input: ResMut<PlayerInput>,
 ) {
-    for command in player_commands.iter() {
-        let command_message = bincode::serialize(command).unwrap();
-        client.send_message(ClientChannel::Command, command_message);
+    let key = |code: KeyCode| match keyboard_input.pressed(code) {
+        true => 1.,
+        false => 0.,
+    };
+    player_input.gas = key(KeyCode::ArrowUp);
+    player_input.brake = key(KeyCode::ArrowDown);
+    player_input.steering = key(KeyCode::ArrowRight) - key(KeyCode::ArrowLeft);
+    for gamepad in gamepads.iter() {
+        let axis = |axis_type| axes.get(GamepadAxis::new(gamepad, axis_type)).unwrap_or(0.);
+        let button = |button_type| buttons.pressed(GamepadButton::new(gamepad, button_type));
+        let stick_x = axis(GamepadAxisType::LeftStickX);
+        if stick_x != 0. {
+            player_input.steering = stick_x;
+        }
+        let stick_y = axis(GamepadAxisType::RightStickY);
+        if stick_y > 0. {
+            player_input.gas = (stick_y / 0.75).min(1.);
+        } else if stick_y < 0. {
+            player_input.brake = (-stick_y / 0.75).min(1.);
+        }
+        if button(GamepadButtonType::North) {
+            player_input.gas = 1.;
+        }
+        if button(GamepadButtonType::South) {
+            player_input.brake = 1.;
+        }
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 fn client_sync_players(
     mut cmd: Commands,
     mut client: ResMut<RenetClient>,
     transport: Res<NetcodeClientTransport>,
     mut lobby: ResMut<ClientLobby>,
+    mut race: ResMut<ClientRace>,
+    mut prediction: ResMut<Prediction>,
+    mut interpolation: ResMut<SnapshotInterpolation>,
     mut network_mapping: ResMut<NetworkMapping>,
+    time: Res<Time>,
     car_res: Res<bevy_garage_car::CarRes>,
-    car_wheels: Query<&CarWheels>,
-    mut wheel_query: Query<&mut Transform, With<Wheel>>,
+    mut car_wheels: Query<&mut CarWheels>,
+    q_predicted: Query<(), With<Predicted>>,
 ) {
-    let client_id = transport.client_id();
+    let client_id = transport.client_id().raw();
     while let Some(message) = client.receive_message(ServerChannel::ServerMessages) {
         let server_message = bincode::deserialize(&message).unwrap();
         match server_message {
             ServerMessages::PlayerCreate {
                 id,
                 translation,
+                rotation,
                 entity,
+                car,
             } => {
                 println!("Player {} connected.", id);
 
                 let is_player = client_id == id;
 
-                // let transform: Transform = Transform::from_translation(translation);
-                let transform: Transform =
-                    Transform::from_xyz(translation[0], translation[1], translation[2]);
+                // the server respawns cars when the grid forms
+                if let Some(PlayerInfo {
+                    server_entity,
+                    client_entity,
+                }) = lobby.players.remove(&id)
+                {
+                    if let Ok(mut wheels) = car_wheels.get_mut(client_entity) {
+                        wheels.despawn(&mut cmd);
+                    }
+                    cmd.entity(client_entity).despawn_recursive();
+                    network_mapping.0.remove(&server_entity);
+                }
+
+                let transform = Transform::from_translation(translation.into())
+                    .with_rotation(Quat::from_array(rotation));
                 let client_entity = bevy_garage_car::spawn_car(
                     &mut cmd,
                     &car_res.car_scene.as_ref().unwrap(),
@@ -193,8 +271,10 @@ fn client_sync_players(
                     transform,
                 );
 
+                // prediction steps with the spec the server simulates
+                cmd.entity(client_entity).insert(car.spec()).insert(car);
                 if is_player {
-                    cmd.entity(client_entity).insert(ControlledPlayer);
+                    cmd.entity(client_entity).insert(Predicted);
                 }
 
                 let player_info = PlayerInfo {
@@ -205,45 +285,226 @@ fn client_sync_players(
                 network_mapping.0.insert(entity, client_entity);
             }
             ServerMessages::PlayerRemove { id } => {
-                println!("Player {} disconnected.", id);
+                println!("Player {} car removed.", id);
                 if let Some(PlayerInfo {
                     server_entity,
                     client_entity,
                 }) = lobby.players.remove(&id)
                 {
-                    cmd.entity(client_entity).despawn();
+                    if let Ok(mut wheels) = car_wheels.get_mut(client_entity) {
+                        wheels.despawn(&mut cmd);
+                    }
+                    cmd.entity(client_entity).despawn_recursive();
                     network_mapping.0.remove(&server_entity);
                 }
             }
+            ServerMessages::Lobby { players } => {
+                lobby.members = players;
+            }
+            ServerMessages::RaceState {
+                state,
+                limit,
+                lights,
+                race_seconds,
+                frozen,
+            } => {
+                if race.state.map(|(state, _)| state) != Some(state) {
+                    println!("race {state:?} {limit:?} at {race_seconds:.1}s");
+                }
+                race.state = Some((state, limit));
+                race.lights = lights;
+                prediction.frozen = frozen;
+            }
+            ServerMessages::RaceStandings { standings } => {
+                race.standings = standings;
+            }
+            ServerMessages::RaceResult {
+                classification,
+                fastest_lap,
+            } => {
+                print_race_result(&classification, fastest_lap);
+            }
         }
     }
 
     while let Some(message) = client.receive_message(ServerChannel::NetworkedEntities) {
-        let networked_entities: NetworkedEntities = bincode::deserialize(&message).unwrap();
-
-        for i in 0..networked_entities.entities.len() {
-            if let Some(entity) = network_mapping.0.get(&networked_entities.entities[i]) {
-                let translation = networked_entities.translations[i].into();
-                let rotation: Quat = Quat::from_array(networked_entities.rotations[i]);
-                let transform = Transform {
-                    translation,
-                    rotation,
-                    ..Default::default()
-                };
-                cmd.entity(*entity).insert(transform);
+        let Some(networked_entities) = NetworkedEntities::decode(&message) else {
+            continue;
+        };
+        let tick = networked_entities.tick;
+        if interpolation
+            .snapshots
+            .latest()
+            .is_some_and(|latest| tick <= latest)
+        {
+            continue;
+        }
+        // the baseline fell out of the buffer, wait for a newer ack to reach the server
+        let baseline = match networked_entities.baseline {
+            Some(baseline) => match interpolation.snapshots.get(baseline) {
+                Some(cars) => Some(cars),
+                None => continue,
+            },
+            None => None,
+        };
+        let cars = networked_entities.resolve(baseline);
 
-                let translations = networked_entities.wheels_translations[i];
-                let rotations = networked_entities.wheels_rotations[i];
+        for car in cars.iter() {
+            let Some(entity) = network_mapping.0.get(&car.entity) else {
+                continue;
+            };
+            if q_predicted.contains(*entity) {
+                prediction.correct(PredictedCarState {
+                    input_tick: car.input_tick,
+                    body: car.body.state(),
+                    wheels: car.wheels.map(|w| w.state()),
+                    esp: car.esp,
+                });
+            }
+        }
 
-                let car_wheels = car_wheels.get(*entity);
-                if let Ok(car_wheels) = car_wheels {
-                    for (i, e) in car_wheels.entities.iter().enumerate() {
-                        let mut wheel_transform = wheel_query.get_mut(*e).unwrap();
-                        wheel_transform.translation = translations[i].into();
-                        wheel_transform.rotation = Quat::from_array(rotations[i]);
-                    }
+        interpolation.snapshots.push(tick, cars);
+        interpolation.latest_at = time.elapsed_seconds_f64();
+        let message = bincode::serialize(&tick).unwrap();
+        client.send_message(ClientChannel::SnapshotAck, message);
+    }
+}
+
+// remote cars are shown between the snapshots around the delayed render tick
+fn interpolation_system(
+    time: Res<Time>,
+    interpolation: Res<SnapshotInterpolation>,
+    network_mapping: Res<NetworkMapping>,
+    q_car: Query<&CarWheels, Without<Predicted>>,
+    mut q_transform: Query<&mut Transform, Without<Predicted>>,
+) {
+    let Some(tick) = interpolation.render_tick(time.elapsed_seconds_f64()) else {
+        return;
+    };
+    for (server_entity, entity) in network_mapping.0.iter() {
+        let Ok(wheels) = q_car.get(*entity) else {
+            continue;
+        };
+        let Some((body, wheel_transforms)) = interpolation.snapshots.sample(*server_entity, tick)
+        else {
+            continue;
+        };
+        if let Ok(mut transform) = q_transform.get_mut(*entity) {
+            *transform = body;
+        }
+        for (e, wheel) in wheels.entities.iter().zip(wheel_transforms) {
+            if let Ok(mut transform) = q_transform.get_mut(*e) {
+                *transform = wheel;
+            }
+        }
+    }
+}
+
+fn print_race_result(classification: &[RaceClassification], fastest_lap: Option<(u64, f32)>) {
+    println!("race result");
+    for c in classification.iter() {
+        let time = match (c.total_time, c.gap, c.laps_down) {
+            (_, _, _) if !c.finished => "DNF".to_string(),
+            (Some(time), _, _) if c.position == 1 => format!("{time:.3}s"),
+            (_, Some(gap), _) => format!("+{gap:.3}s"),
+            (_, _, laps_down) => format!("+{laps_down} laps"),
+        };
+        println!("{:>2}. player {} laps {} {time}", c.position, c.id, c.laps);
+    }
+    if let Some((id, lap)) = fastest_lap {
+        println!("fastest lap player {id} {}", format_lap_time(lap));
+    }
+}
+
+fn race_window_system(
+    mut egui_contexts: EguiContexts,
+    race: Res<ClientRace>,
+    transport: Res<NetcodeClientTransport>,
+) {
+    let Some((state, limit)) = race.state else {
+        return;
+    };
+    let client_id = transport.client_id().raw();
+    egui::Window::new("Race").show(egui_contexts.ctx_mut(), |ui| {
+        match (state, limit) {
+            (RaceState::Countdown, _) => ui.label(format!("lights {}", race.lights)),
+            (_, RaceLimit::Laps(laps)) => ui.label(format!("{state:?}, {laps} laps")),
+            (_, RaceLimit::Time(seconds)) => ui.label(format!("{state:?}, {seconds:.0}s")),
+        };
+        let lap_time = |lap: Option<f32>| lap.map_or("-".to_string(), format_lap_time);
+        for standing in race.standings.iter() {
+            let you = if standing.id == client_id {
+                " (you)"
+            } else {
+                ""
+            };
+            ui.label(format!(
+                "{}. player {}{you} lap {} last {} best {}",
+                standing.place,
+                standing.id,
+                standing.laps.max(0),
+                lap_time(standing.last_lap),
+                lap_time(standing.best_lap),
+            ));
+        }
+    });
+}
+
+// car pick and ready while the server waits in warmup, the host starts the race
+fn lobby_window_system(
+    mut egui_contexts: EguiContexts,
+    mut client: ResMut<RenetClient>,
+    race: Res<ClientRace>,
+    lobby: Res<ClientLobby>,
+    transport: Res<NetcodeClientTransport>,
+) {
+    if !matches!(race.state, Some((RaceState::Warmup, _))) {
+        return;
+    }
+    let client_id = transport.client_id().raw();
+    let Some(me) = lobby.members.iter().find(|m| m.id == client_id) else {
+        return;
+    };
+    let host = lobby.members.first().is_some_and(|m| m.id == client_id);
+    let mut command = None;
+    egui::Window::new("Lobby").show(egui_contexts.ctx_mut(), |ui| {
+        for (i, member) in lobby.members.iter().enumerate() {
+            ui.label(format!(
+                "player {}{}{} {:?}{}",
+                member.id,
+                if member.id == client_id { " (you)" } else { "" },
+                if i == 0 { " host" } else { "" },
+                member.car,
+                if member.ready { " ready" } else { "" },
+            ));
+        }
+        ui.separator();
+        ui.horizontal(|ui| {
+            for car in CarChoice::ALL {
+                if ui
+                    .selectable_label(me.car == car, format!("{car:?}"))
+                    .clicked()
+                {
+                    command = Some(LobbyCommand::PickCar(car));
                 }
             }
+        });
+        let mut ready = me.ready;
+        if ui.checkbox(&mut ready, "ready").changed() {
+            command = Some(LobbyCommand::Ready(ready));
         }
+        if host {
+            let all_ready = lobby.members.iter().all(|m| m.ready);
+            if ui
+                .add_enabled(all_ready, egui::Button::new("start race"))
+                .clicked()
+            {
+                command = Some(LobbyCommand::Start);
+            }
+        }
+    });
+    if let Some(command) = command {
+        let message = bincode::serialize(&command).unwrap();
+        client.send_message(ClientChannel::Lobby, message);
     }
 }
@@ -2,10 +2,19 @@ use bevy::{
     diagnostic::{FrameTimeDiagnosticsPlugin, LogDiagnosticsPlugin},
     prelude::*,
 };
-use bevy_garage_car::{esp_system, spawn_car, Car, CarWheels, Wheel};
+use bevy_garage_car::{car_start_system, Car, CarRes, CarWheels, Wheel};
 use bevy_garage_renet::{
-    connection_config, rapier_config_start_system, setup_level, ClientChannel, NetworkedEntities,
-    Player, PlayerCommand, PlayerInput, ServerChannel, ServerMessages, PROTOCOL_ID,
+    car_step_systems, connection_config, private_key_from_env, rapier_config_start_system,
+    unsecure_from_env, CarChoice, ClientChannel, LobbyCommand, LobbyPlayer, NetBody, NetCar,
+    NetworkedEntities, Player, PlayerInput, RaceClassification, RaceStanding, ServerChannel,
+    ServerMessages, SnapshotBuffer, NET_DT, NET_SUBSTEPS, PROTOCOL_ID,
+};
+use bevy_garage_track::{
+    grid_form_system, initial_velocity_system, pit_enabled, pit_limiter_system, race_freeze_system,
+    race_order, race_progress_system, race_state_system, sim_tick_system, spawn_car_on_track,
+    track_polyline_start_system, CarTrack, InitialVelocity, LapTiming, RaceCar, RaceLimit,
+    RaceResult, RaceSession, RaceState, SimTick, SpawnCarOnTrackEvent, StartingGrid, TrackConfig,
+    TrackPlugin,
 };
 use bevy_rapier3d::prelude::*;
 use bevy_renet::{
@@ -16,11 +25,74 @@ use bevy_renet::{
     transport::NetcodeServerPlugin,
     RenetServerPlugin,
 };
-use std::{collections::HashMap, net::UdpSocket, time::SystemTime};
+use std::{
+    collections::{HashMap, VecDeque},
+    net::UdpSocket,
+    time::SystemTime,
+};
+
+// inputs received ahead of the tick they are applied in
+#[derive(Debug, Default, Component)]
+pub struct InputQueue(pub VecDeque<PlayerInput>);
+
+// queued inputs over this are dropped to catch up with the client
+const INPUT_QUEUE_MAX: usize = 6;
 
 #[derive(Debug, Default, Resource)]
 pub struct ServerLobby {
+    // connected clients in join order, the first one hosts
+    pub members: Vec<LobbyPlayer>,
+    // cars of the running race
     pub players: HashMap<u64, Entity>,
+    // client id by CarTrack index
+    pub indices: HashMap<usize, u64>,
+    // latest snapshot tick decoded by each client
+    pub acks: HashMap<u64, u32>,
+}
+
+// sent snapshots, delta baselines for the acknowledged ticks
+#[derive(Debug, Default, Resource)]
+pub struct SnapshotHistory(pub SnapshotBuffer);
+
+impl ServerLobby {
+    fn id_of(&self, entity: Entity) -> Option<u64> {
+        self.players
+            .iter()
+            .find_map(|(id, e)| (*e == entity).then_some(*id))
+    }
+    fn car_of(&self, id: u64) -> CarChoice {
+        self.members
+            .iter()
+            .find(|m| m.id == id)
+            .map_or_else(CarChoice::default, |m| m.car)
+    }
+    fn can_start(&self, min_players: usize) -> bool {
+        self.members.len() >= min_players.max(1) && self.members.iter().all(|m| m.ready)
+    }
+    fn message(&self) -> Vec<u8> {
+        bincode::serialize(&ServerMessages::Lobby {
+            players: self.members.clone(),
+        })
+        .unwrap()
+    }
+}
+
+// RENET_MIN_PLAYERS=n ready players for the host to start, RENET_RESTART=seconds after the
+// result before the lobby opens again
+#[derive(Debug, Resource)]
+pub struct ServerRace {
+    pub min_players: usize,
+    pub restart: f32,
+}
+
+impl ServerRace {
+    pub fn from_env() -> Self {
+        let env = |name: &str| std::env::var(name).ok()?.parse::<f32>().ok();
+        Self {
+            min_players: env("RENET_MIN_PLAYERS").map_or(1, |n| n as usize),
+            restart: env("RENET_RESTART").unwrap_or(15.),
+        }
+    }
 }
 
 fn new_renet_server() -> (RenetServer, NetcodeServerTransport) {
@@ -44,7 +116,14 @@ fn new_renet_server() -> (RenetServer, NetcodeServerTransport) {
         max_clients: 64,
         protocol_id: PROTOCOL_ID,
         public_addresses: vec![public_addr],
-        authentication: ServerAuthentication::Unsecure,
+        authentication: match private_key_from_env() {
+            Some(private_key) => ServerAuthentication::Secure { private_key },
+            None if unsecure_from_env() => {
+                println!("RENET_UNSECURE=1, unsecure authentication");
+                ServerAuthentication::Unsecure
+            }
+            None => panic!("NETCODE_PRIVATE_KEY is required, or RENET_UNSECURE=1 for local games"),
+        },
     };
 
     let transport = NetcodeServerTransport::new(server_config, socket).unwrap();
@@ -54,9 +133,8 @@ fn new_renet_server() -> (RenetServer, NetcodeServerTransport) {
 
 fn main() {
     let mut app = App::new();
-    #[cfg(feature = "graphics")]
-    app.insert_resource(bevy_garage_car::CarRes {
-        show_rays: true,
+    app.insert_resource(CarRes {
+        show_rays: cfg!(feature = "graphics"),
         ..default()
     });
     #[cfg(feature = "graphics")]
@@ -73,26 +151,67 @@ fn main() {
         RapierDebugRenderPlugin::default(),
         bevy_egui::EguiPlugin,
     ));
+    // the track still loads its meshes and materials, without a window and a gpu
     #[cfg(not(feature = "graphics"))]
-    app.add_plugins(MinimalPlugins.set(bevy::app::ScheduleRunnerPlugin::default()));
+    app.add_plugins((
+        DefaultPlugins
+            .set(WindowPlugin {
+                primary_window: None,
+                exit_condition: bevy::window::ExitCondition::DontExit,
+                ..default()
+            })
+            .set(bevy::render::RenderPlugin {
+                render_creation: bevy::render::settings::WgpuSettings {
+                    backends: None,
+                    ..default()
+                }
+                .into(),
+                ..default()
+            }),
+        bevy::app::ScheduleRunnerPlugin::run_loop(std::time::Duration::from_secs_f32(NET_DT)),
+    ));
+
+    // one input tick per fixed step in real time, also when a window renders faster
+    let mut rapier_config = RapierConfiguration::new(1.);
+    rapier_config.timestep_mode = TimestepMode::Fixed {
+        dt: NET_DT,
+        substeps: NET_SUBSTEPS,
+    };
+    app.insert_resource(rapier_config)
+        .insert_resource(Time::<Fixed>::from_seconds(NET_DT as f64))
+        .init_resource::<SimTick>();
 
     app.add_plugins((
         RenetServerPlugin,
         NetcodeServerPlugin,
-        RapierPhysicsPlugin::<NoUserData>::default(),
+        RapierPhysicsPlugin::<NoUserData>::default().in_fixed_schedule(),
+        TrackPlugin,
         FrameTimeDiagnosticsPlugin,
         LogDiagnosticsPlugin::default(),
     ));
 
-    app.insert_resource(RapierConfiguration {
-        timestep_mode: TimestepMode::Variable {
-            max_dt: 1. / 60.,
-            time_scale: 1.,
-            substeps: 10,
-        },
-        ..default()
-    });
+    // TrackPlugin forms the grid with RACE_LAPS or RACE_TIME, otherwise the server does
+    if !app.world().contains_resource::<RaceSession>() {
+        app.insert_resource(RaceSession::new(RaceLimit::Laps(3)))
+            .add_systems(
+                Update,
+                (
+                    grid_form_system.after(race_state_system),
+                    initial_velocity_system,
+                ),
+            );
+    }
+    // warmup is the lobby, it is left by the host start only
+    app.world_mut().resource_mut::<RaceSession>().warmup = f32::INFINITY;
+    // the lobby sets the grid order from the players in join order
+    let mut grid = StartingGrid::from_env(0);
+    grid.order.clear();
+    app.insert_resource(grid);
+
     app.insert_resource(ServerLobby::default());
+    app.init_resource::<SnapshotHistory>();
+    app.insert_resource(ServerRace::from_env());
+    app.add_event::<SpawnCarOnTrackEvent>();
 
     let (server, transport) = new_renet_server();
     app.insert_resource(server).insert_resource(transport);
@@ -101,118 +220,396 @@ fn main() {
         Update,
         (
             server_update_system,
-            server_network_sync,
-            move_players_system,
-            esp_system.after(move_players_system),
+            server_lobby_system
+                .after(server_update_system)
+                .before(race_state_system),
+            server_spawn_car_system.after(server_update_system),
+            server_race_system
+                .after(server_lobby_system)
+                .before(race_state_system),
+            server_race_sync.after(race_progress_system),
         ),
     );
+    // inputs, race and pit holds and the car step of one tick, before its physics step
+    app.add_systems(
+        FixedUpdate,
+        (
+            sim_tick_system,
+            move_players_system,
+            race_freeze_system,
+            pit_limiter_system.run_if(pit_enabled),
+            car_step_systems(),
+        )
+            .chain()
+            .before(PhysicsSet::SyncBackend),
+    );
 
     #[cfg(feature = "graphics")]
     {
-        app.add_systems(
-            Startup,
-            (bevy_garage_car::car_start_system, setup_simple_camera),
-        );
+        app.add_systems(Startup, setup_simple_camera);
         app.add_systems(Update, (update_visulizer_system,));
         app.insert_resource(renet_visualizer::RenetServerVisualizer::<200>::default());
     }
 
-    app.add_systems(Startup, (rapier_config_start_system, setup_level));
+    // snapshots carry the state after the step of the acknowledged input
+    app.add_systems(
+        FixedUpdate,
+        server_network_sync.after(PhysicsSet::Writeback),
+    );
+
+    app.add_systems(
+        Startup,
+        (
+            rapier_config_start_system,
+            car_start_system.after(track_polyline_start_system),
+        ),
+    );
 
     app.run();
 }
 
 #[allow(clippy::too_many_arguments)]
 fn server_update_system(
+    time: Res<Time>,
     mut server_events: EventReader<ServerEvent>,
     mut cmd: Commands,
     mut lobby: ResMut<ServerLobby>,
     mut server: ResMut<RenetServer>,
-    players: Query<(Entity, &Player, &Transform)>,
-    #[cfg(feature = "graphics")] car_res: Res<bevy_garage_car::CarRes>,
+    mut grid: ResMut<StartingGrid>,
+    session: Res<RaceSession>,
+    players: Query<(Entity, &Player, &Transform, &CarChoice)>,
+    mut q_wheels: Query<&mut CarWheels>,
+    mut q_queue: Query<&mut InputQueue>,
     #[cfg(feature = "graphics")] mut visualizer: ResMut<
         renet_visualizer::RenetServerVisualizer<200>,
     >,
 ) {
-    for event in server_events.iter() {
+    for event in server_events.read() {
         match event {
             ServerEvent::ClientConnected { client_id } => {
-                println!("Player {} connected.", client_id);
+                let id = client_id.raw();
+                println!("Player {} connected.", id);
                 #[cfg(feature = "graphics")]
                 visualizer.add_client(*client_id);
 
-                for (entity, player, transform) in players.iter() {
-                    let translation: [f32; 3] = transform.translation.into();
+                for (entity, player, transform, car) in players.iter() {
                     let message = bincode::serialize(&ServerMessages::PlayerCreate {
                         id: player.id,
                         entity,
-                        translation,
+                        translation: transform.translation.into(),
+                        rotation: transform.rotation.into(),
+                        car: *car,
                     })
                     .unwrap();
                     server.send_message(*client_id, ServerChannel::ServerMessages, message);
                 }
-                let transform = Transform::from_xyz(
-                    (fastrand::f32() - 0.5) * 40.,
-                    0.51,
-                    (fastrand::f32() - 0.5) * 40.,
-                );
-                let player_entity = spawn_car(
-                    &mut cmd,
-                    #[cfg(feature = "graphics")]
-                    &car_res.car_scene.as_ref().unwrap(),
-                    #[cfg(feature = "graphics")]
-                    &car_res.wheel_scene.as_ref().unwrap(),
-                    false,
-                    transform,
-                );
-                cmd.entity(player_entity)
-                    .insert(Player { id: *client_id })
-                    .insert(PlayerInput::default());
-
-                lobby.players.insert(*client_id, player_entity);
-
-                let translation: [f32; 3] = transform.translation.into();
-                let message = bincode::serialize(&ServerMessages::PlayerCreate {
-                    id: *client_id,
-                    entity: player_entity,
-                    translation,
-                })
-                .unwrap();
-                server.broadcast_message(ServerChannel::ServerMessages, message);
+                let message = race_state_message(&session, time.elapsed_seconds_f64());
+                server.send_message(*client_id, ServerChannel::ServerMessages, message);
+
+                // the car is spawned when the host starts the next race
+                lobby.members.push(LobbyPlayer {
+                    id,
+                    car: CarChoice::default(),
+                    ready: false,
+                });
+                server.broadcast_message(ServerChannel::ServerMessages, lobby.message());
             }
             ServerEvent::ClientDisconnected { client_id, reason } => {
-                println!("Player {} disconnected: {}", client_id, reason);
+                let id = client_id.raw();
+                println!("Player {} disconnected: {}", id, reason);
                 #[cfg(feature = "graphics")]
                 visualizer.remove_client(*client_id);
-                if let Some(player_entity) = lobby.players.remove(client_id) {
-                    cmd.entity(player_entity).despawn();
+                if let Some(player_entity) = lobby.players.remove(&id) {
+                    despawn_car(&mut cmd, &mut q_wheels, player_entity);
                 }
+                lobby.members.retain(|m| m.id != id);
+                lobby.indices.retain(|_, client| *client != id);
+                lobby.acks.remove(&id);
+                let indices = &lobby.indices;
+                grid.order.retain(|index| indices.contains_key(index));
 
-                let message =
-                    bincode::serialize(&ServerMessages::PlayerRemove { id: *client_id }).unwrap();
+                let message = bincode::serialize(&ServerMessages::PlayerRemove { id }).unwrap();
                 server.broadcast_message(ServerChannel::ServerMessages, message);
+                server.broadcast_message(ServerChannel::ServerMessages, lobby.message());
+            }
+        }
+    }
+
+    for client_id in server.clients_id() {
+        while let Some(message) = server.receive_message(client_id, ClientChannel::SnapshotAck) {
+            let Ok(tick) = bincode::deserialize::<u32>(&message) else {
+                continue;
+            };
+            let ack = lobby.acks.entry(client_id.raw()).or_default();
+            *ack = tick.max(*ack);
+        }
+        while let Some(message) = server.receive_message(client_id, ClientChannel::Input) {
+            let Ok(input) = bincode::deserialize::<PlayerInput>(&message) else {
+                continue;
+            };
+            if !input.is_finite() {
+                continue;
+            }
+            let Some(player_entity) = lobby.players.get(&client_id.raw()) else {
+                continue;
+            };
+            if let Ok(mut queue) = q_queue.get_mut(*player_entity) {
+                queue.0.push_back(input);
             }
         }
     }
+}
+
+fn despawn_car(cmd: &mut Commands, q_wheels: &mut Query<&mut CarWheels>, entity: Entity) {
+    if let Ok(mut wheels) = q_wheels.get_mut(entity) {
+        wheels.despawn(cmd);
+    }
+    cmd.entity(entity).despawn_recursive();
+}
 
+// car pick and ready in warmup, the host start puts the players on the grid in join order
+fn server_lobby_system(
+    time: Res<Time>,
+    server_race: Res<ServerRace>,
+    mut lobby: ResMut<ServerLobby>,
+    mut server: ResMut<RenetServer>,
+    mut session: ResMut<RaceSession>,
+    mut grid: ResMut<StartingGrid>,
+) {
+    let mut changed = false;
     for client_id in server.clients_id() {
-        while let Some(message) = server.receive_message(client_id, ClientChannel::Command) {
-            let command: PlayerCommand = bincode::deserialize(&message).unwrap();
+        let id = client_id.raw();
+        while let Some(message) = server.receive_message(client_id, ClientChannel::Lobby) {
+            let Ok(command) = bincode::deserialize::<LobbyCommand>(&message) else {
+                continue;
+            };
+            if session.state != RaceState::Warmup {
+                continue;
+            }
             match command {
-                PlayerCommand::BasicAttack { cast_at } => {
-                    println!(
-                        "Received basic attack from client {}: {:?}",
-                        client_id, cast_at
-                    );
+                LobbyCommand::Start => {
+                    let host = lobby.members.first().map(|m| m.id);
+                    if host != Some(id) || !lobby.can_start(server_race.min_players) {
+                        println!("player {id} can't start the race");
+                        continue;
+                    }
+                    lobby.indices = lobby
+                        .members
+                        .iter()
+                        .enumerate()
+                        .map(|(index, m)| (index, m.id))
+                        .collect();
+                    grid.order = (0..lobby.members.len()).collect();
+                    session.set_state(RaceState::Grid, time.elapsed_seconds_f64());
+                }
+                LobbyCommand::PickCar(car) => {
+                    if let Some(member) = lobby.members.iter_mut().find(|m| m.id == id) {
+                        member.car = car;
+                    }
+                }
+                LobbyCommand::Ready(ready) => {
+                    if let Some(member) = lobby.members.iter_mut().find(|m| m.id == id) {
+                        member.ready = ready;
+                    }
                 }
             }
+            changed = true;
         }
-        while let Some(message) = server.receive_message(client_id, ClientChannel::Input) {
-            let input: PlayerInput = bincode::deserialize(&message).unwrap();
-            if let Some(player_entity) = lobby.players.get(&client_id) {
-                cmd.entity(*player_entity).insert(input);
+    }
+    if changed {
+        server.broadcast_message(ServerChannel::ServerMessages, lobby.message());
+    }
+}
+
+// lobby players on every grid formation, autopilot opponents without a client
+fn server_spawn_car_system(
+    mut events: EventReader<SpawnCarOnTrackEvent>,
+    mut cmd: Commands,
+    mut lobby: ResMut<ServerLobby>,
+    mut server: ResMut<RenetServer>,
+    track_config: Res<TrackConfig>,
+    car_res: Res<CarRes>,
+) {
+    for spawn_event in events.read() {
+        let (transform, init_meters) = match (&spawn_event.slot, spawn_event.position) {
+            (Some(slot), _) => (slot.transform, slot.meters),
+            (None, Some(init_meters)) => {
+                let (translate, quat) = track_config.get_transform_by_meter(init_meters);
+                let transform = Transform::from_translation(translate).with_rotation(quat);
+                (transform, init_meters)
+            }
+            (None, None) => track_config.get_transform_random(),
+        };
+        let car_id = spawn_car_on_track(
+            &mut cmd,
+            car_res.car_scene.as_ref().unwrap(),
+            car_res.wheel_scene.as_ref().unwrap(),
+            false,
+            transform,
+            spawn_event.index,
+            init_meters,
+        );
+        if let Some(slot) = spawn_event.slot.as_ref() {
+            if slot.velocity != Vec3::ZERO {
+                cmd.entity(car_id).insert(InitialVelocity(slot.velocity));
             }
         }
+        let Some(id) = lobby.indices.get(&spawn_event.index).copied() else {
+            continue;
+        };
+        // replaces the default spec inserted by the spawn
+        let car = lobby.car_of(id);
+        cmd.entity(car_id)
+            .insert(car.spec())
+            .insert(car)
+            .insert(Player { id })
+            .insert(PlayerInput::default())
+            .insert(InputQueue::default());
+        lobby.players.insert(id, car_id);
+
+        let message = bincode::serialize(&ServerMessages::PlayerCreate {
+            id,
+            entity: car_id,
+            translation: transform.translation.into(),
+            rotation: transform.rotation.into(),
+            car,
+        })
+        .unwrap();
+        server.broadcast_message(ServerChannel::ServerMessages, message);
+    }
+}
+
+// back to the lobby after the result or when everyone left, cars are removed until the next start
+#[allow(clippy::too_many_arguments)]
+fn server_race_system(
+    time: Res<Time>,
+    server_race: Res<ServerRace>,
+    mut cmd: Commands,
+    mut lobby: ResMut<ServerLobby>,
+    mut server: ResMut<RenetServer>,
+    mut session: ResMut<RaceSession>,
+    mut grid: ResMut<StartingGrid>,
+    mut q_wheels: Query<&mut CarWheels>,
+) {
+    let seconds = time.elapsed_seconds_f64();
+    let in_state = (seconds - session.state_at) as f32;
+    let to_lobby = match session.state {
+        RaceState::Warmup => false,
+        RaceState::Finished => in_state >= server_race.restart || lobby.members.is_empty(),
+        _ => lobby.members.is_empty(),
+    };
+    if !to_lobby {
+        return;
+    }
+    for (id, entity) in lobby.players.drain() {
+        despawn_car(&mut cmd, &mut q_wheels, entity);
+        let message = bincode::serialize(&ServerMessages::PlayerRemove { id }).unwrap();
+        server.broadcast_message(ServerChannel::ServerMessages, message);
+    }
+    lobby.indices.clear();
+    grid.order.clear();
+    for member in lobby.members.iter_mut() {
+        member.ready = false;
+    }
+    session.chequered = false;
+    session.set_state(RaceState::Warmup, seconds);
+    server.broadcast_message(ServerChannel::ServerMessages, lobby.message());
+}
+
+fn race_state_message(session: &RaceSession, seconds: f64) -> Vec<u8> {
+    bincode::serialize(&ServerMessages::RaceState {
+        state: session.state,
+        limit: session.limit,
+        lights: session.lights(seconds),
+        race_seconds: session.race_seconds(seconds),
+        frozen: session.frozen(),
+    })
+    .unwrap()
+}
+
+#[allow(clippy::too_many_arguments)]
+fn server_race_sync(
+    time: Res<Time>,
+    session: Res<RaceSession>,
+    lobby: Res<ServerLobby>,
+    mut server: ResMut<RenetServer>,
+    q_car: Query<(&CarTrack, &RaceCar, Option<&LapTiming>)>,
+    mut result_events: EventReader<RaceResult>,
+    mut sent_state: Local<Option<(RaceState, u32)>>,
+    mut sent_standings: Local<Vec<RaceStanding>>,
+) {
+    let seconds = time.elapsed_seconds_f64();
+    let state = (session.state, session.lights(seconds));
+    if *sent_state != Some(state) {
+        *sent_state = Some(state);
+        let message = race_state_message(&session, seconds);
+        server.broadcast_message(ServerChannel::ServerMessages, message);
+    }
+
+    // race order while racing, practice times before
+    let racing = matches!(session.state, RaceState::Green | RaceState::Finished);
+    let mut cars: Vec<(u64, &RaceCar, Option<&LapTiming>)> = q_car
+        .iter()
+        .filter_map(|(car_track, race_car, timing)| {
+            Some((*lobby.indices.get(&car_track.index)?, race_car, timing))
+        })
+        .collect();
+    cars.sort_by(|a, b| race_order(a.1, b.1));
+    let standings: Vec<RaceStanding> = cars
+        .iter()
+        .enumerate()
+        .map(|(i, (id, race_car, timing))| {
+            let (last_lap, best_lap) = match (racing, timing) {
+                (true, _) => (race_car.last_lap, race_car.best_lap),
+                (false, Some(timing)) => (timing.last_lap, timing.best_lap),
+                (false, None) => (None, None),
+            };
+            RaceStanding {
+                id: *id,
+                place: i + 1,
+                laps: race_car.laps,
+                last_lap,
+                best_lap,
+                finished: race_car.total_time(),
+            }
+        })
+        .collect();
+    if *sent_standings != standings {
+        let message = bincode::serialize(&ServerMessages::RaceStandings {
+            standings: standings.clone(),
+        })
+        .unwrap();
+        server.broadcast_message(ServerChannel::ServerMessages, message);
+        *sent_standings = standings;
+    }
+
+    for result in result_events.read() {
+        let classification = result
+            .classification
+            .iter()
+            .filter_map(|c| {
+                Some(RaceClassification {
+                    id: *lobby.indices.get(&c.index)?,
+                    position: c.position,
+                    laps: c.laps,
+                    finished: c.finished,
+                    total_time: c.total_time,
+                    penalty: c.penalty,
+                    gap: c.gap,
+                    laps_down: c.laps_down,
+                    best_lap: c.best_lap,
+                })
+            })
+            .collect();
+        let fastest_lap = result
+            .fastest_lap
+            .and_then(|(entity, lap)| Some((lobby.id_of(entity)?, lap)));
+        let message = bincode::serialize(&ServerMessages::RaceResult {
+            classification,
+            fastest_lap,
+        })
+        .unwrap();
+        server.broadcast_message(ServerChannel::ServerMessages, message);
     }
 }
 
@@ -226,66 +623,62 @@ fn update_visulizer_system(
     visualizer.show_window(egui_contexts.ctx_mut());
 }
 
+// per client delta against its last acknowledged snapshot, absolute until the first ack
 #[allow(clippy::type_complexity)]
 fn server_network_sync(
+    tick: Res<SimTick>,
+    lobby: Res<ServerLobby>,
+    mut history: ResMut<SnapshotHistory>,
     mut server: ResMut<RenetServer>,
-    mut tr_set: ParamSet<(
-        Query<(Entity, &Transform, &CarWheels), With<Player>>,
-        Query<&Transform, With<Wheel>>,
-    )>,
+    q_car: Query<
+        (
+            Entity,
+            &Transform,
+            &Velocity,
+            &Car,
+            &CarWheels,
+            &PlayerInput,
+        ),
+        With<Player>,
+    >,
+    q_wheel: Query<(&Transform, &Velocity), With<Wheel>>,
 ) {
-    let mut networked_entities = NetworkedEntities::default();
-    let mut wheels_all: Vec<[Entity; 4]> = vec![];
-    for (entity, transform, wheels) in tr_set.p0().iter() {
-        networked_entities.entities.push(entity);
-        networked_entities
-            .translations
-            .push(transform.translation.into());
-        networked_entities.rotations.push(transform.rotation.into());
-
-        wheels_all.push(wheels.entities);
+    let mut cars = vec![];
+    for (entity, transform, velocity, car, wheels, input) in q_car.iter() {
+        let Ok(wheels) = q_wheel.get_many(wheels.entities) else {
+            continue;
+        };
+        cars.push(NetCar {
+            entity,
+            input_tick: input.tick,
+            esp: [car.prev_steering, car.prev_torque, car.prev_dir],
+            body: NetBody::new(transform, velocity),
+            wheels: wheels.map(|(t, v)| NetBody::new(t, v)),
+        });
     }
 
-    for wheels in wheels_all {
-        networked_entities.wheels_translations.push([
-            tr_set.p1().get(wheels[0]).unwrap().translation.into(),
-            tr_set.p1().get(wheels[1]).unwrap().translation.into(),
-            tr_set.p1().get(wheels[2]).unwrap().translation.into(),
-            tr_set.p1().get(wheels[3]).unwrap().translation.into(),
-        ]);
-        networked_entities.wheels_rotations.push([
-            tr_set.p1().get(wheels[0]).unwrap().rotation.into(),
-            tr_set.p1().get(wheels[1]).unwrap().rotation.into(),
-            tr_set.p1().get(wheels[2]).unwrap().rotation.into(),
-            tr_set.p1().get(wheels[3]).unwrap().rotation.into(),
-        ]);
+    for client_id in server.clients_id() {
+        let baseline = lobby
+            .acks
+            .get(&client_id.raw())
+            .and_then(|ack| Some((*ack, history.0.get(*ack)?)));
+        let message = NetworkedEntities::new(tick.0, &cars, baseline).encode();
+        server.send_message(client_id, ServerChannel::NetworkedEntities, message);
     }
-
-    let sync_message = bincode::serialize(&networked_entities).unwrap();
-    server.broadcast_message(ServerChannel::NetworkedEntities, sync_message);
+    history.0.push(tick.0, cars);
 }
 
-fn move_players_system(mut query: Query<(&PlayerInput, &mut Car)>) {
-    for (input, mut car) in query.iter_mut() {
-        if input.up {
-            car.gas = 1.;
-        } else {
-            car.gas = 0.;
-        }
-        if input.down {
-            car.brake = 1.;
-        } else {
-            car.brake = 0.;
-        }
-        if input.left {
-            car.steering = -1.;
-        }
-        if input.right {
-            car.steering = 1.;
+// one queued input per tick, the last one is held when the queue runs dry
+fn move_players_system(mut query: Query<(&mut InputQueue, &mut PlayerInput, &mut Car)>) {
+    for (mut queue, mut input, mut car) in query.iter_mut() {
+        let queue = &mut queue.0;
+        if queue.len() > INPUT_QUEUE_MAX {
+            queue.drain(..queue.len() - INPUT_QUEUE_MAX);
         }
-        if !input.left && !input.right {
-            car.steering = 0.;
+        if let Some(next) = queue.pop_front() {
+            *input = next;
         }
+        input.apply(&mut car);
     }
 }
 
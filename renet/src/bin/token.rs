@@ -0,0 +1,75 @@
+use bevy_garage_netcode::NETCODE_KEY_BYTES;
+use bevy_garage_renet::{private_key_from_env, PROTOCOL_ID};
+use bevy_renet::renet::transport::ConnectToken;
+use std::{
+    io::{BufRead, BufReader, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
+    time::SystemTime,
+};
+
+const TOKEN_EXPIRE_SECONDS: u64 = 300;
+const TIMEOUT_SECONDS: i32 = 15;
+
+// a new client id per token, the token carries the game server address
+fn connect_token(private_key: &[u8; NETCODE_KEY_BYTES], server_addr: SocketAddr) -> Vec<u8> {
+    let current_time = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap();
+    let client_id = rand::random::<u64>();
+    let token = ConnectToken::generate(
+        current_time,
+        PROTOCOL_ID,
+        TOKEN_EXPIRE_SECONDS,
+        client_id,
+        TIMEOUT_SECONDS,
+        vec![server_addr],
+        None,
+        private_key,
+    )
+    .unwrap();
+    let mut bytes = vec![];
+    token.write(&mut bytes).unwrap();
+    println!("connect token for client {}", client_id);
+    bytes
+}
+
+// any request gets a token, the request line is only logged
+fn respond(stream: TcpStream, private_key: &[u8; NETCODE_KEY_BYTES], server_addr: SocketAddr) {
+    let mut reader = BufReader::new(&stream);
+    let mut request = String::new();
+    let mut line = String::new();
+    while reader.read_line(&mut line).is_ok_and(|n| n > 2) {
+        if request.is_empty() {
+            request = line.trim().to_string();
+        }
+        line.clear();
+    }
+    println!("{}", request);
+    let body = connect_token(private_key, server_addr);
+    let mut stream = &stream;
+    let head = format!(
+        "HTTP/1.1 200 OK\r\ncontent-type: application/octet-stream\r\ncontent-length: {}\r\nconnection: close\r\n\r\n",
+        body.len()
+    );
+    let _ = stream.write_all(head.as_bytes());
+    let _ = stream.write_all(&body);
+}
+
+// NETCODE_PRIVATE_KEY=64 hex characters shared with the game server,
+// RENET_SERVER_ADDR=public address written into the tokens, RENET_TOKEN_SOCKET=http listen address
+fn main() {
+    let private_key = private_key_from_env().expect("NETCODE_PRIVATE_KEY is required");
+    let server_addr = std::env::var("RENET_SERVER_ADDR")
+        .unwrap_or("127.0.0.1:5000".to_string())
+        .parse()
+        .expect("invalid RENET_SERVER_ADDR");
+    let addr = std::env::var("RENET_TOKEN_SOCKET").unwrap_or("127.0.0.1:3000".to_string());
+    let listener = TcpListener::bind(&addr).unwrap();
+    println!(
+        "connect tokens on http://{}/connect for {}",
+        addr, server_addr
+    );
+    for stream in listener.incoming().flatten() {
+        respond(stream, &private_key, server_addr);
+    }
+}
@@ -1,41 +1,118 @@
-use bevy::prelude::*;
-use bevy_garage_car::STATIC_GROUP;
+pub mod prediction;
+pub mod snapshot;
+
+pub use prediction::*;
+pub use snapshot::*;
+
+use bevy::{ecs::schedule::SystemConfigs, prelude::*};
+use bevy_garage_car::{aero_system, esp_system, Car, CarSpec};
+use bevy_garage_track::{RaceLimit, RaceState};
 use bevy_rapier3d::prelude::*;
-use bevy_renet::renet::{transport::NETCODE_KEY_BYTES, ChannelConfig, ConnectionConfig, SendType};
+use bevy_renet::renet::{ChannelConfig, ConnectionConfig, SendType};
 use serde::{Deserialize, Serialize};
-use std::{f32::consts::PI, time::Duration};
+use std::{num::NonZeroUsize, time::Duration};
 
 pub fn rapier_config_start_system(mut c: ResMut<RapierContext>) {
-    c.integration_parameters.max_velocity_iterations = 64;
-    c.integration_parameters.max_velocity_friction_iterations = 64;
-    c.integration_parameters.max_stabilization_iterations = 16;
-    c.integration_parameters.erp = 0.99;
+    c.integration_parameters.num_solver_iterations = NonZeroUsize::new(6).unwrap();
+    c.integration_parameters.warmstart_coefficient = 0.;
+    c.integration_parameters.contact_natural_frequency = 50.;
+    c.integration_parameters.contact_damping_ratio = 50.;
+}
+
+pub use bevy_garage_netcode::{private_key_from_env, unsecure_from_env, PROTOCOL_ID};
+
+// seconds of one input tick, a single fixed physics step on the server and the client
+pub const NET_DT: f32 = 1. / 60.;
+pub const NET_SUBSTEPS: usize = 5;
+
+// per car systems before the physics step, the server and the client prediction run the same
+pub fn car_step_systems() -> SystemConfigs {
+    (aero_system, esp_system).chain()
+}
+
+// car specs to pick in the lobby, tuned from CarSpec::default
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Component)]
+pub enum CarChoice {
+    #[default]
+    Balanced,
+    // more torque and top speed, slower in the corners
+    Power,
+    // faster through the corners, less torque
+    Grip,
+}
+
+impl CarChoice {
+    pub const ALL: [CarChoice; 3] = [CarChoice::Balanced, CarChoice::Power, CarChoice::Grip];
+
+    pub fn spec(&self) -> CarSpec {
+        let kmh = 1000. / 3600.;
+        let mut spec = CarSpec::default();
+        match self {
+            CarChoice::Balanced => {}
+            CarChoice::Power => {
+                spec.wheel_max_torque = 1500.;
+                spec.max_speed = 330. * kmh;
+                spec.max_steering_speed = 240. * kmh;
+            }
+            CarChoice::Grip => {
+                spec.wheel_max_torque = 1000.;
+                spec.max_speed = 280. * kmh;
+                spec.max_steering_speed = 310. * kmh;
+            }
+        }
+        spec
+    }
 }
 
-pub const PRIVATE_KEY: &[u8; NETCODE_KEY_BYTES] = b"an example very very secret key."; // 32-bytes
-pub const PROTOCOL_ID: u64 = 7;
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LobbyPlayer {
+    pub id: u64,
+    pub car: CarChoice,
+    pub ready: bool,
+}
+
+// sent by clients on the lobby channel while the race is in warmup
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub enum LobbyCommand {
+    PickCar(CarChoice),
+    Ready(bool),
+    // host only, once every player is ready
+    Start,
+}
 
 #[derive(Debug, Component)]
 pub struct Player {
     pub id: u64,
 }
 
-#[derive(Debug, Default, Clone, Copy, Serialize, Deserialize, Component, Resource)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize, Component, Resource)]
 pub struct PlayerInput {
-    pub up: bool,
-    pub down: bool,
-    pub left: bool,
-    pub right: bool,
+    // client tick, acknowledged in snapshots once the server applied it
+    pub tick: u32,
+    // 0..1
+    pub gas: f32,
+    pub brake: f32,
+    // -1..1
+    pub steering: f32,
 }
 
-#[derive(Debug, Serialize, Deserialize, Component, Event)]
-pub enum PlayerCommand {
-    BasicAttack { cast_at: Vec3 },
+impl PlayerInput {
+    // NaN passes clamp, such inputs are dropped by the server
+    pub fn is_finite(&self) -> bool {
+        self.gas.is_finite() && self.brake.is_finite() && self.steering.is_finite()
+    }
+    pub fn apply(&self, car: &mut Car) {
+        car.gas = self.gas.clamp(0., 1.);
+        car.brake = self.brake.clamp(0., 1.);
+        car.steering = self.steering.clamp(-1., 1.);
+    }
 }
 
 pub enum ClientChannel {
     Input,
-    Command,
+    // latest decoded snapshot tick, the baseline for the next delta
+    SnapshotAck,
+    Lobby,
 }
 
 pub enum ServerChannel {
@@ -49,26 +126,69 @@ pub enum ServerMessages {
         entity: Entity,
         id: u64,
         translation: [f32; 3],
+        rotation: [f32; 4],
+        car: CarChoice,
     },
+    // the car of a disconnected player or after the race
     PlayerRemove {
         id: u64,
     },
+    // players in join order, the first one hosts
+    Lobby {
+        players: Vec<LobbyPlayer>,
+    },
+    // on every state change and start light
+    RaceState {
+        state: RaceState,
+        limit: RaceLimit,
+        lights: u32,
+        race_seconds: f32,
+        // inputs are held on the grid
+        frozen: bool,
+    },
+    // when a lap or a place changes, in race order
+    RaceStandings {
+        standings: Vec<RaceStanding>,
+    },
+    RaceResult {
+        classification: Vec<RaceClassification>,
+        // client id and lap seconds
+        fastest_lap: Option<(u64, f32)>,
+    },
 }
 
-#[derive(Debug, Serialize, Deserialize, Default)]
-pub struct NetworkedEntities {
-    pub entities: Vec<Entity>,
-    pub translations: Vec<[f32; 3]>,
-    pub rotations: Vec<[f32; 4]>,
-    pub wheels_translations: Vec<[[f32; 3]; 4]>,
-    pub wheels_rotations: Vec<[[f32; 4]; 4]>,
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RaceStanding {
+    pub id: u64,
+    // 1 based
+    pub place: usize,
+    // completed laps, -1 behind the line before the first crossing
+    pub laps: i32,
+    pub last_lap: Option<f32>,
+    pub best_lap: Option<f32>,
+    // race seconds with penalty
+    pub finished: Option<f32>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RaceClassification {
+    pub id: u64,
+    pub position: usize,
+    pub laps: i32,
+    pub finished: bool,
+    pub total_time: Option<f32>,
+    pub penalty: f32,
+    pub gap: Option<f32>,
+    pub laps_down: i32,
+    pub best_lap: Option<f32>,
 }
 
 impl From<ClientChannel> for u8 {
     fn from(channel_id: ClientChannel) -> Self {
         match channel_id {
-            ClientChannel::Command => 0,
-            ClientChannel::Input => 1,
+            ClientChannel::Input => 0,
+            ClientChannel::SnapshotAck => 1,
+            ClientChannel::Lobby => 2,
         }
     }
 }
@@ -84,10 +204,15 @@ impl ClientChannel {
                 },
             },
             ChannelConfig {
-                channel_id: Self::Command.into(),
-                max_memory_usage_bytes: 5 * 1024 * 1024,
+                channel_id: Self::SnapshotAck.into(),
+                max_memory_usage_bytes: 1024 * 1024,
+                send_type: SendType::Unreliable,
+            },
+            ChannelConfig {
+                channel_id: Self::Lobby.into(),
+                max_memory_usage_bytes: 1024 * 1024,
                 send_type: SendType::ReliableOrdered {
-                    resend_time: Duration::ZERO,
+                    resend_time: Duration::from_millis(200),
                 },
             },
         ]
@@ -129,45 +254,3 @@ pub fn connection_config() -> ConnectionConfig {
         server_channels_config: ServerChannel::channels_config(),
     }
 }
-
-pub fn setup_level(
-    mut cmd: Commands,
-    #[cfg(feature = "graphics")] mut meshes: ResMut<Assets<Mesh>>,
-    #[cfg(feature = "graphics")] mut materials: ResMut<Assets<StandardMaterial>>,
-) {
-    let size = 1000.;
-    let cuboid = Collider::cuboid(size / 2., 0.5, size / 2.);
-    let transform = Transform::from_xyz(0.0, -1.0, 0.0);
-
-    let mut cuboid_cmd = cmd.spawn((
-        cuboid,
-        RigidBody::Fixed,
-        ColliderScale::Absolute(Vec3::ONE),
-        CollisionGroups::new(STATIC_GROUP, Group::ALL),
-        Friction::coefficient(3.),
-        Restitution::coefficient(0.),
-    ));
-    #[cfg(feature = "graphics")]
-    cuboid_cmd.insert(PbrBundle {
-        mesh: meshes.add(Mesh::from(shape::Box::new(size, 1., size))),
-        material: materials.add(Color::rgb(0.3, 0.5, 0.3).into()),
-        transform,
-        ..Default::default()
-    });
-    #[cfg(not(feature = "graphics"))]
-    cuboid_cmd.insert(TransformBundle::from_transform(transform));
-
-    #[cfg(feature = "graphics")]
-    cmd.spawn(DirectionalLightBundle {
-        directional_light: DirectionalLight {
-            shadows_enabled: true,
-            ..default()
-        },
-        transform: Transform {
-            translation: Vec3::new(0.0, 2.0, 0.0),
-            rotation: Quat::from_rotation_x(-PI / 4.),
-            ..default()
-        },
-        ..default()
-    });
-}
@@ -0,0 +1,223 @@
+use crate::{car_step_systems, ClientChannel, PlayerInput, NET_DT, NET_SUBSTEPS};
+use bevy::{ecs::schedule::ScheduleLabel, prelude::*};
+use bevy_garage_car::{Car, CarWheels};
+use bevy_rapier3d::prelude::*;
+use bevy_renet::{client_connected, renet::RenetClient};
+use std::collections::VecDeque;
+
+// unacknowledged inputs kept for replay, two seconds
+const HISTORY_MAX: usize = 120;
+
+// aero, esp and a single rapier step, run every tick and again for each replayed input
+#[derive(ScheduleLabel, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct PredictionStep;
+
+// own car of the client, simulated locally ahead of the server
+#[derive(Component, Debug)]
+pub struct Predicted;
+
+#[derive(Debug, Clone, Copy)]
+pub struct BodyState {
+    pub transform: Transform,
+    pub velocity: Velocity,
+}
+
+// authoritative own car after the server step of input_tick
+#[derive(Debug, Clone, Copy)]
+pub struct PredictedCarState {
+    pub input_tick: u32,
+    pub body: BodyState,
+    pub wheels: [BodyState; 4],
+    // Car prev_steering, prev_torque, prev_dir
+    pub esp: [f32; 3],
+}
+
+// PREDICTION_TOLERANCE=meters between predicted and authoritative position before a rewind
+#[derive(Resource, Debug)]
+pub struct Prediction {
+    pub tick: u32,
+    // sent inputs with the predicted body position after their step
+    pub history: VecDeque<(PlayerInput, Vec3)>,
+    // latest snapshot of the own car, reconciled before the next tick
+    pub correction: Option<PredictedCarState>,
+    pub acked: u32,
+    pub tolerance: f32,
+    // race start, inputs are held like on the server
+    pub frozen: bool,
+    pub rewinds: u32,
+}
+
+impl Prediction {
+    pub fn from_env() -> Self {
+        Self {
+            tick: 0,
+            history: VecDeque::new(),
+            correction: None,
+            acked: 0,
+            tolerance: std::env::var("PREDICTION_TOLERANCE")
+                .ok()
+                .and_then(|v| v.parse::<f32>().ok())
+                .unwrap_or(0.05),
+            frozen: false,
+            rewinds: 0,
+        }
+    }
+    // snapshots can arrive out of order, only newer ones are kept
+    pub fn correct(&mut self, state: PredictedCarState) {
+        let newer = self.correction.map_or(state.input_tick >= self.acked, |c| {
+            state.input_tick >= c.input_tick
+        });
+        if newer {
+            self.correction = Some(state);
+        }
+    }
+}
+
+fn set_body(world: &mut World, e: Entity, body: BodyState) {
+    if let Some(mut transform) = world.get_mut::<Transform>(e) {
+        *transform = body.transform;
+    }
+    if let Some(mut velocity) = world.get_mut::<Velocity>(e) {
+        *velocity = body.velocity;
+    }
+}
+
+fn predict_step(world: &mut World, car: Entity, input: PlayerInput) {
+    let frozen = world.resource::<Prediction>().frozen;
+    if let Some(mut c) = world.get_mut::<Car>(car) {
+        match frozen {
+            true => {
+                c.gas = 0.;
+                c.brake = 1.;
+                c.steering = 0.;
+            }
+            false => input.apply(&mut c),
+        }
+    }
+    world.run_schedule(PredictionStep);
+    let position = world
+        .get::<Transform>(car)
+        .map_or(Vec3::ZERO, |t| t.translation);
+    let mut prediction = world.resource_mut::<Prediction>();
+    prediction.history.push_back((input, position));
+    if prediction.history.len() > HISTORY_MAX {
+        prediction.history.pop_front();
+    }
+}
+
+// rewinds to the snapshot and replays the inputs the server has not applied yet
+fn reconcile(world: &mut World, car: Entity, state: PredictedCarState) {
+    let mut prediction = world.resource_mut::<Prediction>();
+    if state.input_tick < prediction.acked {
+        return;
+    }
+    prediction.acked = state.input_tick;
+    let mut predicted = None;
+    while let Some((input, position)) = prediction.history.front().copied() {
+        if input.tick > state.input_tick {
+            break;
+        }
+        if input.tick == state.input_tick {
+            predicted = Some(position);
+        }
+        prediction.history.pop_front();
+    }
+    let error = predicted.map_or(f32::INFINITY, |p| {
+        p.distance(state.body.transform.translation)
+    });
+    if error <= prediction.tolerance {
+        return;
+    }
+    prediction.rewinds += 1;
+    let replay: Vec<PlayerInput> = prediction.history.drain(..).map(|(i, _)| i).collect();
+
+    set_body(world, car, state.body);
+    if let Some(wheels) = world.get::<CarWheels>(car).map(|w| w.entities) {
+        for (e, body) in wheels.into_iter().zip(state.wheels) {
+            set_body(world, e, body);
+        }
+    }
+    if let Some(mut c) = world.get_mut::<Car>(car) {
+        [c.prev_steering, c.prev_torque, c.prev_dir] = state.esp;
+    }
+    for input in replay {
+        predict_step(world, car, input);
+    }
+}
+
+// one input tick: reconcile, send the input and predict it
+pub fn prediction_system(world: &mut World) {
+    let mut q_car = world.query_filtered::<Entity, With<Predicted>>();
+    let Some(car) = q_car.iter(world).next() else {
+        return;
+    };
+    if let Some(state) = world.resource_mut::<Prediction>().correction.take() {
+        reconcile(world, car, state);
+    }
+    let mut prediction = world.resource_mut::<Prediction>();
+    prediction.tick += 1;
+    let tick = prediction.tick;
+    let input = PlayerInput {
+        tick,
+        ..*world.resource::<PlayerInput>()
+    };
+    let message = bincode::serialize(&input).unwrap();
+    world
+        .resource_mut::<RenetClient>()
+        .send_message(ClientChannel::Input, message);
+    predict_step(world, car, input);
+}
+
+// other cars follow the snapshots, only the own car is simulated
+#[allow(clippy::type_complexity)]
+pub fn remote_car_kinematic_system(
+    q_car: Query<(Entity, &CarWheels), (Added<CarWheels>, Without<Predicted>)>,
+    mut cmd: Commands,
+) {
+    for (e, wheels) in q_car.iter() {
+        for body in std::iter::once(e).chain(wheels.entities) {
+            cmd.entity(body).insert(RigidBody::KinematicPositionBased);
+        }
+    }
+}
+
+pub struct PredictionPlugin;
+
+impl Plugin for PredictionPlugin {
+    fn build(&self, app: &mut App) {
+        let mut rapier_config = RapierConfiguration::new(1.);
+        rapier_config.timestep_mode = TimestepMode::Fixed {
+            dt: NET_DT,
+            substeps: NET_SUBSTEPS,
+        };
+        app.insert_resource(rapier_config)
+            .insert_resource(Prediction::from_env())
+            .insert_resource(Time::<Fixed>::from_seconds(NET_DT as f64))
+            .add_plugins(
+                RapierPhysicsPlugin::<NoUserData>::default().with_default_system_setup(false),
+            )
+            .configure_sets(
+                PredictionStep,
+                (
+                    PhysicsSet::SyncBackend,
+                    PhysicsSet::StepSimulation,
+                    PhysicsSet::Writeback,
+                )
+                    .chain(),
+            )
+            .add_systems(
+                PredictionStep,
+                (
+                    car_step_systems().before(PhysicsSet::SyncBackend),
+                    RapierPhysicsPlugin::<NoUserData>::get_systems(PhysicsSet::SyncBackend)
+                        .in_set(PhysicsSet::SyncBackend),
+                    RapierPhysicsPlugin::<NoUserData>::get_systems(PhysicsSet::StepSimulation)
+                        .in_set(PhysicsSet::StepSimulation),
+                    RapierPhysicsPlugin::<NoUserData>::get_systems(PhysicsSet::Writeback)
+                        .in_set(PhysicsSet::Writeback),
+                ),
+            )
+            .add_systems(Update, remote_car_kinematic_system)
+            .add_systems(FixedUpdate, prediction_system.run_if(client_connected));
+    }
+}
@@ -0,0 +1,349 @@
+use crate::{BodyState, NET_DT};
+use bevy::prelude::*;
+use bevy_rapier3d::prelude::Velocity;
+use bincode::Options;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+
+// millimeters, mm/s and mrad/s
+const LINEAR_SCALE: f32 = 1000.;
+const ROTATION_SCALE: f32 = i16::MAX as f32;
+// snapshots kept as delta baselines and for interpolation, about a second
+pub const SNAPSHOT_HISTORY: usize = 64;
+
+// quantised rigid body, absolute or a delta against the baseline
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NetBody {
+    pub translation: [i32; 3],
+    // quaternion components scaled to i16
+    pub rotation: [i32; 4],
+    pub linvel: [i32; 3],
+    pub angvel: [i32; 3],
+}
+
+fn quantize<const N: usize>(v: [f32; N], scale: f32) -> [i32; N] {
+    v.map(|v| (v * scale).round() as i32)
+}
+
+fn dequantize<const N: usize>(v: [i32; N], scale: f32) -> [f32; N] {
+    v.map(|v| v as f32 / scale)
+}
+
+fn sub<const N: usize>(a: [i32; N], b: [i32; N]) -> [i32; N] {
+    std::array::from_fn(|i| a[i].wrapping_sub(b[i]))
+}
+
+fn add<const N: usize>(a: [i32; N], b: [i32; N]) -> [i32; N] {
+    std::array::from_fn(|i| a[i].wrapping_add(b[i]))
+}
+
+impl NetBody {
+    pub fn new(transform: &Transform, velocity: &Velocity) -> Self {
+        Self {
+            translation: quantize(transform.translation.into(), LINEAR_SCALE),
+            rotation: quantize(transform.rotation.normalize().into(), ROTATION_SCALE),
+            linvel: quantize(velocity.linvel.into(), LINEAR_SCALE),
+            angvel: quantize(velocity.angvel.into(), LINEAR_SCALE),
+        }
+    }
+    pub fn transform(&self) -> Transform {
+        let rotation = Quat::from_array(dequantize(self.rotation, ROTATION_SCALE));
+        Transform::from_translation(dequantize(self.translation, LINEAR_SCALE).into())
+            .with_rotation(rotation.normalize())
+    }
+    pub fn velocity(&self) -> Velocity {
+        Velocity {
+            linvel: dequantize(self.linvel, LINEAR_SCALE).into(),
+            angvel: dequantize(self.angvel, LINEAR_SCALE).into(),
+        }
+    }
+    pub fn state(&self) -> BodyState {
+        BodyState {
+            transform: self.transform(),
+            velocity: self.velocity(),
+        }
+    }
+    fn delta(&self, base: &Self) -> Self {
+        Self {
+            translation: sub(self.translation, base.translation),
+            rotation: sub(self.rotation, base.rotation),
+            linvel: sub(self.linvel, base.linvel),
+            angvel: sub(self.angvel, base.angvel),
+        }
+    }
+    fn undelta(&self, base: &Self) -> Self {
+        Self {
+            translation: add(self.translation, base.translation),
+            rotation: add(self.rotation, base.rotation),
+            linvel: add(self.linvel, base.linvel),
+            angvel: add(self.angvel, base.angvel),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct NetCar {
+    pub entity: Entity,
+    // last applied PlayerInput tick
+    pub input_tick: u32,
+    // Car prev_steering, prev_torque and prev_dir carried by esp_system between steps
+    pub esp: [f32; 3],
+    pub body: NetBody,
+    pub wheels: [NetBody; 4],
+}
+
+impl NetCar {
+    // a car missing in the baseline is sent against zero
+    fn delta(&self, base: Option<&Self>) -> Self {
+        let Some(base) = base else {
+            return *self;
+        };
+        Self {
+            input_tick: self.input_tick.wrapping_sub(base.input_tick),
+            body: self.body.delta(&base.body),
+            wheels: std::array::from_fn(|i| self.wheels[i].delta(&base.wheels[i])),
+            ..*self
+        }
+    }
+    fn undelta(&self, base: Option<&Self>) -> Self {
+        let Some(base) = base else {
+            return *self;
+        };
+        Self {
+            input_tick: self.input_tick.wrapping_add(base.input_tick),
+            body: self.body.undelta(&base.body),
+            wheels: std::array::from_fn(|i| self.wheels[i].undelta(&base.wheels[i])),
+            ..*self
+        }
+    }
+}
+
+// varint encoded, small deltas take a byte per component
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct NetworkedEntities {
+    // server tick after the physics step
+    pub tick: u32,
+    // cars are deltas against this snapshot acknowledged by the client, None for absolute
+    pub baseline: Option<u32>,
+    pub cars: Vec<NetCar>,
+}
+
+impl NetworkedEntities {
+    pub fn new(tick: u32, cars: &[NetCar], baseline: Option<(u32, &[NetCar])>) -> Self {
+        let find = |entity: Entity| {
+            baseline.and_then(|(_, base)| base.iter().find(|car| car.entity == entity))
+        };
+        Self {
+            tick,
+            baseline: baseline.map(|(tick, _)| tick),
+            cars: cars.iter().map(|car| car.delta(find(car.entity))).collect(),
+        }
+    }
+    // absolute cars, the baseline snapshot has to be the one the server encoded against
+    pub fn resolve(&self, baseline: Option<&[NetCar]>) -> Vec<NetCar> {
+        let find =
+            |entity: Entity| baseline.and_then(|base| base.iter().find(|c| c.entity == entity));
+        self.cars
+            .iter()
+            .map(|car| car.undelta(find(car.entity)))
+            .collect()
+    }
+    pub fn encode(&self) -> Vec<u8> {
+        bincode::DefaultOptions::new().serialize(self).unwrap()
+    }
+    pub fn decode(bytes: &[u8]) -> Option<Self> {
+        bincode::DefaultOptions::new().deserialize(bytes).ok()
+    }
+}
+
+// absolute snapshots by tick, oldest first
+#[derive(Debug, Default)]
+pub struct SnapshotBuffer {
+    pub snapshots: VecDeque<(u32, Vec<NetCar>)>,
+}
+
+impl SnapshotBuffer {
+    pub fn push(&mut self, tick: u32, cars: Vec<NetCar>) {
+        if self.latest().is_some_and(|latest| tick <= latest) {
+            return;
+        }
+        self.snapshots.push_back((tick, cars));
+        if self.snapshots.len() > SNAPSHOT_HISTORY {
+            self.snapshots.pop_front();
+        }
+    }
+    pub fn get(&self, tick: u32) -> Option<&[NetCar]> {
+        self.snapshots
+            .iter()
+            .find(|(t, _)| *t == tick)
+            .map(|(_, cars)| cars.as_slice())
+    }
+    pub fn latest(&self) -> Option<u32> {
+        self.snapshots.back().map(|(tick, _)| *tick)
+    }
+    // body and wheels between the snapshots around the tick, held at the ends
+    pub fn sample(&self, entity: Entity, tick: f32) -> Option<(Transform, [Transform; 4])> {
+        let find = |cars: &Vec<NetCar>| cars.iter().find(|car| car.entity == entity).copied();
+        let next = self
+            .snapshots
+            .iter()
+            .position(|(t, _)| *t as f32 >= tick)
+            .unwrap_or(self.snapshots.len().checked_sub(1)?);
+        let (to_tick, to) = &self.snapshots[next];
+        let to = find(to)?;
+        let pose = |car: &NetCar| (car.body.transform(), car.wheels.map(|w| w.transform()));
+        let Some((from_tick, Some(from))) = next
+            .checked_sub(1)
+            .map(|prev| &self.snapshots[prev])
+            .map(|(t, cars)| (*t, find(cars)))
+        else {
+            return Some(pose(&to));
+        };
+        let s = ((tick - from_tick as f32) / (*to_tick - from_tick) as f32).clamp(0., 1.);
+        let lerp = |a: Transform, b: Transform| Transform {
+            translation: a.translation.lerp(b.translation, s),
+            rotation: a.rotation.slerp(b.rotation, s),
+            scale: a.scale,
+        };
+        let (from, to) = (pose(&from), pose(&to));
+        Some((
+            lerp(from.0, to.0),
+            std::array::from_fn(|i| lerp(from.1[i], to.1[i])),
+        ))
+    }
+}
+
+// INTERPOLATION_DELAY=seconds remote cars are shown behind the latest snapshot
+#[derive(Resource, Debug)]
+pub struct SnapshotInterpolation {
+    pub snapshots: SnapshotBuffer,
+    pub delay: f32,
+    // elapsed seconds when the latest snapshot arrived
+    pub latest_at: f64,
+}
+
+impl SnapshotInterpolation {
+    pub fn from_env() -> Self {
+        Self {
+            snapshots: SnapshotBuffer::default(),
+            delay: std::env::var("INTERPOLATION_DELAY")
+                .ok()
+                .and_then(|v| v.parse::<f32>().ok())
+                .unwrap_or(0.1),
+            latest_at: 0.,
+        }
+    }
+    // fractional server tick to show
+    pub fn render_tick(&self, seconds: f64) -> Option<f32> {
+        let latest = self.snapshots.latest()?;
+        let since = (seconds - self.latest_at) as f32;
+        Some(latest as f32 + (since - self.delay) / NET_DT)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn body(x: f32, yaw: f32) -> NetBody {
+        let transform = Transform::from_xyz(x, 0.5, -x).with_rotation(Quat::from_rotation_y(yaw));
+        let velocity = Velocity {
+            linvel: Vec3::new(x, 0., 1.),
+            angvel: Vec3::new(0., yaw, 0.),
+        };
+        NetBody::new(&transform, &velocity)
+    }
+
+    fn car(index: u32, x: f32) -> NetCar {
+        NetCar {
+            entity: Entity::from_raw(index),
+            input_tick: 100 + index,
+            esp: [0.1, 0.2, 0.3],
+            body: body(x, 0.5),
+            wheels: std::array::from_fn(|i| body(x + i as f32, 0.5)),
+        }
+    }
+
+    #[test]
+    fn delta_round_trip() {
+        let base = vec![car(1, 10.), car(2, -20.)];
+        // car 3 is missing in the baseline and car 2 left
+        let cars = vec![car(1, 10.25), car(3, 5.)];
+        let snapshot = NetworkedEntities::new(8, &cars, Some((7, &base)));
+        let decoded = NetworkedEntities::decode(&snapshot.encode()).unwrap();
+        assert_eq!(decoded.tick, 8);
+        assert_eq!(decoded.baseline, Some(7));
+        assert_eq!(decoded.cars[1], cars[1]);
+        assert_eq!(decoded.resolve(Some(&base)), cars);
+
+        let absolute = NetworkedEntities::new(8, &cars, None);
+        assert_eq!(absolute.baseline, None);
+        assert_eq!(absolute.resolve(None), cars);
+        assert!(snapshot.encode().len() < absolute.encode().len());
+    }
+
+    #[test]
+    fn quantisation_error() {
+        let transform = Transform::from_xyz(123.4567, -0.0004, 987.6543)
+            .with_rotation(Quat::from_euler(EulerRot::YXZ, 2.1, -0.3, 0.05));
+        let velocity = Velocity {
+            linvel: Vec3::new(55.5555, -1.2345, 0.0006),
+            angvel: Vec3::new(-0.7777, 3.3333, 0.0004),
+        };
+        let state = NetBody::new(&transform, &velocity).state();
+        let linear = 0.5 / LINEAR_SCALE + 1e-4;
+        assert!(state.transform.translation.distance(transform.translation) <= linear);
+        assert!(state.transform.rotation.angle_between(transform.rotation) < 1e-3);
+        assert!(
+            (state.velocity.linvel - velocity.linvel)
+                .abs()
+                .max_element()
+                <= linear
+        );
+        assert!(
+            (state.velocity.angvel - velocity.angvel)
+                .abs()
+                .max_element()
+                <= linear
+        );
+    }
+
+    #[test]
+    fn delta_wraps() {
+        let mut base = car(1, 0.);
+        base.input_tick = u32::MAX;
+        base.body.translation = [i32::MAX, i32::MIN, 0];
+        let mut next = base;
+        next.input_tick = 1;
+        next.body.translation = [i32::MIN + 4, i32::MAX - 4, -1];
+        let delta = next.delta(Some(&base));
+        assert_eq!(delta.input_tick, 2);
+        assert_eq!(delta.body.translation, [5, -5, -1]);
+        assert_eq!(delta.undelta(Some(&base)), next);
+    }
+
+    #[test]
+    fn sample_interpolates_and_holds() {
+        let mut buffer = SnapshotBuffer::default();
+        buffer.push(10, vec![car(1, 0.)]);
+        buffer.push(12, vec![car(1, 2.)]);
+        // older or repeated ticks are ignored
+        buffer.push(11, vec![car(1, 100.)]);
+        assert_eq!(buffer.latest(), Some(12));
+        let entity = Entity::from_raw(1);
+        let at = |tick: f32| buffer.sample(entity, tick).unwrap().0.translation.x;
+        assert!((at(11.) - 1.).abs() < 1e-3);
+        assert!((at(11.5) - 1.5).abs() < 1e-3);
+        assert_eq!(at(5.), 0.);
+        assert_eq!(at(20.), 2.);
+        let (_, wheels) = buffer.sample(entity, 11.).unwrap();
+        assert!((wheels[3].translation.x - 4.).abs() < 1e-3);
+        assert!(buffer.sample(Entity::from_raw(2), 11.).is_none());
+
+        for tick in 13..13 + SNAPSHOT_HISTORY as u32 {
+            buffer.push(tick, vec![]);
+        }
+        assert_eq!(buffer.snapshots.len(), SNAPSHOT_HISTORY);
+        assert!(buffer.get(10).is_none());
+    }
+}
@@ -4,7 +4,7 @@ use bevy::{
     prelude::*,
 };
 use bevy_garage_car::Player;
-use bevy_garage_track::CarTrack;
+use bevy_garage_track::{format_lap_time, CarTrack, LapTiming};
 use bevy_rapier3d::prelude::*;
 
 #[derive(Component)]
@@ -187,7 +187,7 @@ pub fn dash_start_system(mut cmd: Commands, asset_server: Res<AssetServer>) {
                     })
                     .insert(KmphText);
 
-                #[cfg(feature = "nn")]
+                #[cfg(any(feature = "nn", feature = "nn_evolution"))]
                 {
                     use bevy_garage_nn::dash::{
                         TrainerEpsilonText, TrainerGenerationText, TrainerRewardsText,
@@ -286,9 +286,10 @@ pub fn dash_speed_update_system(
         Query<&mut Text, With<RideDistanceText>>,
         Query<&mut Text, With<LapText>>,
     )>,
-    mut cars: Query<(&Velocity, &CarTrack), With<Player>>,
+    mut cars: Query<(&Velocity, &CarTrack, Option<&LapTiming>), With<Player>>,
+    time: Res<Time>,
 ) {
-    for (velocity, car_track) in cars.iter_mut() {
+    for (velocity, car_track, timing) in cars.iter_mut() {
         let mps = velocity.linvel.length();
         let kmph = mps * 3.6;
         texts.p0().single_mut().sections[0].value = format!("{:.1}m/s", mps);
@@ -304,6 +305,23 @@ pub fn dash_speed_update_system(
         texts.p3().single_mut().sections[0].value =
             format!("{sign}{:.1}m", car_track.ride_distance.abs());
 
-        texts.p4().single_mut().sections[0].value = format!("lap {}", car_track.lap);
+        let lap = match timing {
+            Some(timing) => {
+                let lap_time = |t: Option<f32>| t.map_or("-:--.---".to_string(), format_lap_time);
+                let delta = match (timing.invalid, timing.delta) {
+                    (true, _) => " invalid".to_string(),
+                    (false, Some(delta)) => format!(" {delta:+.2}"),
+                    (false, None) => String::new(),
+                };
+                format!(
+                    "lap {} {}{delta}\nbest {}",
+                    car_track.lap,
+                    lap_time(timing.current(time.elapsed_seconds_f64())),
+                    lap_time(timing.best_lap)
+                )
+            }
+            None => format!("lap {}", car_track.lap),
+        };
+        texts.p4().single_mut().sections[0].value = lap;
     }
 }
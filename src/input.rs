@@ -1,7 +1,11 @@
 use bevy::prelude::*;
 use bevy_garage_camera::CameraConfig;
-use bevy_garage_car::{Car, CarRes, CarWheels, Player};
-use bevy_garage_track::SpawnCarOnTrackEvent;
+use bevy_garage_car::{
+    instruments::{Instruments, InstrumentsState},
+    lidar::{Lidar, LidarScan},
+    Car, CarRes, CarWheels, Player,
+};
+use bevy_garage_track::{Autopilot, PitStopPlan, ReplayPlayback, SpawnCarOnTrackEvent};
 
 pub fn input_system(
     input: Res<ButtonInput<KeyCode>>,
@@ -10,6 +14,10 @@ pub fn input_system(
     gamepads: Res<Gamepads>,
     mut camera_config: ResMut<CameraConfig>,
     mut cars: Query<(&mut Car, &mut CarWheels, Entity, &Transform), With<Player>>,
+    q_lidar: Query<(), With<Lidar>>,
+    q_instruments: Query<(), With<Instruments>>,
+    q_autopilot: Query<(), With<Autopilot>>,
+    q_pit_plan: Query<(), With<PitStopPlan>>,
     mut cmd: Commands,
     mut car_spawn_events: EventWriter<SpawnCarOnTrackEvent>,
     mut debug_ctx: ResMut<bevy_rapier3d::render::DebugRenderContext>,
@@ -71,8 +79,39 @@ pub fn input_system(
                 player: true,
                 index: 0,
                 position: None,
+                slot: None,
             });
         }
+        if input.just_pressed(KeyCode::F7) {
+            if q_lidar.contains(e) {
+                cmd.entity(e).remove::<(Lidar, LidarScan)>();
+            } else {
+                cmd.entity(e).insert(Lidar::default());
+            }
+        }
+        if input.just_pressed(KeyCode::F8) {
+            if q_instruments.contains(e) {
+                cmd.entity(e).remove::<(Instruments, InstrumentsState)>();
+            } else {
+                cmd.entity(e).insert(Instruments::default());
+            }
+        }
+        // pit request for the next lane entry
+        if input.just_pressed(KeyCode::KeyP) {
+            if q_pit_plan.contains(e) {
+                cmd.entity(e).remove::<PitStopPlan>();
+            } else {
+                cmd.entity(e).insert(PitStopPlan::default());
+            }
+        }
+        if input.just_pressed(KeyCode::F9) {
+            if q_autopilot.contains(e) {
+                cmd.entity(e).remove::<Autopilot>();
+                (car.gas, car.brake, car.steering) = (0., 0., 0.);
+            } else {
+                cmd.entity(e).insert(Autopilot::default());
+            }
+        }
         if input.pressed(KeyCode::ArrowUp) {
             car.gas = 1.;
         }
@@ -109,3 +148,30 @@ pub fn input_system(
         // }
     }
 }
+
+pub fn replay_input_system(
+    input: Res<ButtonInput<KeyCode>>,
+    playback: Option<ResMut<ReplayPlayback>>,
+) {
+    let Some(mut playback) = playback else {
+        return;
+    };
+    if input.just_pressed(KeyCode::F10) {
+        playback.toggle_pause();
+    }
+    if input.just_pressed(KeyCode::F11) {
+        playback.toggle_reverse();
+    }
+    if input.just_pressed(KeyCode::Comma) {
+        playback.step(-1.);
+    }
+    if input.just_pressed(KeyCode::Period) {
+        playback.step(1.);
+    }
+    if input.just_pressed(KeyCode::Minus) {
+        playback.scale_speed(0.5);
+    }
+    if input.just_pressed(KeyCode::Equal) {
+        playback.scale_speed(2.);
+    }
+}
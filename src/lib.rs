@@ -12,7 +12,10 @@ use bevy::{
     diagnostic::FrameTimeDiagnosticsPlugin, ecs::system::SystemParam,
     pbr::DirectionalLightShadowMap, prelude::*,
 };
-use bevy_garage_car::{aero_system, car_start_system, esp_system, CarRes, CarSet};
+use bevy_garage_car::{
+    aero_system, car_start_system, esp_system, instruments::InstrumentsPlugin, lidar::LidarPlugin,
+    CarRes, CarSet,
+};
 use bevy_garage_light::{animate_light_direction, light_start_system};
 use bevy_garage_track::{track_polyline_start_system, SpawnCarOnTrackEvent, TrackPlugin};
 use bevy_rapier3d::prelude::*;
@@ -33,9 +36,9 @@ fn rapier_config_start_system(mut c: ResMut<RapierContext>) {
 }
 
 pub fn car_app(app: &mut App) -> &mut App {
-    #[cfg(feature = "nn")]
+    #[cfg(any(feature = "nn", feature = "nn_evolution"))]
     let esp_run_after: CarSet = CarSet::NeuralNetwork;
-    #[cfg(not(feature = "nn"))]
+    #[cfg(not(any(feature = "nn", feature = "nn_evolution")))]
     let esp_run_after: CarSet = CarSet::Input;
 
     let mut rapier_config = RapierConfiguration::new(1.);
@@ -54,6 +57,8 @@ pub fn car_app(app: &mut App) -> &mut App {
             FrameTimeDiagnosticsPlugin::default(),
             RapierPhysicsPlugin::<MyPhysicsHooks>::default(),
             TrackPlugin,
+            LidarPlugin,
+            InstrumentsPlugin,
             RapierDebugRenderPlugin {
                 enabled: false,
                 style: DebugRenderStyle {
@@ -85,6 +90,7 @@ pub fn car_app(app: &mut App) -> &mut App {
                 spawn_car_system,
                 aero_system.in_set(CarSet::Input),
                 input_system.in_set(CarSet::Input),
+                replay_input_system,
                 esp_system.in_set(CarSet::Esp).after(esp_run_after),
                 animate_light_direction,
                 dash_fps_system,
@@ -100,6 +106,10 @@ pub fn car_app(app: &mut App) -> &mut App {
     {
         app.add_plugins(bevy_garage_nn::NeuralNetworkPlugin);
     }
+    #[cfg(feature = "nn_evolution")]
+    {
+        app.add_plugins(bevy_garage_nn::NeuroEvolutionPlugin);
+    }
 
     app
 }
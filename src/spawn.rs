@@ -1,12 +1,25 @@
 use bevy::prelude::*;
-use bevy_garage_car::CarRes;
-use bevy_garage_track::{spawn_car_on_track, SpawnCarOnTrackEvent, TrackConfig};
+use bevy_garage_car::{CarRes, SimRng};
+use bevy_garage_track::{
+    spawn_car_on_track, InitialVelocity, InputReplay, ReplayPlayback, SpawnCarOnTrackEvent,
+    StartingGrid, TrackConfig,
+};
 
-pub fn spawn_car_start_system(mut car_spawn_events: EventWriter<SpawnCarOnTrackEvent>) {
+pub fn spawn_car_start_system(
+    mut car_spawn_events: EventWriter<SpawnCarOnTrackEvent>,
+    grid: Option<Res<StartingGrid>>,
+    playback: Option<Res<ReplayPlayback>>,
+    input_replay: Option<Res<InputReplay>>,
+) {
+    if grid.is_some() || playback.is_some() || input_replay.is_some() {
+        // the player is spawned on the grid or by the replay
+        return;
+    }
     car_spawn_events.send(SpawnCarOnTrackEvent {
         player: true,
         index: 0,
         position: Some(0.),
+        slot: None,
     });
 }
 
@@ -15,19 +28,33 @@ pub fn spawn_car_system(
     mut cmd: Commands,
     track_config: ResMut<TrackConfig>,
     car_res: ResMut<CarRes>,
+    mut rng: Option<ResMut<SimRng>>,
+    input_replay: Option<Res<InputReplay>>,
 ) {
+    if input_replay.is_some() {
+        // re-simulation spawns the recorded cars
+        events.clear();
+        return;
+    }
     for spawn_event in events.read() {
         dbg!(spawn_event);
 
-        let (transform, init_meters) = if let Some(init_meters) = spawn_event.position {
-            let (translate, quat) = track_config.get_transform_by_meter(init_meters);
-            let transform = Transform::from_translation(translate).with_rotation(quat);
-            (transform, init_meters)
-        } else {
-            track_config.get_transform_random()
+        let (transform, init_meters) = match (&spawn_event.slot, spawn_event.position) {
+            (Some(slot), _) => (slot.transform, slot.meters),
+            (None, Some(init_meters)) => {
+                let (translate, quat) = track_config.get_transform_by_meter(init_meters);
+                let transform = Transform::from_translation(translate).with_rotation(quat);
+                (transform, init_meters)
+            }
+            (None, None) => match rng.as_mut() {
+                Some(rng) => {
+                    track_config.get_transform_random_with(&mut rng.0, track_config.track_length)
+                }
+                None => track_config.get_transform_random(),
+            },
         };
 
-        spawn_car_on_track(
+        let car_id = spawn_car_on_track(
             &mut cmd,
             &car_res.car_scene.as_ref().unwrap(),
             &car_res.wheel_scene.as_ref().unwrap(),
@@ -36,5 +63,10 @@ pub fn spawn_car_system(
             spawn_event.index,
             init_meters,
         );
+        if let Some(slot) = spawn_event.slot.as_ref() {
+            if slot.velocity != Vec3::ZERO {
+                cmd.entity(car_id).insert(InitialVelocity(slot.velocity));
+            }
+        }
     }
 }
@@ -0,0 +1,46 @@
+// cargo run -p bevy_garage_track --example telemetry_listener -- 127.0.0.1:20777
+use bevy_garage_track::{format_lap_time, TelemetryPacket};
+use std::net::UdpSocket;
+
+fn main() -> std::io::Result<()> {
+    let addr = std::env::args()
+        .nth(1)
+        .unwrap_or("127.0.0.1:20777".to_string());
+    let socket = UdpSocket::bind(&addr)?;
+    println!("listening on {addr}");
+    let mut buf = [0u8; 1024];
+    loop {
+        let (len, _) = socket.recv_from(&mut buf)?;
+        let Some((header, packet)) = TelemetryPacket::decode(&buf[..len]) else {
+            println!("unknown packet, {len} bytes");
+            continue;
+        };
+        let car = match header.player {
+            true => format!("player {}", header.car),
+            false => format!("car {}", header.car),
+        };
+        match packet {
+            TelemetryPacket::Motion(m) => println!(
+                "{:8.2}s {car} motion pos {:.1} {:.1} {:.1} G lat {:.2} long {:.2}",
+                header.time, m.position.x, m.position.y, m.position.z, m.g_lat, m.g_long
+            ),
+            TelemetryPacket::Status(s) => println!(
+                "{:8.2}s {car} status {:.0} km/h throttle {:.2} brake {:.2} steering {:.2} slip {:.2?} fuel {:.1}",
+                header.time, s.speed, s.throttle, s.brake, s.steering, s.wheel_slip, s.fuel
+            ),
+            TelemetryPacket::Lap(l) => println!(
+                "{:8.2}s {car} lap {} P{} {:.0}m sector {} current {} best {} delta {:+.3}{}{}",
+                header.time,
+                l.lap,
+                l.place,
+                l.lap_distance,
+                l.sector + 1,
+                format_lap_time(l.current_lap),
+                format_lap_time(l.best_lap),
+                l.delta,
+                if l.invalid { " invalid" } else { "" },
+                if l.in_pit { " pit" } else { "" },
+            ),
+        }
+    }
+}
@@ -4,7 +4,7 @@ use bevy::{
     prelude::*,
     render::{mesh::*, primitives::Aabb, render_asset::RenderAssetUsages},
 };
-use bevy_garage_car::STATIC_GROUP;
+use bevy_garage_car::{SurfaceKind, STATIC_GROUP};
 use bevy_rapier3d::{na::Point3, prelude::*, rapier::prelude::ColliderShape};
 
 #[derive(Component, Debug)]
@@ -142,6 +142,7 @@ pub fn spawn_road(
 
     cmd.spawn((
         TrackRoad,
+        SurfaceKind::Asphalt,
         Collider::from(ColliderShape::trimesh(
             track_vertices
                 .iter()
@@ -0,0 +1,200 @@
+use crate::{CarTrack, CenterLine, RacingLine, SpawnCarOnTrackEvent, TrackConfig, TrackLine};
+use bevy::prelude::*;
+use bevy_garage_car::{Car, CarSpec};
+use bevy_rapier3d::prelude::*;
+
+#[derive(Debug, Clone, Copy)]
+pub enum Steering {
+    // lookahead = base + gain * speed, meters
+    PurePursuit {
+        lookahead_base: f32,
+        lookahead_gain: f32,
+    },
+    // heading error + atan(k * cross track error / (softening + speed))
+    Stanley {
+        k: f32,
+        softening: f32,
+    },
+}
+
+#[derive(Debug, Clone)]
+pub struct Pid {
+    pub kp: f32,
+    pub ki: f32,
+    pub kd: f32,
+    integral: f32,
+    prev_error: Option<f32>,
+}
+
+impl Pid {
+    pub fn new(kp: f32, ki: f32, kd: f32) -> Self {
+        Self {
+            kp,
+            ki,
+            kd,
+            integral: 0.,
+            prev_error: None,
+        }
+    }
+    pub fn update(&mut self, error: f32, dt: f32) -> f32 {
+        if dt <= 0. {
+            return self.kp * error;
+        }
+        self.integral = (self.integral + error * dt).clamp(-10., 10.);
+        let derivative = match self.prev_error {
+            Some(prev_error) => (error - prev_error) / dt,
+            None => 0.,
+        };
+        self.prev_error = Some(error);
+        self.kp * error + self.ki * self.integral + self.kd * derivative
+    }
+}
+
+// deterministic driver, follows own line, the racing line or the track center line
+#[derive(Component, Debug, Clone)]
+pub struct Autopilot {
+    pub steering: Steering,
+    pub speed_pid: Pid,
+    // multiplier of the line speed profile
+    pub speed_scale: f32,
+    pub line: Option<TrackLine>,
+}
+
+impl Default for Autopilot {
+    fn default() -> Self {
+        Self::pure_pursuit()
+    }
+}
+
+impl Autopilot {
+    pub fn pure_pursuit() -> Self {
+        Self {
+            steering: Steering::PurePursuit {
+                lookahead_base: 6.,
+                lookahead_gain: 0.4,
+            },
+            speed_pid: Pid::new(0.3, 0.02, 0.01),
+            speed_scale: 1.,
+            line: None,
+        }
+    }
+    pub fn stanley() -> Self {
+        Self {
+            steering: Steering::Stanley {
+                k: 1.5,
+                softening: 2.,
+            },
+            ..Self::pure_pursuit()
+        }
+    }
+    pub fn with_line(mut self, line: TrackLine) -> Self {
+        self.line = Some(line);
+        self
+    }
+}
+
+pub fn autopilot_system(
+    time: Res<Time>,
+    racing_line: Option<Res<RacingLine>>,
+    center_line: Option<Res<CenterLine>>,
+    mut q_car: Query<(&mut Car, &mut Autopilot, &CarSpec, &Transform, &Velocity)>,
+) {
+    let dt = time.delta_seconds();
+    for (mut car, mut autopilot, spec, t, v) in q_car.iter_mut() {
+        let autopilot = autopilot.as_mut();
+        let line = match (&autopilot.line, &racing_line, &center_line) {
+            (Some(line), _, _) => line,
+            (None, Some(racing_line), _) => &racing_line.0,
+            (None, None, Some(center_line)) => &center_line.0,
+            (None, None, None) => continue,
+        };
+        let speed = v.linvel.length();
+        let inverse_rotation = t.rotation.inverse();
+        let projection = line.project(t.translation);
+        // car local frame, +Z forward, -X right, positive steering turns right
+        let to_local = |p: Vec3| {
+            let mut local = inverse_rotation.mul_vec3(p);
+            local.y = 0.;
+            local
+        };
+        let wheel_base = 2. * spec.wheel_mount[0].anchor.z.abs();
+        let angle = match autopilot.steering {
+            Steering::PurePursuit {
+                lookahead_base,
+                lookahead_gain,
+            } => {
+                let lookahead = lookahead_base + lookahead_gain * speed;
+                let target = to_local(line.point_at(projection.meters + lookahead) - t.translation);
+                let alpha = (-target.x).atan2(target.z);
+                (2. * wheel_base * alpha.sin() / target.length().max(0.1)).atan()
+            }
+            Steering::Stanley { k, softening } => {
+                let tangent = to_local(projection.tangent);
+                let heading_error = (-tangent.x).atan2(tangent.z);
+                let front_axle = t.translation + t.rotation.mul_vec3(Vec3::Z * wheel_base / 2.);
+                let front = line.project(front_axle);
+                let cross_track = -to_local(front.point - front_axle).x;
+                heading_error + (k * cross_track).atan2(softening + speed)
+            }
+        };
+        car.steering = (angle / spec.wheel_max_angle).clamp(-1., 1.);
+
+        let target_speed = line.speed_at(projection.meters) * autopilot.speed_scale;
+        let u = autopilot.speed_pid.update(target_speed - speed, dt);
+        (car.gas, car.brake) = match u >= 0. {
+            true => (u.min(1.), 0.),
+            false => (0., (-u).min(1.)),
+        };
+    }
+}
+
+// CarTrack index of the first opponent, keeps them apart from player and trainer cars
+pub const AUTOPILOT_FIRST_INDEX: usize = 1000;
+
+// AUTOPILOT_OPPONENTS=n spawns n autopilot cars behind the start, every second one uses Stanley
+#[derive(Resource, Debug)]
+pub struct AutopilotOpponents {
+    pub count: usize,
+}
+
+impl AutopilotOpponents {
+    pub fn from_env() -> Option<Self> {
+        let count = std::env::var("AUTOPILOT_OPPONENTS")
+            .ok()?
+            .parse::<usize>()
+            .ok()?;
+        Some(Self { count })
+    }
+}
+
+pub fn autopilot_opponents_start_system(
+    opponents: Res<AutopilotOpponents>,
+    track_config: Res<TrackConfig>,
+    mut car_spawn_events: EventWriter<SpawnCarOnTrackEvent>,
+) {
+    for i in 0..opponents.count {
+        let meters = track_config.track_length - 12. * (i + 1) as f32;
+        car_spawn_events.send(SpawnCarOnTrackEvent {
+            player: false,
+            index: AUTOPILOT_FIRST_INDEX + i,
+            position: Some(meters.max(0.)),
+            slot: None,
+        });
+    }
+}
+
+pub fn add_autopilot_on_spawned_car_system(
+    q_car: Query<(Entity, &CarTrack), Added<CarTrack>>,
+    mut cmd: Commands,
+) {
+    for (e, car_track) in q_car.iter() {
+        if car_track.index < AUTOPILOT_FIRST_INDEX {
+            continue;
+        }
+        let autopilot = match (car_track.index - AUTOPILOT_FIRST_INDEX) % 2 {
+            0 => Autopilot::pure_pursuit(),
+            _ => Autopilot::stanley(),
+        };
+        cmd.entity(e).insert(autopilot);
+    }
+}
@@ -1,3 +1,4 @@
+use crate::GridSlot;
 use bevy::prelude::*;
 use bevy_garage_car::spawn_car;
 
@@ -6,6 +7,8 @@ pub struct SpawnCarOnTrackEvent {
     pub player: bool,
     pub index: usize,
     pub position: Option<f32>,
+    // exact transform and velocity, overrides position
+    pub slot: Option<GridSlot>,
 }
 
 #[derive(Component, Debug)]
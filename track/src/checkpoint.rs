@@ -0,0 +1,196 @@
+use crate::{CarTrack, TrackConfig};
+use bevy::prelude::*;
+use bevy_garage_car::{CarSet, CAR_TRAINING_GROUP, CHECKPOINT_GROUP};
+use bevy_rapier3d::prelude::*;
+
+// evenly spaced gates along the polyline, gate 0 is the start line
+#[derive(Resource, Debug, Clone)]
+pub struct CheckpointConfig {
+    pub count: usize,
+    // half extents of the gate box across, up and along the track
+    pub half_size: Vec3,
+}
+
+impl Default for CheckpointConfig {
+    fn default() -> Self {
+        Self {
+            count: 20,
+            half_size: Vec3::new(8., 2., 0.25),
+        }
+    }
+}
+
+impl CheckpointConfig {
+    pub fn spacing(&self, track_length: f32) -> f32 {
+        track_length / self.count.max(1) as f32
+    }
+    // track position of the gate
+    pub fn meters(&self, gate: usize, track_length: f32) -> f32 {
+        gate as f32 * self.spacing(track_length)
+    }
+}
+
+#[derive(Component, Debug)]
+pub struct Checkpoint {
+    pub index: usize,
+    // along the track
+    pub forward: Vec3,
+}
+
+#[derive(Component, Debug, Clone, Default)]
+pub struct CheckpointProgress {
+    // gate expected next
+    pub next: usize,
+    // the start line was crossed, laps can be counted
+    pub started: bool,
+    // a gate was skipped on this lap
+    pub missed: bool,
+    pub wrong_way: bool,
+}
+
+// a car body entering a gate sensor, the single source of lap counting
+#[derive(Event, Debug, Clone)]
+pub struct GateCrossed {
+    pub entity: Entity,
+    pub gate: usize,
+    // against the track direction
+    pub backward: bool,
+    // forward: no gate was skipped since the previous start line crossing,
+    // backward: the gate has to be passed again
+    pub counted: bool,
+}
+
+#[derive(Event, Debug, Clone)]
+pub struct WrongWay {
+    pub entity: Entity,
+    pub gate: usize,
+}
+
+// sensors only the car body collides with, wheels and the static world pass through
+pub fn checkpoint_start_system(
+    mut cmd: Commands,
+    track_config: Res<TrackConfig>,
+    config: Res<CheckpointConfig>,
+) {
+    for index in 0..config.count {
+        let meters = config.meters(index, track_config.track_length);
+        let (translation, rotation) = track_config.get_transform_by_meter(meters);
+        cmd.spawn((
+            Name::new(format!("Checkpoint {index}")),
+            Checkpoint {
+                index,
+                forward: rotation.mul_vec3(Vec3::Z),
+            },
+            Collider::cuboid(config.half_size.x, config.half_size.y, config.half_size.z),
+            Sensor,
+            ActiveEvents::COLLISION_EVENTS,
+            CollisionGroups::new(CHECKPOINT_GROUP, CAR_TRAINING_GROUP),
+            TransformBundle::from_transform(
+                Transform::from_translation(translation + Vec3::Y * config.half_size.y / 2.)
+                    .with_rotation(rotation),
+            ),
+        ));
+    }
+}
+
+pub fn add_checkpoint_progress_system(
+    q_car: Query<(Entity, &CarTrack), Added<CarTrack>>,
+    track_config: Res<TrackConfig>,
+    config: Res<CheckpointConfig>,
+    mut cmd: Commands,
+) {
+    for (e, car_track) in q_car.iter() {
+        // CarTrack position is not updated yet, spawned at start_shift meters
+        let spacing = config.spacing(track_config.track_length);
+        let next = (car_track.start_shift / spacing).ceil() as usize % config.count.max(1);
+        cmd.entity(e)
+            .insert(CheckpointProgress { next, ..default() });
+    }
+}
+
+pub fn checkpoint_system(
+    mut collision_events: EventReader<CollisionEvent>,
+    config: Res<CheckpointConfig>,
+    q_gate: Query<&Checkpoint>,
+    mut q_car: Query<(&mut CheckpointProgress, &mut CarTrack, &Velocity)>,
+    mut gate_events: EventWriter<GateCrossed>,
+    mut wrong_way_events: EventWriter<WrongWay>,
+) {
+    let count = config.count.max(1);
+    for event in collision_events.read() {
+        let CollisionEvent::Started(a, b, _) = event else {
+            continue;
+        };
+        let (gate, e) = match (q_gate.get(*a), q_gate.get(*b)) {
+            (Ok(gate), _) => (gate, *b),
+            (_, Ok(gate)) => (gate, *a),
+            _ => continue,
+        };
+        let Ok((mut progress, mut car_track, v)) = q_car.get_mut(e) else {
+            continue;
+        };
+        let g = gate.index;
+        let backward = v.linvel.dot(gate.forward) < 0.;
+        if backward {
+            progress.wrong_way = true;
+            wrong_way_events.send(WrongWay { entity: e, gate: g });
+            // has to be passed forward again
+            let counted = (g + 1) % count == progress.next;
+            if counted {
+                progress.next = g;
+                if g == 0 && progress.started {
+                    car_track.lap -= 1;
+                }
+            }
+            gate_events.send(GateCrossed {
+                entity: e,
+                gate: g,
+                backward,
+                counted,
+            });
+            continue;
+        }
+        progress.wrong_way = false;
+        if g != progress.next {
+            progress.missed = true;
+        }
+        let counted = !progress.missed;
+        if g == 0 {
+            if progress.started && counted {
+                car_track.lap += 1;
+            }
+            progress.started = true;
+            progress.missed = false;
+        }
+        progress.next = (g + 1) % count;
+        gate_events.send(GateCrossed {
+            entity: e,
+            gate: g,
+            backward,
+            counted,
+        });
+    }
+}
+
+pub struct CheckpointPlugin;
+
+impl Plugin for CheckpointPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<CheckpointConfig>()
+            .add_event::<GateCrossed>()
+            .add_event::<WrongWay>()
+            .add_systems(
+                Startup,
+                checkpoint_start_system.after(crate::track_polyline_start_system),
+            )
+            .add_systems(
+                Update,
+                (
+                    add_checkpoint_progress_system,
+                    checkpoint_system
+                        .in_set(CarSet::Input)
+                        .after(crate::progress_system),
+                ),
+            );
+    }
+}
@@ -1,3 +1,4 @@
+use crate::{PitLaneLayout, PIT_LANES, TRACKS};
 use bevy::prelude::*;
 use bevy_rapier3d::parry::shape::Polyline;
 use rand::Rng;
@@ -5,6 +6,7 @@ use rand::Rng;
 
 #[derive(Resource)]
 pub struct TrackConfig {
+    pub track_index: usize,
     pub polyline: Option<Polyline>,
     pub segments: Vec<f32>,
     pub start_segment_i: usize,
@@ -15,6 +17,7 @@ pub struct TrackConfig {
 impl Default for TrackConfig {
     fn default() -> Self {
         Self {
+            track_index: 0,
             polyline: None,
             segments: vec![],
             start_segment_i: 0,
@@ -31,9 +34,24 @@ impl TrackConfig {
     //     let transform = Transform::from_translation(translate).with_rotation(quat);
     //     return (transform, meters);
     // }
+    // one of TRACKS, has to be chosen before Startup
+    pub fn positions(&self) -> &'static [(f32, f32, f32, f32)] {
+        TRACKS[self.track_index.min(TRACKS.len() - 1)]
+    }
+    pub fn pit_lane(&self) -> &'static PitLaneLayout {
+        &PIT_LANES[self.track_index.min(PIT_LANES.len() - 1)]
+    }
     pub fn get_transform_random(&self) -> (Transform, f32) {
-        let mut rng = rand::thread_rng();
-        let meters = rng.gen_range(0.0..self.track_length);
+        self.get_transform_random_with(&mut rand::thread_rng(), self.track_length)
+    }
+    // random position within max_meters from the start, rng can be seeded by caller
+    pub fn get_transform_random_with<R: Rng>(
+        &self,
+        rng: &mut R,
+        max_meters: f32,
+    ) -> (Transform, f32) {
+        let max_meters = max_meters.min(self.track_length).max(1.);
+        let meters = rng.gen_range(0.0..max_meters);
         let (translate, quat) = self.get_transform_by_meter(meters);
         let transform = Transform::from_translation(translate).with_rotation(quat);
         return (transform, meters);
@@ -54,7 +72,8 @@ impl TrackConfig {
                 let a: Vec3 = segment.a.into();
                 let dir: Vec3 = segment.direction().unwrap().into();
                 let mut pos: Vec3 = a + dir * (shift - seg_meters);
-                pos.y = 0.47;
+                // car body center above the polyline elevation
+                pos.y += 0.47;
 
                 return (pos, Quat::from_rotation_arc(Vec3::Z, dir));
             }
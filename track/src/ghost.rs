@@ -0,0 +1,322 @@
+use crate::{format_lap_time, CarTrack, LapCompleted, LapTiming, TrackConfig};
+use bevy::{prelude::*, utils::HashMap};
+use bevy_garage_car::{CarRes, CarWheels, Player};
+use serde::{Deserialize, Serialize};
+use std::{fs, path::PathBuf};
+
+const GHOST_ALPHA: f32 = 0.35;
+
+// replay is time based, the ghost is where the best lap was at the same lap time
+#[derive(Debug, Clone, Copy)]
+pub struct GhostSample {
+    // meters from the lap start by CarTrack.ride_distance
+    pub distance: f32,
+    // seconds from the lap start
+    pub time: f32,
+    pub body: Transform,
+    pub wheels: [Transform; 4],
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct GhostLap {
+    pub lap_time: f32,
+    pub samples: Vec<GhostSample>,
+}
+
+impl GhostLap {
+    // body and wheels interpolated at seconds from the lap start
+    pub fn transforms_at(&self, time: f32) -> Option<(Transform, [Transform; 4])> {
+        let last = self.samples.last()?;
+        if time > last.time {
+            return None;
+        }
+        let i = self.samples.partition_point(|s| s.time <= time);
+        let b = self.samples[i.min(self.samples.len() - 1)];
+        let a = self.samples[i.saturating_sub(1)];
+        let t = match b.time > a.time {
+            true => (time - a.time) / (b.time - a.time),
+            false => 0.,
+        };
+        let lerp = |a: &Transform, b: &Transform| Transform {
+            translation: a.translation.lerp(b.translation, t),
+            rotation: a.rotation.slerp(b.rotation, t),
+            scale: a.scale,
+        };
+        let wheels = [0, 1, 2, 3].map(|w| lerp(&a.wheels[w], &b.wheels[w]));
+        Some((lerp(&a.body, &b.body), wheels))
+    }
+}
+
+// translation and rotation arrays
+type TransformFile = ([f32; 3], [f32; 4]);
+
+#[derive(Serialize, Deserialize)]
+pub struct GhostFile {
+    pub lap_time: f32,
+    // distance, time, body and four wheels
+    pub samples: Vec<(f32, f32, TransformFile, [TransformFile; 4])>,
+}
+
+fn to_file(t: &Transform) -> TransformFile {
+    (t.translation.to_array(), t.rotation.to_array())
+}
+
+fn from_file((translation, rotation): &TransformFile) -> Transform {
+    Transform::from_translation(Vec3::from(*translation)).with_rotation(Quat::from_array(*rotation))
+}
+
+impl From<&GhostLap> for GhostFile {
+    fn from(lap: &GhostLap) -> Self {
+        Self {
+            lap_time: lap.lap_time,
+            samples: lap
+                .samples
+                .iter()
+                .map(|s| {
+                    (
+                        s.distance,
+                        s.time,
+                        to_file(&s.body),
+                        s.wheels.each_ref().map(to_file),
+                    )
+                })
+                .collect(),
+        }
+    }
+}
+
+impl From<GhostFile> for GhostLap {
+    fn from(file: GhostFile) -> Self {
+        Self {
+            lap_time: file.lap_time,
+            samples: file
+                .samples
+                .iter()
+                .map(|(distance, time, body, wheels)| GhostSample {
+                    distance: *distance,
+                    time: *time,
+                    body: from_file(body),
+                    wheels: wheels.each_ref().map(from_file),
+                })
+                .collect(),
+        }
+    }
+}
+
+pub fn load_ghost(path: &PathBuf) -> Option<GhostLap> {
+    let ron = fs::read_to_string(path).ok()?;
+    let file: GhostFile = ron::from_str(&ron).ok()?;
+    Some(file.into())
+}
+
+pub fn save_ghost(path: &PathBuf, lap: &GhostLap) -> Result<(), String> {
+    let ron = ron::to_string(&GhostFile::from(lap)).map_err(|e| e.to_string())?;
+    fs::write(path, ron).map_err(|e| e.to_string())
+}
+
+// best lap of the player, GHOST_FILE=path loads it at start and saves every new best
+#[derive(Resource, Debug, Default)]
+pub struct Ghost {
+    pub best: Option<GhostLap>,
+    pub file: Option<PathBuf>,
+    recording: Vec<GhostSample>,
+    start_distance: f32,
+    lap_started_at: Option<f64>,
+}
+
+impl Ghost {
+    pub fn from_env() -> Self {
+        let file = std::env::var("GHOST_FILE").ok().map(PathBuf::from);
+        let best = file.as_ref().and_then(load_ghost);
+        if let Some(best) = best.as_ref() {
+            println!("ghost loaded, lap {}", format_lap_time(best.lap_time));
+        }
+        Self {
+            best,
+            file,
+            ..default()
+        }
+    }
+}
+
+#[derive(Component, Debug)]
+pub struct GhostCar;
+
+#[derive(Component, Debug)]
+pub struct GhostWheel(pub usize);
+
+pub fn ghost_record_system(
+    time: Res<Time>,
+    track_config: Res<TrackConfig>,
+    mut ghost: ResMut<Ghost>,
+    q_player: Query<(Entity, &CarTrack, &LapTiming, &Transform, &CarWheels), With<Player>>,
+    q_wheel: Query<&Transform>,
+    mut lap_events: EventReader<LapCompleted>,
+) {
+    let Ok((e, car_track, timing, transform, wheels)) = q_player.get_single() else {
+        return;
+    };
+    let wheel_transforms = wheels
+        .entities
+        .map(|w| q_wheel.get(w).copied().unwrap_or_default());
+    for lap in lap_events.read().filter(|lap| lap.entity == e) {
+        let faster = ghost
+            .best
+            .as_ref()
+            .is_none_or(|best| lap.time < best.lap_time);
+        if !lap.valid || !faster || ghost.recording.is_empty() {
+            continue;
+        }
+        let mut samples = std::mem::take(&mut ghost.recording);
+        samples.push(GhostSample {
+            // the ride distance already restarted at the line
+            distance: track_config.track_length,
+            time: lap.time,
+            body: *transform,
+            wheels: wheel_transforms,
+        });
+        let best = GhostLap {
+            lap_time: lap.time,
+            samples,
+        };
+        if let Some(path) = ghost.file.as_ref() {
+            if let Err(err) = save_ghost(path, &best) {
+                println!("ghost save error {path:?}: {err}");
+            }
+        }
+        ghost.best = Some(best);
+    }
+
+    if timing.lap_started_at != ghost.lap_started_at {
+        ghost.lap_started_at = timing.lap_started_at;
+        ghost.start_distance = car_track.ride_distance;
+        ghost.recording.clear();
+    }
+    let Some(time) = timing.current(time.elapsed_seconds_f64()) else {
+        return;
+    };
+    // every tick, backing over the start line makes the distance negative instead of a track length
+    let distance = car_track.ride_distance - ghost.start_distance;
+    ghost.recording.push(GhostSample {
+        distance,
+        time,
+        body: *transform,
+        wheels: wheel_transforms,
+    });
+}
+
+// kinematic copy of the car scene without colliders, spawned once there is a best lap
+pub fn ghost_spawn_system(
+    ghost: Res<Ghost>,
+    car_res: Res<CarRes>,
+    q_ghost: Query<(), With<GhostCar>>,
+    mut cmd: Commands,
+) {
+    if ghost.best.is_none() || !q_ghost.is_empty() {
+        return;
+    }
+    let (Some(car_scene), Some(wheel_scene)) = (&car_res.car_scene, &car_res.wheel_scene) else {
+        return;
+    };
+    cmd.spawn((
+        Name::new("ghost"),
+        GhostCar,
+        SceneBundle {
+            scene: car_scene.clone(),
+            visibility: Visibility::Hidden,
+            ..default()
+        },
+    ));
+    for i in 0..4 {
+        cmd.spawn((
+            Name::new("ghost wheel"),
+            GhostWheel(i),
+            SceneBundle {
+                scene: wheel_scene.clone(),
+                visibility: Visibility::Hidden,
+                ..default()
+            },
+        ));
+    }
+}
+
+// best lap at the same time from the player lap start, hidden out of a timed lap
+#[allow(clippy::type_complexity)]
+pub fn ghost_replay_system(
+    time: Res<Time>,
+    ghost: Res<Ghost>,
+    q_player: Query<&LapTiming, With<Player>>,
+    mut q_car: Query<(&mut Transform, &mut Visibility), (With<GhostCar>, Without<GhostWheel>)>,
+    mut q_wheel: Query<(&GhostWheel, &mut Transform, &mut Visibility), Without<GhostCar>>,
+) {
+    let lap_time = q_player
+        .get_single()
+        .ok()
+        .and_then(|timing| timing.current(time.elapsed_seconds_f64()));
+    let transforms = ghost
+        .best
+        .as_ref()
+        .zip(lap_time)
+        .and_then(|(best, lap_time)| best.transforms_at(lap_time));
+    let visibility = match transforms {
+        Some(_) => Visibility::Visible,
+        None => Visibility::Hidden,
+    };
+    for (mut transform, mut v) in q_car.iter_mut() {
+        *v = visibility;
+        if let Some((body, _)) = transforms {
+            *transform = body;
+        }
+    }
+    for (wheel, mut transform, mut v) in q_wheel.iter_mut() {
+        *v = visibility;
+        if let Some((_, wheels)) = transforms {
+            *transform = wheels[wheel.0];
+        }
+    }
+}
+
+// scene materials are shared with the cars, ghost meshes get translucent copies
+#[allow(clippy::type_complexity)]
+pub fn ghost_material_system(
+    mut q_material: Query<(Entity, &mut Handle<StandardMaterial>), Added<Handle<StandardMaterial>>>,
+    q_parent: Query<&Parent>,
+    q_ghost: Query<(), Or<(With<GhostCar>, With<GhostWheel>)>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    mut translucent: Local<HashMap<AssetId<StandardMaterial>, Handle<StandardMaterial>>>,
+) {
+    for (e, mut handle) in q_material.iter_mut() {
+        if !q_parent.iter_ancestors(e).any(|a| q_ghost.contains(a)) {
+            continue;
+        }
+        let id = handle.id();
+        if let Some(ghost_handle) = translucent.get(&id) {
+            *handle = ghost_handle.clone();
+            continue;
+        }
+        let Some(mut material) = materials.get(id).cloned() else {
+            continue;
+        };
+        material.base_color.set_alpha(GHOST_ALPHA);
+        material.alpha_mode = AlphaMode::Blend;
+        let ghost_handle = materials.add(material);
+        translucent.insert(id, ghost_handle.clone());
+        *handle = ghost_handle;
+    }
+}
+
+pub struct GhostPlugin;
+
+impl Plugin for GhostPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(Ghost::from_env()).add_systems(
+            Update,
+            (
+                ghost_record_system.after(crate::timing_system),
+                ghost_spawn_system,
+                ghost_replay_system.after(crate::timing_system),
+                ghost_material_system,
+            ),
+        );
+    }
+}
@@ -0,0 +1,294 @@
+use crate::{
+    CarTrack, LapTiming, RaceSession, RaceState, SpawnCarOnTrackEvent, Track, TrackConfig,
+    AUTOPILOT_FIRST_INDEX,
+};
+use bevy::prelude::*;
+use bevy_garage_car::CarWheels;
+use bevy_rapier3d::prelude::*;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PoleSide {
+    Left,
+    Right,
+}
+
+#[derive(Debug, Clone)]
+pub struct GridSlot {
+    // 1 based grid position
+    pub position: usize,
+    // CarTrack index
+    pub index: usize,
+    // track position
+    pub meters: f32,
+    pub transform: Transform,
+    pub velocity: Vec3,
+}
+
+// two staggered columns behind the start line, front to back in order
+#[derive(Resource, Debug, Clone)]
+pub struct StartingGrid {
+    // CarTrack indices, pole first
+    pub order: Vec<usize>,
+    pub pole_side: PoleSide,
+    // pole distance to the start line, meters
+    pub first_row: f32,
+    pub row_spacing: f32,
+    // second column is this much further back
+    pub stagger: f32,
+    // column distance from the center line
+    pub lateral: f32,
+    // m/s, None for a standing start
+    pub rolling_speed: Option<f32>,
+    // order is taken from warmup best laps when the grid forms
+    pub qualifying: bool,
+}
+
+impl StartingGrid {
+    pub fn new(order: Vec<usize>) -> Self {
+        Self {
+            order,
+            pole_side: PoleSide::Left,
+            first_row: 6.,
+            row_spacing: 16.,
+            stagger: 8.,
+            lateral: 2.5,
+            rolling_speed: None,
+            qualifying: false,
+        }
+    }
+    // player and autopilot opponents, GRID_ORDER=1000,0,1001 to reorder, GRID_QUALIFYING=1
+    // orders by warmup best laps, RACE_ROLLING_SPEED=km/h for a rolling start
+    pub fn from_env(opponents: usize) -> Self {
+        let order = match std::env::var("GRID_ORDER") {
+            Ok(order) => order
+                .split(',')
+                .filter_map(|i| i.trim().parse::<usize>().ok())
+                .collect(),
+            Err(_) => std::iter::once(0)
+                .chain((0..opponents).map(|i| AUTOPILOT_FIRST_INDEX + i))
+                .collect(),
+        };
+        let mut grid = Self::new(order);
+        grid.qualifying = std::env::var("GRID_QUALIFYING").is_ok_and(|v| v == "1");
+        if let Some(kmph) = std::env::var("RACE_ROLLING_SPEED")
+            .ok()
+            .and_then(|v| v.parse::<f32>().ok())
+        {
+            grid = grid.rolling(kmph / 3.6);
+        }
+        grid
+    }
+    pub fn rolling(mut self, speed: f32) -> Self {
+        self.rolling_speed = Some(speed);
+        self
+    }
+    // best lap first, cars without a time keep their order at the back
+    pub fn with_qualifying(mut self, best_laps: &[(usize, Option<f32>)]) -> Self {
+        let best_lap = |index: usize| {
+            best_laps
+                .iter()
+                .find(|(i, _)| *i == index)
+                .and_then(|(_, lap)| *lap)
+        };
+        // stable, untimed cars keep their order
+        self.order
+            .sort_by(|a, b| match (best_lap(*a), best_lap(*b)) {
+                (Some(a), Some(b)) => a.total_cmp(&b),
+                (Some(_), None) => std::cmp::Ordering::Less,
+                (None, Some(_)) => std::cmp::Ordering::Greater,
+                (None, None) => std::cmp::Ordering::Equal,
+            });
+        self
+    }
+    pub fn slots(&self, track_config: &TrackConfig, track: &Track) -> Vec<GridSlot> {
+        let length = track_config.track_length;
+        self.order
+            .iter()
+            .enumerate()
+            .map(|(i, index)| {
+                let (row, column) = (i / 2, i % 2);
+                let behind =
+                    self.first_row + row as f32 * self.row_spacing + column as f32 * self.stagger;
+                let meters = (length - behind).rem_euclid(length);
+                let (translation, rotation) = track_config.get_transform_by_meter(meters);
+                let side = match (self.pole_side, column) {
+                    (PoleSide::Left, 0) | (PoleSide::Right, 1) => 1.,
+                    _ => -1.,
+                };
+                let left = left_norm_at(track_config, track, meters);
+                let translation = translation + left * side * self.lateral;
+                let forward = rotation.mul_vec3(Vec3::Z);
+                GridSlot {
+                    position: i + 1,
+                    index: *index,
+                    meters,
+                    transform: Transform::from_translation(translation).with_rotation(rotation),
+                    velocity: forward * self.rolling_speed.unwrap_or(0.),
+                }
+            })
+            .collect()
+    }
+}
+
+// Track normal interpolated along the polyline segment
+pub fn left_norm_at(track_config: &TrackConfig, track: &Track, meters: f32) -> Vec3 {
+    let shift = (meters + track_config.start_shift).rem_euclid(track_config.track_length);
+    let segment = track_config
+        .segments
+        .partition_point(|s| *s <= shift)
+        .saturating_sub(1);
+    let next = (segment + 1).min(track.left_norm.len() - 1);
+    let start = track_config.segments[segment];
+    let end = track_config
+        .segments
+        .get(segment + 1)
+        .copied()
+        .unwrap_or(track_config.track_length);
+    let t = (shift - start) / (end - start).max(f32::EPSILON);
+    let mut norm = track.left_norm[segment].lerp(track.left_norm[next], t);
+    norm.y = 0.;
+    norm.normalize_or_zero()
+}
+
+// applied once to a spawned car and its wheels, rolling start
+#[derive(Component, Debug)]
+pub struct InitialVelocity(pub Vec3);
+
+pub fn initial_velocity_system(
+    q_car: Query<(Entity, &InitialVelocity, &CarWheels)>,
+    mut q_velocity: Query<&mut Velocity>,
+    mut cmd: Commands,
+) {
+    for (e, initial, wheels) in q_car.iter() {
+        for body in std::iter::once(e).chain(wheels.entities) {
+            if let Ok(mut v) = q_velocity.get_mut(body) {
+                v.linvel = initial.0;
+            }
+        }
+        cmd.entity(e).remove::<InitialVelocity>();
+    }
+}
+
+fn send_grid(
+    grid: &StartingGrid,
+    track_config: &TrackConfig,
+    track: &Track,
+    car_spawn_events: &mut EventWriter<SpawnCarOnTrackEvent>,
+) {
+    for slot in grid.slots(track_config, track) {
+        car_spawn_events.send(SpawnCarOnTrackEvent {
+            player: slot.index == 0,
+            index: slot.index,
+            position: Some(slot.meters),
+            slot: Some(slot),
+        });
+    }
+}
+
+pub fn grid_start_system(
+    grid: Res<StartingGrid>,
+    track_config: Res<TrackConfig>,
+    track: Res<Track>,
+    mut car_spawn_events: EventWriter<SpawnCarOnTrackEvent>,
+) {
+    send_grid(&grid, &track_config, &track, &mut car_spawn_events);
+}
+
+// cars drive in warmup and after the finish, they are respawned on the grid when it forms again
+#[allow(clippy::too_many_arguments)]
+pub fn grid_form_system(
+    session: Res<RaceSession>,
+    mut prev_state: Local<Option<RaceState>>,
+    mut grid: ResMut<StartingGrid>,
+    track_config: Res<TrackConfig>,
+    track: Res<Track>,
+    mut q_car: Query<(Entity, &CarTrack, &mut CarWheels, Option<&LapTiming>)>,
+    mut cmd: Commands,
+    mut car_spawn_events: EventWriter<SpawnCarOnTrackEvent>,
+) {
+    let prev = prev_state.replace(session.state);
+    if session.state != RaceState::Grid || prev == Some(RaceState::Grid) {
+        return;
+    }
+    let from_startup = prev.is_none() || prev == Some(RaceState::Warmup) && session.warmup <= 0.;
+    if from_startup {
+        return;
+    }
+    if grid.qualifying {
+        let best_laps: Vec<(usize, Option<f32>)> = q_car
+            .iter()
+            .map(|(_, car_track, _, timing)| (car_track.index, timing.and_then(|t| t.best_lap)))
+            .collect();
+        *grid = grid.clone().with_qualifying(&best_laps);
+        println!("grid from qualifying {:?}", grid.order);
+    }
+    for (e, car_track, mut wheels, _) in q_car.iter_mut() {
+        if grid.order.contains(&car_track.index) {
+            cmd.entity(e).despawn_recursive();
+            wheels.despawn(&mut cmd);
+        }
+    }
+    send_grid(&grid, &track_config, &track, &mut car_spawn_events);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy::ecs::system::RunSystemOnce;
+
+    fn square_track() -> (TrackConfig, Track) {
+        let mut world = World::new();
+        world.insert_resource(TrackConfig {
+            track_index: 1,
+            ..default()
+        });
+        world.run_system_once(crate::track_polyline_start_system);
+        let track_config = world.remove_resource::<TrackConfig>().unwrap();
+        let track = Track::new(track_config.positions());
+        (track_config, track)
+    }
+
+    #[test]
+    fn staggered_columns() {
+        let (track_config, track) = square_track();
+        let length = track_config.track_length;
+        let grid = StartingGrid::new(vec![1000, 0, 1001]);
+        let slots = grid.slots(&track_config, &track);
+        let order: Vec<(usize, usize)> = slots.iter().map(|s| (s.position, s.index)).collect();
+        assert_eq!(order, vec![(1, 1000), (2, 0), (3, 1001)]);
+        let behind: Vec<f32> = slots.iter().map(|s| length - s.meters).collect();
+        for (behind, expected) in behind.iter().zip([6., 14., 22.]) {
+            assert!((behind - expected).abs() < 1e-3, "{behind} != {expected}");
+        }
+        for (i, slot) in slots.iter().enumerate() {
+            let (center, rotation) = track_config.get_transform_by_meter(slot.meters);
+            let left = left_norm_at(&track_config, &track, slot.meters);
+            let side = (slot.transform.translation - center).dot(left);
+            // pole on the left, columns alternate
+            let expected = if i % 2 == 0 {
+                grid.lateral
+            } else {
+                -grid.lateral
+            };
+            assert!((side - expected).abs() < 1e-3, "slot {i} side {side}");
+            assert_eq!(slot.transform.rotation, rotation);
+            assert_eq!(slot.velocity, Vec3::ZERO);
+        }
+    }
+
+    #[test]
+    fn pole_side_and_rolling() {
+        let (track_config, track) = square_track();
+        let mut grid = StartingGrid::new(vec![0, 1]).rolling(20.);
+        grid.pole_side = PoleSide::Right;
+        grid.first_row = 0.;
+        let slots = grid.slots(&track_config, &track);
+        // on the line, not a track length away
+        assert!(slots[0].meters.abs() < 1e-3);
+        let left = left_norm_at(&track_config, &track, slots[0].meters);
+        let (center, _) = track_config.get_transform_by_meter(slots[0].meters);
+        assert!((slots[0].transform.translation - center).dot(left) < 0.);
+        let forward = slots[0].transform.rotation.mul_vec3(Vec3::Z);
+        assert!(slots[0].velocity.distance(forward * 20.) < 1e-4);
+    }
+}
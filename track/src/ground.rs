@@ -1,7 +1,7 @@
 use super::{GroundPbr, MaterialHandle};
 use crate::mesh::QuadPlane;
 use bevy::{pbr::NotShadowCaster, prelude::*, render::primitives::Aabb};
-use bevy_garage_car::STATIC_GROUP;
+use bevy_garage_car::{SurfaceKind, STATIC_GROUP};
 use bevy_rapier3d::prelude::*;
 
 #[derive(Component, Debug)]
@@ -50,6 +50,7 @@ pub fn spawn_ground_heightfield(
 
     cmd.spawn((
         Name::new("ground-heightfield"),
+        SurfaceKind::Ground,
         RigidBody::Fixed,
         ColliderScale::Absolute(Vec3::ONE),
         CollisionGroups::new(STATIC_GROUP, Group::ALL),
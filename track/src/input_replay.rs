@@ -0,0 +1,562 @@
+use crate::{
+    pit_limiter_system, race_freeze_system, replay::ReplayReader, replay::ReplayWriter,
+    spawn_car_on_track, CarTrack, InitialVelocity, ReplayPlayback, TrackConfig,
+};
+use bevy::{
+    app::AppExit, ecs::schedule::SystemConfigs, prelude::*, time::TimeUpdateStrategy,
+    utils::HashMap,
+};
+use bevy_garage_car::{sim_rng_seed, Car, CarRes, CarSet, CarWheels, Player};
+use bevy_rapier3d::prelude::*;
+use rand::Rng;
+use std::{fs, path::PathBuf, time::Duration};
+
+const INPUT_MAGIC: &[u8; 4] = b"BGIN";
+const INPUT_VERSION: u8 = 2;
+
+// session settings read at startup, a replay runs with the recorded values
+pub const INPUT_SESSION_ENV: [&str; 15] = [
+    "RACE_LAPS",
+    "RACE_TIME",
+    "RACE_WARMUP",
+    "RACE_ROLLING_SPEED",
+    "GRID_ORDER",
+    "GRID_QUALIFYING",
+    "AUTOPILOT_OPPONENTS",
+    "PIT_ENABLED",
+    "PIT_SPEED_LIMIT",
+    "PIT_REFUEL_RATE",
+    "PIT_TIRE_CHANGE",
+    "PIT_REPAIR",
+    "TRACK_LIMITS_TOLERANCE",
+    "TRACK_LIMITS_WARNINGS",
+    "TRACK_LIMITS_PENALTY",
+];
+
+// car added or removed at the tick, before its first physics step
+#[derive(Debug, Clone, Copy)]
+pub struct InputSpawn {
+    pub tick: u32,
+    pub index: usize,
+    pub player: bool,
+    // None for a despawn
+    pub transform: Option<Transform>,
+    pub start_shift: f32,
+    pub velocity: Vec3,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct InputCar {
+    pub index: usize,
+    pub gas: f32,
+    pub brake: f32,
+    pub steering: f32,
+    // body before the physics step of the tick
+    pub body: Transform,
+}
+
+// initial state, seed and per tick inputs of a deterministic session
+#[derive(Debug, Clone, Default)]
+pub struct InputRecording {
+    pub dt: f32,
+    pub substeps: usize,
+    pub seed: u64,
+    // TrackConfig.track_index
+    pub track_index: usize,
+    // INPUT_SESSION_ENV names and values set in the recording session
+    pub env: Vec<(String, String)>,
+    pub spawns: Vec<InputSpawn>,
+    pub ticks: Vec<Vec<InputCar>>,
+}
+
+impl InputRecording {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut w = ReplayWriter::default();
+        w.0.extend(INPUT_MAGIC);
+        w.u8(INPUT_VERSION);
+        w.f32(self.dt);
+        w.u32(self.substeps as u32);
+        w.u64(self.seed);
+        w.u32(self.track_index as u32);
+        w.u32(self.env.len() as u32);
+        for (name, value) in self.env.iter() {
+            w.str(name);
+            w.str(value);
+        }
+        w.u32(self.spawns.len() as u32);
+        for spawn in self.spawns.iter() {
+            w.u32(spawn.tick);
+            w.u32(spawn.index as u32);
+            w.u8(spawn.player as u8);
+            w.u8(spawn.transform.is_some() as u8);
+            w.transform(&spawn.transform.unwrap_or_default());
+            w.f32(spawn.start_shift);
+            for v in spawn.velocity.to_array() {
+                w.f32(v);
+            }
+        }
+        w.u32(self.ticks.len() as u32);
+        for cars in self.ticks.iter() {
+            w.u32(cars.len() as u32);
+            for car in cars.iter() {
+                w.u32(car.index as u32);
+                w.f32(car.gas);
+                w.f32(car.brake);
+                w.f32(car.steering);
+                w.transform(&car.body);
+            }
+        }
+        w.0
+    }
+
+    pub fn decode(bytes: &[u8]) -> Option<Self> {
+        let mut r = ReplayReader { bytes, at: 0 };
+        if r.take::<4>()? != *INPUT_MAGIC || r.u8()? != INPUT_VERSION {
+            return None;
+        }
+        let (dt, substeps, seed) = (r.f32()?, r.u32()? as usize, r.u64()?);
+        let track_index = r.u32()? as usize;
+        let env = (0..r.u32()?)
+            .map(|_| Some((r.str()?, r.str()?)))
+            .collect::<Option<Vec<_>>>()?;
+        let spawns = (0..r.u32()?)
+            .map(|_| {
+                let (tick, index, player) = (r.u32()?, r.u32()? as usize, r.u8()? != 0);
+                let spawned = r.u8()? != 0;
+                let transform = r.transform()?;
+                Some(InputSpawn {
+                    tick,
+                    index,
+                    player,
+                    transform: spawned.then_some(transform),
+                    start_shift: r.f32()?,
+                    velocity: Vec3::new(r.f32()?, r.f32()?, r.f32()?),
+                })
+            })
+            .collect::<Option<Vec<_>>>()?;
+        let ticks = (0..r.u32()?)
+            .map(|_| {
+                (0..r.u32()?)
+                    .map(|_| {
+                        Some(InputCar {
+                            index: r.u32()? as usize,
+                            gas: r.f32()?,
+                            brake: r.f32()?,
+                            steering: r.f32()?,
+                            body: r.transform()?,
+                        })
+                    })
+                    .collect::<Option<Vec<_>>>()
+            })
+            .collect::<Option<Vec<_>>>()?;
+        Some(Self {
+            dt,
+            substeps,
+            seed,
+            track_index,
+            env,
+            spawns,
+            ticks,
+        })
+    }
+}
+
+pub fn load_input_recording(path: &PathBuf) -> Option<InputRecording> {
+    InputRecording::decode(&fs::read(path).ok()?)
+}
+
+pub fn save_input_recording(path: &PathBuf, recording: &InputRecording) -> Result<(), String> {
+    fs::write(path, recording.encode()).map_err(|e| e.to_string())
+}
+
+// frames since startup, each one is a single fixed physics step
+#[derive(Resource, Debug, Default)]
+pub struct SimTick(pub u32);
+
+pub fn sim_tick_system(mut tick: ResMut<SimTick>) {
+    tick.0 += 1;
+}
+
+// fixed bevy and rapier timestep, the same in recording and re-simulation
+pub fn deterministic_timestep(app: &mut App, dt: f32, substeps: usize) {
+    app.insert_resource(TimeUpdateStrategy::ManualDuration(Duration::from_secs_f32(
+        dt,
+    )))
+    .init_resource::<SimTick>()
+    .add_systems(
+        Startup,
+        move |mut rapier_config: ResMut<RapierConfiguration>| {
+            rapier_config.timestep_mode = TimestepMode::Fixed { dt, substeps };
+        },
+    )
+    .add_systems(First, sim_tick_system);
+}
+
+// INPUT_RECORD=path records inputs in a deterministic timestep, saved on exit,
+// INPUT_SEED=u64 for random spawns, INPUT_DT=seconds per tick
+#[derive(Resource, Debug)]
+pub struct InputRecorder {
+    pub path: PathBuf,
+    pub recording: InputRecording,
+    indices: HashMap<Entity, (usize, bool)>,
+}
+
+impl InputRecorder {
+    pub fn from_env() -> Option<Self> {
+        let path = PathBuf::from(std::env::var("INPUT_RECORD").ok()?);
+        let dt = std::env::var("INPUT_DT")
+            .ok()
+            .and_then(|v| v.parse::<f32>().ok())
+            .unwrap_or(1. / 60.);
+        let seed = std::env::var("INPUT_SEED")
+            .ok()
+            .and_then(|v| v.parse::<u64>().ok())
+            .unwrap_or_else(|| rand::thread_rng().gen());
+        let env = INPUT_SESSION_ENV
+            .iter()
+            .filter_map(|name| Some((name.to_string(), std::env::var(name).ok()?)))
+            .collect();
+        Some(Self {
+            path,
+            recording: InputRecording {
+                dt,
+                substeps: 5,
+                seed,
+                env,
+                ..default()
+            },
+            indices: HashMap::default(),
+        })
+    }
+}
+
+// spawns and despawns seen by the physics step of this tick
+pub fn input_record_spawn_system(
+    tick: Res<SimTick>,
+    mut recorder: ResMut<InputRecorder>,
+    q_added: Query<(Entity, &Car, &CarTrack), Added<CarTrack>>,
+    q_player: Query<(), With<Player>>,
+    q_velocity: Query<&InitialVelocity>,
+    mut removed: RemovedComponents<CarTrack>,
+) {
+    let recorder = recorder.as_mut();
+    for e in removed.read() {
+        let Some((index, player)) = recorder.indices.remove(&e) else {
+            continue;
+        };
+        recorder.recording.spawns.push(InputSpawn {
+            tick: tick.0,
+            index,
+            player,
+            transform: None,
+            start_shift: 0.,
+            velocity: Vec3::ZERO,
+        });
+    }
+    for (e, car, car_track) in q_added.iter() {
+        let player = q_player.contains(e);
+        let velocity = q_velocity.get(e).ok();
+        recorder.indices.insert(e, (car_track.index, player));
+        recorder.recording.spawns.push(InputSpawn {
+            tick: tick.0,
+            index: car_track.index,
+            player,
+            transform: Some(car.spawn_transform),
+            start_shift: car_track.start_shift,
+            velocity: velocity.map_or(Vec3::ZERO, |v| v.0),
+        });
+    }
+}
+
+// final inputs after every controller and limiter, before they become forces
+pub fn input_record_system(
+    tick: Res<SimTick>,
+    mut recorder: ResMut<InputRecorder>,
+    q_car: Query<(&Car, &CarTrack, &Transform)>,
+) {
+    let mut cars: Vec<InputCar> = q_car
+        .iter()
+        .map(|(car, car_track, transform)| InputCar {
+            index: car_track.index,
+            gas: car.gas,
+            brake: car.brake,
+            steering: car.steering,
+            body: *transform,
+        })
+        .collect();
+    cars.sort_by_key(|car| car.index);
+    let ticks = &mut recorder.recording.ticks;
+    ticks.resize(tick.0 as usize, vec![]);
+    ticks.push(cars);
+}
+
+pub fn input_record_save_system(
+    track_config: Res<TrackConfig>,
+    mut recorder: ResMut<InputRecorder>,
+    mut exit_events: EventReader<AppExit>,
+) {
+    if exit_events.read().count() == 0 {
+        return;
+    }
+    recorder.recording.track_index = track_config.track_index;
+    match save_input_recording(&recorder.path, &recorder.recording) {
+        Ok(_) => println!(
+            "input recording saved {:?}, {} ticks seed {}",
+            recorder.path,
+            recorder.recording.ticks.len(),
+            recorder.recording.seed
+        ),
+        Err(err) => println!("input recording save error {:?}: {err}", recorder.path),
+    }
+}
+
+// INPUT_REPLAY=path re-simulates the recording, INPUT_REPLAY_TOLERANCE=meters,
+// exits with an error at the first diverged tick
+#[derive(Resource, Debug)]
+pub struct InputReplay {
+    pub recording: InputRecording,
+    pub tolerance: f32,
+    // first tick, car index and position error over the tolerance
+    pub diverged: Option<(u32, usize, f32)>,
+}
+
+impl InputReplay {
+    pub fn from_env() -> Option<Self> {
+        let path = PathBuf::from(std::env::var("INPUT_REPLAY").ok()?);
+        let Some(recording) = load_input_recording(&path) else {
+            println!("input recording load error {path:?}");
+            return None;
+        };
+        let tolerance = std::env::var("INPUT_REPLAY_TOLERANCE")
+            .ok()
+            .and_then(|v| v.parse::<f32>().ok())
+            .unwrap_or(0.001);
+        println!(
+            "input recording loaded {path:?}, {} ticks seed {}",
+            recording.ticks.len(),
+            recording.seed
+        );
+        Some(Self {
+            recording,
+            tolerance,
+            diverged: None,
+        })
+    }
+}
+
+pub fn input_replay_spawn_system(
+    tick: Res<SimTick>,
+    replay: Res<InputReplay>,
+    car_res: Res<CarRes>,
+    mut q_car: Query<(Entity, &CarTrack, &mut CarWheels)>,
+    mut cmd: Commands,
+) {
+    let spawns = replay.recording.spawns.iter().filter(|s| s.tick == tick.0);
+    for spawn in spawns {
+        let Some(transform) = spawn.transform else {
+            for (e, _, mut wheels) in q_car.iter_mut().filter(|(_, t, _)| t.index == spawn.index) {
+                cmd.entity(e).despawn_recursive();
+                wheels.despawn(&mut cmd);
+            }
+            continue;
+        };
+        let (Some(car_scene), Some(wheel_scene)) = (&car_res.car_scene, &car_res.wheel_scene)
+        else {
+            continue;
+        };
+        let e = spawn_car_on_track(
+            &mut cmd,
+            car_scene,
+            wheel_scene,
+            spawn.player,
+            transform,
+            spawn.index,
+            spawn.start_shift,
+        );
+        if spawn.velocity != Vec3::ZERO {
+            cmd.entity(e).insert(InitialVelocity(spawn.velocity));
+        }
+    }
+}
+
+// recorded inputs replace the controllers, bodies are compared before the physics step
+pub fn input_replay_system(
+    tick: Res<SimTick>,
+    mut replay: ResMut<InputReplay>,
+    mut q_car: Query<(&mut Car, &CarTrack, &Transform)>,
+    mut exit_events: EventWriter<AppExit>,
+) {
+    let Some(cars) = replay.recording.ticks.get(tick.0 as usize) else {
+        match replay.diverged {
+            Some((tick, index, error)) => {
+                println!("re-simulation diverged at tick {tick}, car {index} off by {error:.4}m");
+                exit_events.send(AppExit::error());
+            }
+            None => {
+                println!(
+                    "re-simulation matched {} ticks",
+                    replay.recording.ticks.len()
+                );
+                exit_events.send(AppExit::Success);
+            }
+        }
+        return;
+    };
+    let mut diverged: Option<(u32, usize, f32)> = None;
+    for (mut car, car_track, transform) in q_car.iter_mut() {
+        let Some(recorded) = cars.iter().find(|c| c.index == car_track.index) else {
+            continue;
+        };
+        car.gas = recorded.gas;
+        car.brake = recorded.brake;
+        car.steering = recorded.steering;
+        let error = transform.translation.distance(recorded.body.translation);
+        if error > replay.tolerance && diverged.is_none_or(|(_, _, max)| error > max) {
+            diverged = Some((tick.0, car_track.index, error));
+        }
+    }
+    if replay.diverged.is_none() {
+        if let Some((tick, index, error)) = diverged {
+            println!("re-simulation diverged at tick {tick}, car {index} off by {error:.4}m");
+            replay.diverged = diverged;
+        }
+    }
+}
+
+// run condition of the usual spawns, cars come from a recording in replays
+pub fn live_spawns(
+    playback: Option<Res<ReplayPlayback>>,
+    input_replay: Option<Res<InputReplay>>,
+) -> bool {
+    playback.is_none() && input_replay.is_none()
+}
+
+pub struct InputReplayPlugin;
+
+impl Plugin for InputReplayPlugin {
+    fn build(&self, app: &mut App) {
+        let apply_inputs = |system: SystemConfigs| {
+            system
+                .after(CarSet::Input)
+                .after(CarSet::NeuralNetwork)
+                .after(race_freeze_system)
+                .after(pit_limiter_system)
+                .before(CarSet::Esp)
+        };
+        if let Some(replay) = InputReplay::from_env() {
+            let recording = &replay.recording;
+            // before the other track plugins read the session env
+            for name in INPUT_SESSION_ENV {
+                std::env::remove_var(name);
+            }
+            for (name, value) in recording.env.iter() {
+                std::env::set_var(name, value);
+            }
+            let track_index = recording.track_index;
+            deterministic_timestep(app, recording.dt, recording.substeps);
+            app.add_systems(PreStartup, move |mut track_config: ResMut<TrackConfig>| {
+                track_config.track_index = track_index;
+            })
+            .insert_resource(sim_rng_seed(recording.seed))
+            .insert_resource(replay)
+            .add_systems(
+                Update,
+                (
+                    input_replay_spawn_system,
+                    apply_inputs(input_replay_system.into_configs()),
+                ),
+            );
+        } else if let Some(recorder) = InputRecorder::from_env() {
+            let recording = &recorder.recording;
+            deterministic_timestep(app, recording.dt, recording.substeps);
+            app.insert_resource(sim_rng_seed(recording.seed))
+                .insert_resource(recorder)
+                .add_systems(Update, apply_inputs(input_record_system.into_configs()))
+                .add_systems(
+                    PostUpdate,
+                    input_record_spawn_system.before(PhysicsSet::SyncBackend),
+                )
+                .add_systems(Last, input_record_save_system);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn recording() -> InputRecording {
+        let body = Transform::from_xyz(1., 0.5, -3.).with_rotation(Quat::from_rotation_y(0.7));
+        InputRecording {
+            dt: 1. / 60.,
+            substeps: 5,
+            seed: u64::MAX - 1,
+            track_index: 1,
+            env: vec![
+                ("RACE_LAPS".to_string(), "3".to_string()),
+                ("GRID_ORDER".to_string(), "0,2,1".to_string()),
+            ],
+            spawns: vec![
+                InputSpawn {
+                    tick: 0,
+                    index: 0,
+                    player: true,
+                    transform: Some(body),
+                    start_shift: 12.5,
+                    velocity: Vec3::new(0., 0., 20.),
+                },
+                InputSpawn {
+                    tick: 90,
+                    index: 0,
+                    player: true,
+                    transform: None,
+                    start_shift: 0.,
+                    velocity: Vec3::ZERO,
+                },
+            ],
+            ticks: vec![
+                vec![],
+                vec![InputCar {
+                    index: 0,
+                    gas: 1.,
+                    brake: 0.,
+                    steering: -0.5,
+                    body,
+                }],
+            ],
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let recording = recording();
+        let decoded = InputRecording::decode(&recording.encode()).unwrap();
+        assert_eq!((decoded.dt, decoded.substeps), (recording.dt, 5));
+        assert_eq!(decoded.seed, recording.seed);
+        assert_eq!(decoded.track_index, 1);
+        assert_eq!(decoded.env, recording.env);
+        assert_eq!(decoded.spawns.len(), 2);
+        let (spawn, despawn) = (&decoded.spawns[0], &decoded.spawns[1]);
+        assert_eq!((spawn.tick, spawn.index, spawn.player), (0, 0, true));
+        assert_eq!(spawn.transform, recording.spawns[0].transform);
+        assert_eq!(
+            (spawn.start_shift, spawn.velocity),
+            (12.5, Vec3::new(0., 0., 20.))
+        );
+        assert_eq!((despawn.tick, despawn.transform), (90, None));
+        assert_eq!(decoded.ticks.len(), 2);
+        assert!(decoded.ticks[0].is_empty());
+        let car = &decoded.ticks[1][0];
+        assert_eq!((car.gas, car.brake, car.steering), (1., 0., -0.5));
+        assert_eq!(car.body, recording.ticks[1][0].body);
+    }
+
+    #[test]
+    fn rejects_truncated_and_foreign() {
+        let bytes = recording().encode();
+        assert!(InputRecording::decode(&bytes[..bytes.len() - 1]).is_none());
+        let mut foreign = bytes.clone();
+        foreign[4] = INPUT_VERSION - 1;
+        assert!(InputRecording::decode(&foreign).is_none());
+    }
+}
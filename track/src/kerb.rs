@@ -5,7 +5,7 @@ use bevy::{
     prelude::*,
     render::{mesh::*, render_asset::RenderAssetUsages},
 };
-use bevy_garage_car::STATIC_GROUP;
+use bevy_garage_car::{SurfaceKind, STATIC_GROUP};
 use bevy_rapier3d::{na::Point3, prelude::*, rapier::prelude::ColliderShape};
 use std::ops::Sub;
 
@@ -73,6 +73,7 @@ pub fn spawn_kerb(
             ..default()
         },
         NotShadowCaster,
+        SurfaceKind::Kerb,
         ColliderScale::Absolute(Vec3::ONE),
         CollisionGroups::new(STATIC_GROUP, Group::ALL),
         Restitution::coefficient(0.),
@@ -130,6 +131,7 @@ pub fn spawn_kerb(
             ..default()
         },
         NotShadowCaster,
+        SurfaceKind::Kerb,
         ColliderScale::Absolute(Vec3::ONE),
         CollisionGroups::new(STATIC_GROUP, Group::ALL),
         Restitution::coefficient(0.),
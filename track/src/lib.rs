@@ -1,28 +1,56 @@
 pub mod asphalt;
+pub mod autopilot;
 pub mod car_track;
+pub mod checkpoint;
 pub mod config;
 pub mod decor;
+pub mod ghost;
+pub mod grid;
 pub mod ground;
+pub mod input_replay;
 pub mod kerb;
+pub mod limits;
+pub mod line;
 pub mod material;
 pub mod mesh;
+pub mod pit;
 pub mod progress;
 pub mod quality;
+pub mod race;
+pub mod racing_line;
+pub mod replay;
 pub mod shader;
+pub mod telemetry;
+pub mod timing;
 pub mod track;
+pub mod udp;
 pub mod wall;
 
 pub use asphalt::*;
+pub use autopilot::*;
 use bevy_garage_car::CarSet;
 pub use car_track::*;
+pub use checkpoint::*;
 pub use config::*;
 pub use decor::*;
+pub use ghost::*;
+pub use grid::*;
 pub use ground::*;
+pub use input_replay::*;
+pub use limits::*;
+pub use line::*;
 pub use material::*;
+pub use pit::*;
 pub use progress::*;
 pub use quality::*;
+pub use race::*;
+pub use racing_line::*;
+pub use replay::*;
 pub use shader::*;
+pub use telemetry::*;
+pub use timing::*;
 pub use track::*;
+pub use udp::*;
 
 use bevy::prelude::*;
 
@@ -38,6 +66,16 @@ impl Plugin for TrackPlugin {
         app.insert_resource(TrackConfig::default())
             .add_plugins((
                 ShadersPlugin,
+                // first, a replay sets the recorded session env the others read
+                InputReplayPlugin,
+                CheckpointPlugin,
+                RacePlugin,
+                TimingPlugin,
+                TrackLimitsPlugin,
+                PitPlugin,
+                GhostPlugin,
+                ReplayPlugin,
+                TelemetryPlugin,
                 // MaterialPlugin::<GroundMaterial>::default(),
                 // MaterialPlugin::<AsphaltMaterial>::default(),
             ))
@@ -48,18 +86,59 @@ impl Plugin for TrackPlugin {
                     track_polyline_start_system,
                     track_start_system,
                     track_decorations_start_system.after(track_polyline_start_system),
+                    center_line_start_system.after(track_polyline_start_system),
+                    racing_line_start_system.after(track_start_system),
                 ),
             )
-            .add_systems(Update, (far_culling, progress_system.in_set(CarSet::Input)));
+            .add_systems(
+                Update,
+                (
+                    far_culling,
+                    // grid rolling starts and replayed spawns
+                    initial_velocity_system,
+                    progress_system.in_set(CarSet::Input),
+                    add_autopilot_on_spawned_car_system,
+                    autopilot_system.in_set(CarSet::Input),
+                    racing_line_task_system.before(autopilot_system),
+                    racing_line_gizmos_system,
+                ),
+            );
+        let opponents = AutopilotOpponents::from_env();
+        if let Some(session) = RaceSession::from_env() {
+            let count = opponents.as_ref().map_or(0, |opponents| opponents.count);
+            app.insert_resource(session)
+                .insert_resource(StartingGrid::from_env(count))
+                .add_systems(
+                    Startup,
+                    grid_start_system
+                        .after(track_polyline_start_system)
+                        .after(track_start_system)
+                        .run_if(live_spawns),
+                )
+                .add_systems(
+                    Update,
+                    (grid_form_system
+                        .after(race_state_system)
+                        .run_if(live_spawns),),
+                );
+        } else if let Some(opponents) = opponents {
+            app.insert_resource(opponents).add_systems(
+                Startup,
+                autopilot_opponents_start_system
+                    .after(track_polyline_start_system)
+                    .run_if(live_spawns),
+            );
+        }
     }
 }
 
 pub fn track_start_system(
+    track_config: Res<TrackConfig>,
     handled_materials: Res<MaterialHandle>,
     mut cmd: Commands,
     mut meshes: ResMut<Assets<Mesh>>,
 ) {
-    let track = Track::new();
+    let track = Track::new(track_config.positions());
     let aabb = spawn_road(&handled_materials, &mut cmd, &mut meshes, &track);
     spawn_ground_heightfield(&mut cmd, &mut meshes, &handled_materials, &aabb, 100.);
 
@@ -86,4 +165,6 @@ pub fn track_start_system(
         &right_wall_points,
         &track.right_norm,
     );
+    // shared by the grid, pit lane, racing line and track limits
+    cmd.insert_resource(track);
 }
@@ -0,0 +1,145 @@
+use crate::{CarTrack, LapTiming, PitLaneState, RaceCar, Track, TrackConfig};
+use bevy::prelude::*;
+use bevy_garage_car::{CarSet, CarWheels};
+use bevy_rapier3d::{
+    na::Point3,
+    parry::{query::PointQueryWithLocation, shape::SegmentPointLocation},
+};
+
+// TRACK_LIMITS_TOLERANCE=meters beyond the asphalt edge (1. covers kerbs),
+// TRACK_LIMITS_WARNINGS=n violations per penalty, TRACK_LIMITS_PENALTY=seconds added to race time
+#[derive(Resource, Debug, Clone)]
+pub struct TrackLimits {
+    pub tolerance: f32,
+    pub warnings_per_penalty: u32,
+    pub penalty: Option<f32>,
+}
+
+impl Default for TrackLimits {
+    fn default() -> Self {
+        Self {
+            tolerance: 1.,
+            warnings_per_penalty: 3,
+            penalty: None,
+        }
+    }
+}
+
+impl TrackLimits {
+    pub fn from_env() -> Self {
+        let env = |name: &str| std::env::var(name).ok()?.parse::<f32>().ok();
+        let default = Self::default();
+        Self {
+            tolerance: env("TRACK_LIMITS_TOLERANCE").unwrap_or(default.tolerance),
+            warnings_per_penalty: env("TRACK_LIMITS_WARNINGS")
+                .map_or(default.warnings_per_penalty, |n| n.max(1.) as u32),
+            penalty: env("TRACK_LIMITS_PENALTY"),
+        }
+    }
+}
+
+#[derive(Component, Debug, Clone, Default)]
+pub struct TrackLimitsState {
+    // all four wheels are off now
+    pub off: bool,
+    pub warnings: u32,
+    // seconds
+    pub penalties: f32,
+}
+
+#[derive(Event, Debug, Clone)]
+pub struct TrackLimitsViolation {
+    pub entity: Entity,
+    pub warnings: u32,
+    pub penalty: Option<f32>,
+}
+
+pub fn add_track_limits_state_system(q_car: Query<Entity, Added<CarTrack>>, mut cmd: Commands) {
+    for e in q_car.iter() {
+        cmd.entity(e).insert(TrackLimitsState::default());
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn track_limits_system(
+    limits: Res<TrackLimits>,
+    track: Res<Track>,
+    track_config: Res<TrackConfig>,
+    mut q_car: Query<(
+        Entity,
+        &CarWheels,
+        &mut TrackLimitsState,
+        Option<&PitLaneState>,
+    )>,
+    mut q_timing: Query<&mut LapTiming>,
+    mut q_race_car: Query<&mut RaceCar>,
+    q_wheel: Query<&Transform>,
+    mut violation_events: EventWriter<TrackLimitsViolation>,
+) {
+    let Some(polyline) = track_config.polyline.as_ref() else {
+        return;
+    };
+    for (e, wheels, mut state, pit) in q_car.iter_mut() {
+        // the pit lane is off the asphalt on purpose
+        if pit.is_some_and(|pit| pit.in_lane) {
+            state.off = false;
+            continue;
+        }
+        let on_track = |wheel: &Entity| {
+            let Ok(tr) = q_wheel.get(*wheel) else {
+                return true;
+            };
+            let point: Point3<f32> = Point3::from(tr.translation);
+            let (_, (segment, location)) =
+                polyline.project_local_point_and_get_location(&point, true);
+            let t = match location {
+                SegmentPointLocation::OnEdge(uv) => uv[1],
+                SegmentPointLocation::OnVertex(i) => i as f32,
+            };
+            track.contains(segment as usize, t, tr.translation, limits.tolerance)
+        };
+        let off = !wheels.entities.iter().any(on_track);
+        let entered = off && !state.off;
+        state.off = off;
+        if !entered {
+            continue;
+        }
+        state.warnings += 1;
+        if let Ok(mut timing) = q_timing.get_mut(e) {
+            timing.invalid = true;
+        }
+        let penalty = match limits.penalty {
+            Some(penalty) if state.warnings % limits.warnings_per_penalty == 0 => Some(penalty),
+            _ => None,
+        };
+        if let Some(penalty) = penalty {
+            state.penalties += penalty;
+            if let Ok(mut race_car) = q_race_car.get_mut(e) {
+                race_car.penalty += penalty;
+            }
+        }
+        violation_events.send(TrackLimitsViolation {
+            entity: e,
+            warnings: state.warnings,
+            penalty,
+        });
+    }
+}
+
+pub struct TrackLimitsPlugin;
+
+impl Plugin for TrackLimitsPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(TrackLimits::from_env())
+            .add_event::<TrackLimitsViolation>()
+            .add_systems(
+                Update,
+                (
+                    add_track_limits_state_system,
+                    track_limits_system
+                        .after(CarSet::Input)
+                        .before(crate::timing_system),
+                ),
+            );
+    }
+}
@@ -0,0 +1,184 @@
+use crate::TrackConfig;
+use bevy::prelude::*;
+use bevy_garage_car::CarSpec;
+
+const G: f32 = 9.81;
+
+// grip and size limits used for line and speed profile, accelerations in g
+#[derive(Debug, Clone, Copy)]
+pub struct CarLimits {
+    pub max_speed: f32,
+    pub lateral_g: f32,
+    pub accel_g: f32,
+    pub brake_g: f32,
+    pub half_width: f32,
+}
+
+impl Default for CarLimits {
+    fn default() -> Self {
+        Self::from_spec(&CarSpec::default())
+    }
+}
+
+impl CarLimits {
+    pub fn from_spec(spec: &CarSpec) -> Self {
+        Self {
+            max_speed: spec.max_speed,
+            lateral_g: 1.2,
+            accel_g: 0.5,
+            brake_g: 1.5,
+            half_width: spec.size.hw,
+        }
+    }
+}
+
+pub struct LineProjection {
+    pub meters: f32,
+    pub point: Vec3,
+    pub tangent: Vec3,
+}
+
+// closed loop line on the track with a target speed per point
+#[derive(Debug, Clone)]
+pub struct TrackLine {
+    pub points: Vec<Vec3>,
+    // distance from the first point
+    pub meters: Vec<f32>,
+    pub speeds: Vec<f32>,
+    pub length: f32,
+}
+
+impl TrackLine {
+    pub fn new(mut points: Vec<Vec3>) -> Self {
+        if points.len() > 1 && points.first() == points.last() {
+            points.pop();
+        }
+        let mut meters: Vec<f32> = Vec::with_capacity(points.len());
+        let mut length = 0.;
+        for (i, p) in points.iter().enumerate() {
+            meters.push(length);
+            length += p.distance(points[(i + 1) % points.len()]);
+        }
+        let speeds = vec![f32::MAX; points.len()];
+        Self {
+            points,
+            meters,
+            speeds,
+            length,
+        }
+    }
+    fn wrap(&self, i: isize) -> usize {
+        i.rem_euclid(self.points.len() as isize) as usize
+    }
+    // menger curvature of the point and its neighbours, 1/m
+    pub fn curvature(&self, i: usize) -> f32 {
+        let a = self.points[self.wrap(i as isize - 1)];
+        let b = self.points[i];
+        let c = self.points[self.wrap(i as isize + 1)];
+        let (ab, bc, ca) = (b - a, c - b, a - c);
+        let denominator = ab.length() * bc.length() * ca.length();
+        if denominator <= f32::EPSILON {
+            return 0.;
+        }
+        2. * ab.cross(bc).length() / denominator
+    }
+    // friction circle: lateral grip limit per point, then forward (traction) and
+    // backward (braking) passes with longitudinal grip left after the lateral part,
+    // speeds already set are kept as upper limits
+    pub fn with_speed_profile(mut self, limits: &CarLimits) -> Self {
+        let n = self.points.len();
+        let lateral = limits.lateral_g * G;
+        let curvature: Vec<f32> = (0..n).map(|i| self.curvature(i)).collect();
+        for (speed, k) in self.speeds.iter_mut().zip(curvature.iter()) {
+            *speed = speed.min(match *k > f32::EPSILON {
+                true => (lateral / k).sqrt().min(limits.max_speed),
+                false => limits.max_speed,
+            });
+        }
+        let longitudinal = |v: f32, k: f32, max: f32| {
+            let used = (v * v * k / lateral).min(1.);
+            max * G * (1. - used * used).sqrt()
+        };
+        // two laps so the limits propagate over the start
+        for j in 0..2 * n {
+            let (i, next) = (j % n, (j + 1) % n);
+            let ds = self.points[i].distance(self.points[next]);
+            let a = longitudinal(self.speeds[i], curvature[i], limits.accel_g);
+            let v = (self.speeds[i].powi(2) + 2. * a * ds).sqrt();
+            self.speeds[next] = self.speeds[next].min(v);
+        }
+        for j in (0..2 * n).rev() {
+            let (i, next) = (j % n, (j + 1) % n);
+            let ds = self.points[i].distance(self.points[next]);
+            let a = longitudinal(self.speeds[next], curvature[next], limits.brake_g);
+            let v = (self.speeds[next].powi(2) + 2. * a * ds).sqrt();
+            self.speeds[i] = self.speeds[i].min(v);
+        }
+        self
+    }
+    // seconds, driving exactly the speed profile
+    pub fn lap_time(&self) -> f32 {
+        let n = self.points.len();
+        (0..n)
+            .map(|i| {
+                let next = (i + 1) % n;
+                let v = (self.speeds[i] + self.speeds[next]) / 2.;
+                self.points[i].distance(self.points[next]) / v.max(0.1)
+            })
+            .sum()
+    }
+    fn segment_at(&self, meters: f32) -> (usize, f32) {
+        let meters = meters.rem_euclid(self.length);
+        let i = match self.meters.binary_search_by(|m| m.total_cmp(&meters)) {
+            Ok(i) => i,
+            Err(i) => i - 1,
+        };
+        (i, meters - self.meters[i])
+    }
+    pub fn point_at(&self, meters: f32) -> Vec3 {
+        let (i, shift) = self.segment_at(meters);
+        let (a, b) = (self.points[i], self.points[(i + 1) % self.points.len()]);
+        a + (b - a).normalize_or_zero() * shift
+    }
+    pub fn speed_at(&self, meters: f32) -> f32 {
+        let (i, shift) = self.segment_at(meters);
+        let next = (i + 1) % self.points.len();
+        let ds = self.points[i].distance(self.points[next]).max(f32::EPSILON);
+        self.speeds[i] + (self.speeds[next] - self.speeds[i]) * (shift / ds).min(1.)
+    }
+    // closest point on the line in xz plane
+    pub fn project(&self, pos: Vec3) -> LineProjection {
+        let flat = Vec3::new(pos.x, 0., pos.z);
+        let mut best = (f32::MAX, 0, 0.);
+        for i in 0..self.points.len() {
+            let a = self.points[i] * Vec3::new(1., 0., 1.);
+            let b = self.points[(i + 1) % self.points.len()] * Vec3::new(1., 0., 1.);
+            let ab = b - a;
+            let t = ((flat - a).dot(ab) / ab.length_squared().max(f32::EPSILON)).clamp(0., 1.);
+            let d = flat.distance_squared(a + ab * t);
+            if d < best.0 {
+                best = (d, i, t);
+            }
+        }
+        let (_, i, t) = best;
+        let (a, b) = (self.points[i], self.points[(i + 1) % self.points.len()]);
+        LineProjection {
+            meters: self.meters[i] + a.distance(b) * t,
+            point: a + (b - a) * t,
+            tangent: (b - a).normalize_or_zero(),
+        }
+    }
+}
+
+// track polyline with default speed profile
+#[derive(Resource, Debug)]
+pub struct CenterLine(pub TrackLine);
+
+pub fn center_line_start_system(mut cmd: Commands, track_config: Res<TrackConfig>) {
+    let Some(polyline) = track_config.polyline.as_ref() else {
+        return;
+    };
+    let points: Vec<Vec3> = polyline.vertices().iter().map(|p| Vec3::from(*p)).collect();
+    let line = TrackLine::new(points).with_speed_profile(&CarLimits::default());
+    cmd.insert_resource(CenterLine(line));
+}
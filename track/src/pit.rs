@@ -0,0 +1,493 @@
+use crate::{
+    left_norm_at, Autopilot, CarLimits, CarTrack, LapTiming, Track, TrackConfig, TrackLine,
+};
+use bevy::prelude::*;
+use bevy_garage_car::{Car, CarRes, CarSet};
+use bevy_rapier3d::prelude::*;
+
+// meters between pit lane polyline points
+const PIT_LANE_STEP: f32 = 2.;
+// meters to move between the track center and the lane offset at entry and exit
+const PIT_LANE_RAMP: f32 = 40.;
+// the car is stopped in the box below this speed, m/s
+const PIT_BOX_SPEED: f32 = 2.;
+// m/s2 above braking, speed loss in m/s that wrecks the car
+const IMPACT_DECELERATION: f32 = 30.;
+const IMPACT_FULL_DAMAGE: f32 = 60.;
+
+// secondary polyline next to the main straight, entry, exit and box are track positions
+#[derive(Debug, Clone)]
+pub struct PitLane {
+    pub points: Vec<Vec3>,
+    pub entry_meters: f32,
+    pub exit_meters: f32,
+    pub box_meters: f32,
+    pub box_position: Vec3,
+    // lane center distance to the right of the track center
+    pub offset: f32,
+    pub width: f32,
+}
+
+impl PitLane {
+    pub fn new(
+        track_config: &TrackConfig,
+        track: &Track,
+        entry_meters: f32,
+        exit_meters: f32,
+        box_meters: f32,
+        offset: f32,
+        width: f32,
+    ) -> Self {
+        let length = track_config.track_length;
+        let lane_length = (exit_meters - entry_meters).rem_euclid(length);
+        let at = |meters: f32, offset: f32| {
+            let (mut pos, _) = track_config.get_transform_by_meter(meters);
+            pos.y -= 0.47;
+            pos - left_norm_at(track_config, track, meters) * offset
+        };
+        let steps = (lane_length / PIT_LANE_STEP).ceil() as usize;
+        let points: Vec<Vec3> = (0..=steps)
+            .map(|i| {
+                let along = lane_length * i as f32 / steps as f32;
+                // leaves and joins the track center over the ramp
+                let ramp = (along.min(lane_length - along) / PIT_LANE_RAMP).min(1.);
+                at(entry_meters + along, offset * ramp)
+            })
+            .collect();
+        Self {
+            points,
+            entry_meters,
+            exit_meters,
+            box_meters,
+            box_position: at(box_meters, offset),
+            offset,
+            width,
+        }
+    }
+    // track position is between entry and exit
+    pub fn contains_meters(&self, meters: f32, track_length: f32) -> bool {
+        let lane_length = (self.exit_meters - self.entry_meters).rem_euclid(track_length);
+        (meters - self.entry_meters).rem_euclid(track_length) <= lane_length
+    }
+    // closed line for autopilot, main track up to the entry, the lane and back to the start
+    pub fn line(
+        &self,
+        track_config: &TrackConfig,
+        limits: &CarLimits,
+        speed_limit: f32,
+    ) -> TrackLine {
+        let length = track_config.track_length;
+        let main_length = (self.entry_meters - self.exit_meters).rem_euclid(length);
+        let steps = (main_length / 4.).ceil() as usize;
+        let mut points: Vec<Vec3> = (1..steps)
+            .map(|i| {
+                let meters = self.exit_meters + main_length * i as f32 / steps as f32;
+                let (mut pos, _) = track_config.get_transform_by_meter(meters);
+                pos.y -= 0.47;
+                pos
+            })
+            .collect();
+        let main_points = points.len();
+        points.extend(self.points.iter());
+        let mut line = TrackLine::new(points);
+        let box_point = self.box_position;
+        for (i, speed) in line.speeds.iter_mut().enumerate().skip(main_points) {
+            *speed = match line.points[i].distance(box_point) < PIT_LANE_STEP {
+                true => PIT_BOX_SPEED / 2.,
+                false => speed_limit * 0.95,
+            };
+        }
+        line.with_speed_profile(limits)
+    }
+}
+
+// PIT_ENABLED=1, PIT_SPEED_LIMIT=km/h, PIT_REFUEL_RATE=liters per second,
+// PIT_TIRE_CHANGE=seconds, PIT_REPAIR=seconds to repair full damage
+#[derive(Resource, Debug, Clone)]
+pub struct PitConfig {
+    // cars get fuel, wear and damage only in pit sessions
+    pub enabled: bool,
+    // m/s
+    pub speed_limit: f32,
+    // liters per second
+    pub refuel_rate: f32,
+    // seconds
+    pub tire_change: f32,
+    // seconds for full damage, scaled by damage
+    pub repair: f32,
+    // meters from the box position to start the stop
+    pub box_radius: f32,
+}
+
+impl Default for PitConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            speed_limit: 60. / 3.6,
+            refuel_rate: 4.,
+            tire_change: 6.,
+            repair: 20.,
+            box_radius: 3.,
+        }
+    }
+}
+
+impl PitConfig {
+    pub fn from_env() -> Self {
+        let env = |name: &str| std::env::var(name).ok()?.parse::<f32>().ok();
+        let default = Self::default();
+        Self {
+            enabled: std::env::var("PIT_ENABLED").is_ok_and(|v| v == "1"),
+            speed_limit: env("PIT_SPEED_LIMIT").map_or(default.speed_limit, |kmph| kmph / 3.6),
+            refuel_rate: env("PIT_REFUEL_RATE").unwrap_or(default.refuel_rate),
+            tire_change: env("PIT_TIRE_CHANGE").unwrap_or(default.tire_change),
+            repair: env("PIT_REPAIR").unwrap_or(default.repair),
+            ..default
+        }
+    }
+}
+
+// fuel in liters, tire wear and damage from 0 (new) to 1
+#[derive(Component, Debug, Clone)]
+pub struct CarCondition {
+    pub fuel: f32,
+    pub fuel_capacity: f32,
+    // liters per second at full gas
+    pub fuel_rate: f32,
+    pub tire_wear: f32,
+    // meters to wear tires out
+    pub tire_life: f32,
+    pub damage: f32,
+    prev_speed: f32,
+}
+
+impl Default for CarCondition {
+    fn default() -> Self {
+        Self {
+            fuel: 60.,
+            fuel_capacity: 60.,
+            fuel_rate: 0.08,
+            tire_wear: 0.,
+            tire_life: 50_000.,
+            damage: 0.,
+            prev_speed: 0.,
+        }
+    }
+}
+
+// requested service for the next pit stop, the car enters the lane only with a plan
+#[derive(Component, Debug, Clone)]
+pub struct PitStopPlan {
+    pub refuel: bool,
+    pub tires: bool,
+    pub repair: bool,
+}
+
+impl Default for PitStopPlan {
+    fn default() -> Self {
+        Self {
+            refuel: true,
+            tires: true,
+            repair: true,
+        }
+    }
+}
+
+impl PitStopPlan {
+    // refuel and tires in parallel, repair after them
+    pub fn duration(&self, config: &PitConfig, condition: &CarCondition) -> f32 {
+        let refuel = match self.refuel {
+            true => (condition.fuel_capacity - condition.fuel).max(0.) / config.refuel_rate,
+            false => 0.,
+        };
+        let tires = match self.tires {
+            true => config.tire_change,
+            false => 0.,
+        };
+        let repair = match self.repair {
+            true => config.repair * condition.damage,
+            false => 0.,
+        };
+        refuel.max(tires) + repair
+    }
+    pub fn serve(&self, condition: &mut CarCondition) {
+        if self.refuel {
+            condition.fuel = condition.fuel_capacity;
+        }
+        if self.tires {
+            condition.tire_wear = 0.;
+        }
+        if self.repair {
+            condition.damage = 0.;
+        }
+    }
+}
+
+#[derive(Component, Debug, Clone, Default)]
+pub struct PitLaneState {
+    pub in_lane: bool,
+    pub entered_at: f64,
+    pub stop_started_at: Option<f64>,
+    pub stop_duration: f32,
+    // the stop is done, the car drives to the exit
+    pub served: bool,
+    pub stops: usize,
+    // autopilot follows the pit line
+    autopilot_line: bool,
+    prev_position: Option<f32>,
+}
+
+#[derive(Event, Debug, Clone)]
+pub struct PitLaneEntered {
+    pub entity: Entity,
+}
+
+#[derive(Event, Debug, Clone)]
+pub struct PitStopCompleted {
+    pub entity: Entity,
+    // stationary seconds
+    pub duration: f32,
+}
+
+#[derive(Event, Debug, Clone)]
+pub struct PitLaneExited {
+    pub entity: Entity,
+    // entry to exit seconds
+    pub lane_time: f32,
+}
+
+// pit lane of the selected track, right side of the start straight
+#[derive(Resource, Debug)]
+pub struct PitLaneRes(pub PitLane);
+
+pub fn pit_lane_start_system(mut cmd: Commands, track_config: Res<TrackConfig>, track: Res<Track>) {
+    let length = track_config.track_length;
+    let layout = track_config.pit_lane();
+    let lane = PitLane::new(
+        &track_config,
+        &track,
+        layout.entry.rem_euclid(length),
+        layout.exit.rem_euclid(length),
+        layout.box_meters.rem_euclid(length),
+        layout.offset,
+        layout.width,
+    );
+    cmd.insert_resource(PitLaneRes(lane));
+}
+
+pub fn pit_enabled(config: Res<PitConfig>) -> bool {
+    config.enabled
+}
+
+pub fn add_car_condition_system(q_car: Query<Entity, Added<CarTrack>>, mut cmd: Commands) {
+    for e in q_car.iter() {
+        cmd.entity(e)
+            .insert((CarCondition::default(), PitLaneState::default()));
+    }
+}
+
+pub fn car_condition_system(
+    time: Res<Time>,
+    mut q_car: Query<(&Car, &Velocity, &mut CarCondition)>,
+) {
+    let dt = time.delta_seconds();
+    for (car, v, mut condition) in q_car.iter_mut() {
+        condition.fuel = (condition.fuel - car.gas * condition.fuel_rate * dt).max(0.);
+        condition.tire_wear =
+            (condition.tire_wear + v.linvel.length() * dt / condition.tire_life).min(1.);
+        // impacts, speed lost faster than the brakes can do
+        let speed = v.linvel.length();
+        let impact = condition.prev_speed - speed - IMPACT_DECELERATION * dt;
+        if impact > 0. {
+            condition.damage = (condition.damage + impact / IMPACT_FULL_DAMAGE).min(1.);
+        }
+        condition.prev_speed = speed;
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn pit_system(
+    time: Res<Time>,
+    config: Res<PitConfig>,
+    pit_lane: Res<PitLaneRes>,
+    track_config: Res<TrackConfig>,
+    mut q_car: Query<(
+        Entity,
+        &CarTrack,
+        &Transform,
+        &Velocity,
+        &mut PitLaneState,
+        &mut CarCondition,
+    )>,
+    q_plan: Query<&PitStopPlan>,
+    mut q_autopilot: Query<&mut Autopilot>,
+    mut cmd: Commands,
+    mut entered_events: EventWriter<PitLaneEntered>,
+    mut stop_events: EventWriter<PitStopCompleted>,
+    mut exited_events: EventWriter<PitLaneExited>,
+) {
+    let seconds = time.elapsed_seconds_f64();
+    let lane = &pit_lane.0;
+    let length = track_config.track_length;
+    for (e, car_track, t, v, mut state, mut condition) in q_car.iter_mut() {
+        let position = car_track.track_position;
+        let prev_position = state.prev_position.replace(position);
+        let plan = q_plan.get(e).ok();
+
+        // autopilot takes the pit line on the lap of the stop
+        if let Ok(mut autopilot) = q_autopilot.get_mut(e) {
+            let wants_line = plan.is_some() || state.in_lane;
+            if wants_line && !state.autopilot_line {
+                let limits = CarLimits::default();
+                autopilot.line = Some(lane.line(&track_config, &limits, config.speed_limit));
+                state.autopilot_line = true;
+            } else if !wants_line && state.autopilot_line {
+                autopilot.line = None;
+                state.autopilot_line = false;
+            }
+        }
+
+        let Some(prev_position) = prev_position else {
+            continue;
+        };
+        // forward pass over the track position since the last frame
+        let moved = (position - prev_position).rem_euclid(length);
+        let crossed = |meters: f32| {
+            moved < length / 2. && (meters - prev_position).rem_euclid(length) <= moved
+        };
+        if !state.in_lane {
+            let lateral = t.translation.distance(pit_lane_point(lane, t.translation));
+            if plan.is_some() && crossed(lane.entry_meters) && lateral < lane.width {
+                state.in_lane = true;
+                state.entered_at = seconds;
+                state.served = false;
+                entered_events.send(PitLaneEntered { entity: e });
+            }
+            continue;
+        }
+
+        if let Some(started_at) = state.stop_started_at {
+            if (seconds - started_at) as f32 >= state.stop_duration {
+                if let Some(plan) = plan {
+                    plan.serve(&mut condition);
+                }
+                state.stop_started_at = None;
+                state.served = true;
+                state.stops += 1;
+                cmd.entity(e).remove::<PitStopPlan>();
+                stop_events.send(PitStopCompleted {
+                    entity: e,
+                    duration: state.stop_duration,
+                });
+            }
+        } else if !state.served
+            && t.translation.distance(lane.box_position) < config.box_radius
+            && v.linvel.length() < PIT_BOX_SPEED
+        {
+            state.stop_started_at = Some(seconds);
+            state.stop_duration = plan.map_or(0., |plan| plan.duration(&config, &condition));
+        }
+
+        if crossed(lane.exit_meters) || !lane.contains_meters(position, length) {
+            state.in_lane = false;
+            state.stop_started_at = None;
+            exited_events.send(PitLaneExited {
+                entity: e,
+                lane_time: (seconds - state.entered_at) as f32,
+            });
+        }
+    }
+}
+
+fn pit_lane_point(lane: &PitLane, pos: Vec3) -> Vec3 {
+    lane.points
+        .iter()
+        .copied()
+        .min_by(|a, b| a.distance_squared(pos).total_cmp(&b.distance_squared(pos)))
+        .unwrap_or(lane.box_position)
+}
+
+// speed limiter in the lane, held in the box during the stop, no gas without fuel
+pub fn pit_limiter_system(
+    config: Res<PitConfig>,
+    mut q_car: Query<(&mut Car, &Velocity, &PitLaneState, &CarCondition)>,
+) {
+    for (mut car, v, state, condition) in q_car.iter_mut() {
+        if condition.fuel <= 0. {
+            car.gas = 0.;
+        }
+        if state.stop_started_at.is_some() {
+            car.gas = 0.;
+            car.brake = 1.;
+            continue;
+        }
+        if state.in_lane && v.linvel.length() > config.speed_limit {
+            car.gas = 0.;
+            car.brake = car.brake.max(0.3);
+        }
+    }
+}
+
+// lane time and stops are added to the current lap
+pub fn pit_timing_system(
+    mut exited_events: EventReader<PitLaneExited>,
+    mut q_timing: Query<&mut LapTiming>,
+) {
+    for event in exited_events.read() {
+        if let Ok(mut timing) = q_timing.get_mut(event.entity) {
+            timing.pit_time += event.lane_time;
+        }
+    }
+}
+
+pub fn pit_lane_gizmos_system(car_res: Res<CarRes>, pit_lane: Res<PitLaneRes>, mut gizmos: Gizmos) {
+    if !car_res.show_rays {
+        return;
+    }
+    let lane = &pit_lane.0;
+    let h = Vec3::Y * 0.1;
+    gizmos.linestrip(
+        lane.points.iter().map(|p| *p + h),
+        Color::srgb(0.9, 0.9, 0.2),
+    );
+    gizmos.circle(
+        lane.box_position + h,
+        Dir3::Y,
+        2.,
+        Color::srgb(0.9, 0.9, 0.2),
+    );
+}
+
+pub struct PitPlugin;
+
+impl Plugin for PitPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(PitConfig::from_env())
+            .add_event::<PitLaneEntered>()
+            .add_event::<PitStopCompleted>()
+            .add_event::<PitLaneExited>()
+            .add_systems(
+                Startup,
+                pit_lane_start_system
+                    .after(crate::track_polyline_start_system)
+                    .after(crate::track_start_system),
+            )
+            .add_systems(
+                Update,
+                (
+                    add_car_condition_system,
+                    car_condition_system,
+                    pit_system.in_set(CarSet::Input),
+                    pit_limiter_system
+                        .after(CarSet::Input)
+                        .after(CarSet::NeuralNetwork)
+                        .before(CarSet::Esp),
+                    pit_timing_system
+                        .after(pit_system)
+                        .before(crate::timing_system),
+                    pit_lane_gizmos_system,
+                )
+                    .run_if(pit_enabled),
+            );
+    }
+}
@@ -1,5 +1,5 @@
 use crate::car_track::CarTrack;
-use crate::{TrackConfig, TRACK_POSITIONS};
+use crate::{CheckpointConfig, CheckpointProgress, TrackConfig};
 use bevy::prelude::*;
 use bevy_garage_car::{CarRes, CAR_TRAINING_GROUP, STATIC_GROUP};
 use bevy_rapier3d::parry::query::PointQueryWithLocation;
@@ -9,7 +9,7 @@ use bevy_rapier3d::{na::Point3, prelude::*, rapier::prelude::ColliderShape};
 use std::cmp::Ordering;
 
 pub fn track_polyline_start_system(mut cmd: Commands, mut track_config: ResMut<TrackConfig>) {
-    let positions = TRACK_POSITIONS;
+    let positions = track_config.positions();
 
     let vertices: Vec<Point3<Real>> = positions
         .iter()
@@ -58,15 +58,39 @@ pub fn track_polyline_start_system(mut cmd: Commands, mut track_config: ResMut<T
     ));
 }
 
+// polyline point and direction at a track position
+fn line_at(track_config: &TrackConfig, track_position: f32) -> (Vec3, Vec3) {
+    let polyline = track_config.polyline.as_ref().unwrap();
+    let meters = (track_position + track_config.start_shift).rem_euclid(track_config.track_length);
+    let i = track_config
+        .segments
+        .partition_point(|s| *s <= meters)
+        .max(1)
+        - 1;
+    let segment = polyline.segment(i as u32);
+    let dir = Vec3::from(segment.direction().unwrap());
+    (
+        Vec3::from(segment.a) + dir * (meters - track_config.segments[i]),
+        dir,
+    )
+}
+
 pub fn progress_system(
     track_config: Res<TrackConfig>,
-    mut cars: Query<(&Transform, &mut CarTrack, Entity)>,
+    checkpoint_config: Res<CheckpointConfig>,
+    mut cars: Query<(
+        &Transform,
+        &mut CarTrack,
+        Entity,
+        Option<&CheckpointProgress>,
+    )>,
     car_res: Res<CarRes>,
     mut gizmos: Gizmos,
 ) {
     let polyline = track_config.polyline.as_ref().unwrap();
     let mut board: Vec<(Entity, f32)> = Vec::new();
-    for (tr, mut car, e) in cars.iter_mut() {
+    let spacing = checkpoint_config.spacing(track_config.track_length);
+    for (tr, mut car, e, checkpoints) in cars.iter_mut() {
         let point: Point3<Real> = Point3::from(tr.translation);
         let point_location = polyline.project_local_point_and_get_location(&point, true);
         let (segment_i, segment_location) = point_location.1;
@@ -96,15 +120,29 @@ pub fn progress_system(
             // prevent increasing distance by going backward
             ride_distance = ride_distance - track_config.track_length;
         }
-        if ride_distance.is_sign_positive() && car.ride_distance.is_sign_negative()
-            || ride_distance < half && car.ride_distance > half
-        {
-            car.lap += 1;
+        // laps are counted by gates, a projection past the next gate is a shortcut
+        let shortcut = checkpoints.is_some_and(|checkpoints| {
+            let gate = checkpoints.next as f32 * spacing;
+            let past_gate = (track_position - gate).rem_euclid(track_config.track_length);
+            past_gate > spacing / 2. && past_gate < half
+        });
+        if shortcut {
+            // the line point stays at the held position as well
+            (car.line_pos, car.line_dir) = line_at(&track_config, car.track_position);
+            board.push((e, car.track_position));
+            continue;
         }
-        if ride_distance.is_sign_negative() && car.ride_distance.is_sign_positive()
-            || ride_distance > -half && car.ride_distance < -half
-        {
-            car.lap -= 1;
+        if checkpoints.is_none() {
+            if ride_distance.is_sign_positive() && car.ride_distance.is_sign_negative()
+                || ride_distance < half && car.ride_distance > half
+            {
+                car.lap += 1;
+            }
+            if ride_distance.is_sign_negative() && car.ride_distance.is_sign_positive()
+                || ride_distance > -half && car.ride_distance < -half
+            {
+                car.lap -= 1;
+            }
         }
         car.track_position = track_position;
         car.ride_distance = ride_distance;
@@ -129,7 +167,7 @@ pub fn progress_system(
         Ordering::Less
     });
     for (i, (e, _)) in board.iter().enumerate() {
-        let (_, mut p, _) = cars.get_mut(*e).unwrap();
+        let (_, mut p, _, _) = cars.get_mut(*e).unwrap();
         p.place = i;
     }
 }
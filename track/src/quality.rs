@@ -37,7 +37,10 @@ pub fn far_culling(
         >,
     )>,
 ) {
-    let cam_translation = pset.p0().single().translation;
+    // the headless server has no camera
+    let Ok(cam_translation) = pset.p0().get_single().map(|t| t.translation) else {
+        return;
+    };
 
     for (transform, mut cell_visibility, inherited_visibility, entity, mut cell) in
         pset.p1().iter_mut()
@@ -0,0 +1,458 @@
+use crate::{checkpoint_system, CarTrack, GateCrossed, TrackConfig};
+use bevy::prelude::*;
+use bevy_garage_car::{Car, CarSet};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RaceState {
+    // free practice before the race
+    Warmup,
+    // cars hold on the grid
+    Grid,
+    // start lights
+    Countdown,
+    Green,
+    Finished,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum RaceLimit {
+    Laps(i32),
+    // seconds, the leader finishes on the first line crossing after the limit
+    Time(f32),
+}
+
+#[derive(Resource, Debug)]
+pub struct RaceSession {
+    pub state: RaceState,
+    pub limit: RaceLimit,
+    // seconds of every state before green
+    pub warmup: f32,
+    pub grid: f32,
+    pub countdown: f32,
+    // seconds after the winner for others to finish
+    pub finish_timeout: f32,
+    // elapsed seconds when the state was entered
+    pub state_at: f64,
+    pub green_at: f64,
+    // chequered flag is out, every car finishes on its next line crossing
+    pub chequered: bool,
+    pub chequered_at: f64,
+    // cars keep driving behind the line until green
+    pub rolling: bool,
+}
+
+impl RaceSession {
+    pub fn new(limit: RaceLimit) -> Self {
+        Self {
+            state: RaceState::Warmup,
+            limit,
+            warmup: 0.,
+            grid: 3.,
+            countdown: 5.,
+            finish_timeout: 60.,
+            state_at: 0.,
+            green_at: 0.,
+            chequered: false,
+            chequered_at: 0.,
+            rolling: false,
+        }
+    }
+    // RACE_LAPS=n or RACE_TIME=seconds, RACE_WARMUP=seconds of practice before the grid,
+    // RACE_ROLLING_SPEED=km/h rolling start
+    pub fn from_env() -> Option<Self> {
+        let env = |name: &str| std::env::var(name).ok()?.parse::<f32>().ok();
+        let limit = match (env("RACE_LAPS"), env("RACE_TIME")) {
+            (Some(laps), _) => RaceLimit::Laps(laps as i32),
+            (None, Some(seconds)) => RaceLimit::Time(seconds),
+            (None, None) => return None,
+        };
+        let mut session = Self::new(limit);
+        session.warmup = env("RACE_WARMUP").unwrap_or(session.warmup);
+        session.rolling = env("RACE_ROLLING_SPEED").is_some();
+        Some(session)
+    }
+    pub fn set_state(&mut self, state: RaceState, seconds: f64) {
+        println!("race {:?} -> {state:?}", self.state);
+        self.state = state;
+        self.state_at = seconds;
+        if state == RaceState::Green {
+            self.green_at = seconds;
+        }
+    }
+    // restarts from the grid
+    pub fn restart(&mut self, seconds: f64) {
+        self.chequered = false;
+        self.set_state(RaceState::Grid, seconds);
+    }
+    pub fn frozen(&self) -> bool {
+        !self.rolling && matches!(self.state, RaceState::Grid | RaceState::Countdown)
+    }
+    // start lights on, 0 when green
+    pub fn lights(&self, seconds: f64) -> u32 {
+        match self.state {
+            RaceState::Countdown => {
+                let left = self.countdown as f64 - (seconds - self.state_at);
+                5 - (5. * left / self.countdown as f64).floor().clamp(0., 5.) as u32
+            }
+            _ => 0,
+        }
+    }
+    pub fn race_seconds(&self, seconds: f64) -> f32 {
+        match self.state {
+            RaceState::Green | RaceState::Finished => (seconds - self.green_at) as f32,
+            _ => 0.,
+        }
+    }
+}
+
+// per car race progress, line crossings are counted from green
+#[derive(Component, Debug, Clone, Default)]
+pub struct RaceCar {
+    // completed laps, -1 for cars starting behind the line
+    pub laps: i32,
+    pub lap_started_at: f64,
+    pub best_lap: Option<f32>,
+    pub last_lap: Option<f32>,
+    // race seconds when finished, without penalty
+    pub finished: Option<f32>,
+    // seconds added to the race time
+    pub penalty: f32,
+    // laps * track length + track position
+    pub distance: f32,
+}
+
+impl RaceCar {
+    // race seconds with penalty
+    pub fn total_time(&self) -> Option<f32> {
+        self.finished.map(|time| time + self.penalty)
+    }
+}
+
+// more laps first, finishers by time on the same lap, the rest by distance
+pub fn race_order(a: &RaceCar, b: &RaceCar) -> std::cmp::Ordering {
+    b.laps
+        .max(0)
+        .cmp(&a.laps.max(0))
+        .then_with(|| match (a.total_time(), b.total_time()) {
+            (Some(a), Some(b)) => a.total_cmp(&b),
+            (Some(_), None) => std::cmp::Ordering::Less,
+            (None, Some(_)) => std::cmp::Ordering::Greater,
+            (None, None) => b.distance.total_cmp(&a.distance),
+        })
+}
+
+#[derive(Debug, Clone)]
+pub struct RaceClassification {
+    pub position: usize,
+    pub entity: Entity,
+    // CarTrack index
+    pub index: usize,
+    pub laps: i32,
+    pub finished: bool,
+    // with penalty
+    pub total_time: Option<f32>,
+    pub penalty: f32,
+    // seconds behind the winner on the same lap
+    pub gap: Option<f32>,
+    pub laps_down: i32,
+    pub best_lap: Option<f32>,
+}
+
+#[derive(Event, Debug, Clone)]
+pub struct RaceResult {
+    pub limit: RaceLimit,
+    pub classification: Vec<RaceClassification>,
+    pub fastest_lap: Option<(Entity, f32)>,
+}
+
+pub fn add_race_car_system(q_car: Query<Entity, Added<CarTrack>>, mut cmd: Commands) {
+    for e in q_car.iter() {
+        cmd.entity(e).insert(RaceCar::default());
+    }
+}
+
+pub fn race_state_system(
+    time: Res<Time>,
+    mut session: ResMut<RaceSession>,
+    mut q_car: Query<(Entity, &CarTrack, &mut RaceCar)>,
+    mut gate_events: EventReader<GateCrossed>,
+    track_config: Res<TrackConfig>,
+) {
+    let seconds = time.elapsed_seconds_f64();
+    let in_state = (seconds - session.state_at) as f32;
+    let crossed: Vec<Entity> = gate_events
+        .read()
+        .filter(|gate| gate.gate == 0 && !gate.backward)
+        .map(|gate| gate.entity)
+        .collect();
+    let green = match session.state {
+        RaceState::Warmup if in_state >= session.warmup => {
+            session.set_state(RaceState::Grid, seconds);
+            false
+        }
+        // rolling start, green as soon as the leader reaches the line
+        RaceState::Grid | RaceState::Countdown if session.rolling && !crossed.is_empty() => true,
+        RaceState::Grid if in_state >= session.grid => {
+            session.set_state(RaceState::Countdown, seconds);
+            false
+        }
+        RaceState::Countdown => in_state >= session.countdown,
+        _ => false,
+    };
+    if !green {
+        return;
+    }
+    session.set_state(RaceState::Green, seconds);
+    let half = track_config.track_length / 2.;
+    for (e, car_track, mut race_car) in q_car.iter_mut() {
+        // crossing the line in this frame is counted as the first one
+        let behind_line = car_track.track_position > half || crossed.contains(&e);
+        *race_car = RaceCar {
+            laps: if behind_line { -1 } else { 0 },
+            lap_started_at: seconds,
+            ..default()
+        };
+    }
+}
+
+pub fn race_progress_system(
+    time: Res<Time>,
+    mut session: ResMut<RaceSession>,
+    track_config: Res<TrackConfig>,
+    mut q_car: Query<(Entity, &CarTrack, &mut RaceCar)>,
+    mut gate_events: EventReader<GateCrossed>,
+    mut result_events: EventWriter<RaceResult>,
+) {
+    if session.state != RaceState::Green {
+        gate_events.clear();
+        return;
+    }
+    let seconds = time.elapsed_seconds_f64();
+    let race_seconds = session.race_seconds(seconds);
+    // only the leader takes the chequered flag, the others finish behind it
+    let leader = q_car
+        .iter()
+        .max_by(|a, b| a.2.distance.total_cmp(&b.2.distance))
+        .map(|(e, _, _)| e);
+    for gate in gate_events.read() {
+        if gate.gate != 0 || !gate.counted {
+            continue;
+        }
+        let Ok((_, _, mut race_car)) = q_car.get_mut(gate.entity) else {
+            continue;
+        };
+        if race_car.finished.is_some() {
+            continue;
+        }
+        if !gate.backward {
+            race_car.laps += 1;
+            if race_car.laps > 0 {
+                let lap = (seconds - race_car.lap_started_at) as f32;
+                race_car.last_lap = Some(lap);
+                race_car.best_lap = Some(race_car.best_lap.map_or(lap, |best| best.min(lap)));
+            }
+            race_car.lap_started_at = seconds;
+            let limit_reached = match session.limit {
+                RaceLimit::Laps(laps) => race_car.laps >= laps,
+                RaceLimit::Time(limit) => race_seconds >= limit,
+            };
+            let leader = leader == Some(gate.entity);
+            if session.chequered || limit_reached && leader && race_car.laps > 0 {
+                if !session.chequered {
+                    session.chequered = true;
+                    session.chequered_at = seconds;
+                }
+                race_car.finished = Some(race_seconds);
+            }
+        } else {
+            // backwards over the line
+            race_car.laps -= 1;
+        }
+    }
+    let length = track_config.track_length;
+    for (_, car_track, mut race_car) in q_car.iter_mut() {
+        if race_car.finished.is_some() {
+            continue;
+        }
+        race_car.distance = race_car.laps as f32 * length + car_track.track_position;
+    }
+
+    let all_finished = q_car
+        .iter()
+        .all(|(_, _, race_car)| race_car.finished.is_some());
+    let timed_out =
+        session.chequered && (seconds - session.chequered_at) as f32 >= session.finish_timeout;
+    if !all_finished && !timed_out {
+        return;
+    }
+    session.set_state(RaceState::Finished, seconds);
+    let cars = q_car
+        .iter()
+        .map(|(e, car_track, race_car)| (e, car_track.index, race_car))
+        .collect();
+    result_events.send(race_result(session.limit, cars));
+}
+
+// cars with their CarTrack index
+pub fn race_result(limit: RaceLimit, mut cars: Vec<(Entity, usize, &RaceCar)>) -> RaceResult {
+    cars.sort_by(|a, b| race_order(a.2, b.2));
+    let winner = cars
+        .first()
+        .map(|(_, _, c)| (c.laps.max(0), c.total_time()));
+    let classification = cars
+        .iter()
+        .enumerate()
+        .map(|(i, (e, index, race_car))| {
+            let laps_down = winner.map_or(0, |(laps, _)| laps - race_car.laps.max(0));
+            let gap = match (winner, race_car.total_time(), laps_down) {
+                (Some((_, Some(winner_time))), Some(time), 0) => Some(time - winner_time),
+                _ => None,
+            };
+            RaceClassification {
+                position: i + 1,
+                entity: *e,
+                index: *index,
+                laps: race_car.laps.max(0),
+                finished: race_car.finished.is_some(),
+                total_time: race_car.total_time(),
+                penalty: race_car.penalty,
+                gap,
+                laps_down,
+                best_lap: race_car.best_lap,
+            }
+        })
+        .collect();
+    let fastest_lap = cars
+        .iter()
+        .filter_map(|(e, _, c)| c.best_lap.map(|lap| (*e, lap)))
+        .min_by(|a, b| a.1.total_cmp(&b.1));
+    RaceResult {
+        limit,
+        classification,
+        fastest_lap,
+    }
+}
+
+pub fn race_result_print_system(mut result_events: EventReader<RaceResult>) {
+    for result in result_events.read() {
+        println!("race result {:?}", result.limit);
+        for c in result.classification.iter() {
+            let time = match (c.total_time, c.gap, c.laps_down) {
+                (_, _, _) if !c.finished => "DNF".to_string(),
+                (Some(time), _, _) if c.position == 1 => format!("{time:.3}s"),
+                (_, Some(gap), _) => format!("+{gap:.3}s"),
+                (_, _, laps_down) => format!("+{laps_down} laps"),
+            };
+            let best_lap = c
+                .best_lap
+                .map_or("-".to_string(), |lap| format!("{lap:.3}s"));
+            let penalty = match c.penalty > 0. {
+                true => format!(" (+{:.0}s penalty)", c.penalty),
+                false => String::new(),
+            };
+            println!(
+                "{:>2}. car {} laps {} {time}{penalty} best {best_lap}",
+                c.position, c.index, c.laps
+            );
+        }
+    }
+}
+
+// inputs are held until green, runs after input and nn systems
+pub fn race_freeze_system(session: Res<RaceSession>, mut q_car: Query<&mut Car>) {
+    if !session.frozen() {
+        return;
+    }
+    for mut car in q_car.iter_mut() {
+        car.gas = 0.;
+        car.brake = 1.;
+        car.steering = 0.;
+    }
+}
+
+pub struct RacePlugin;
+
+impl Plugin for RacePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<RaceResult>().add_systems(
+            Update,
+            (
+                add_race_car_system,
+                race_state_system.after(checkpoint_system),
+                race_progress_system
+                    .after(race_state_system)
+                    .after(checkpoint_system),
+                race_result_print_system.after(race_progress_system),
+                race_freeze_system
+                    .after(CarSet::Input)
+                    .after(CarSet::NeuralNetwork)
+                    .before(CarSet::Esp),
+            )
+                .run_if(resource_exists::<RaceSession>),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn race_car(laps: i32, finished: Option<f32>, penalty: f32, distance: f32) -> RaceCar {
+        RaceCar {
+            laps,
+            finished,
+            penalty,
+            distance,
+            best_lap: finished.map(|time| time / laps.max(1) as f32),
+            ..default()
+        }
+    }
+
+    #[test]
+    fn classification() {
+        let cars = [
+            // penalty drops it behind the second finisher
+            race_car(3, Some(100.), 5., 3000.),
+            race_car(3, Some(102.), 0., 3000.),
+            // lapped, not finished but further than the one behind the line
+            race_car(2, None, 0., 2500.),
+            race_car(-1, None, 0., -10.),
+            // lapped finisher is ahead of a lapped runner
+            race_car(2, Some(110.), 0., 2000.),
+        ];
+        let entities: Vec<Entity> = (0..cars.len() as u32).map(Entity::from_raw).collect();
+        let input = cars
+            .iter()
+            .enumerate()
+            .map(|(i, car)| (entities[i], i, car))
+            .collect();
+        let result = race_result(RaceLimit::Laps(3), input);
+        let order: Vec<usize> = result.classification.iter().map(|c| c.index).collect();
+        assert_eq!(order, vec![1, 0, 4, 2, 3]);
+        let positions: Vec<usize> = result.classification.iter().map(|c| c.position).collect();
+        assert_eq!(positions, vec![1, 2, 3, 4, 5]);
+
+        let [winner, second, lapped_finisher, lapped, behind] = &result.classification[..] else {
+            panic!("five cars");
+        };
+        assert_eq!((winner.total_time, winner.gap), (Some(102.), Some(0.)));
+        assert_eq!(
+            (second.total_time, second.gap, second.penalty),
+            (Some(105.), Some(3.), 5.)
+        );
+        assert_eq!((lapped_finisher.laps_down, lapped_finisher.gap), (1, None));
+        assert!(lapped_finisher.finished && !lapped.finished);
+        assert_eq!((lapped.laps_down, lapped.total_time), (1, None));
+        // laps before the line don't count
+        assert_eq!((behind.laps, behind.laps_down), (0, 3));
+        assert_eq!(result.fastest_lap, Some((entities[0], 100. / 3.)));
+    }
+
+    #[test]
+    fn empty_classification() {
+        let result = race_result(RaceLimit::Time(60.), vec![]);
+        assert!(result.classification.is_empty());
+        assert!(result.fastest_lap.is_none());
+    }
+}
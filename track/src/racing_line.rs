@@ -0,0 +1,214 @@
+use crate::{CarLimits, Track, TrackConfig, TrackLine};
+use bevy::{
+    prelude::*,
+    tasks::{block_on, futures_lite::future, AsyncComputeTaskPool, Task},
+};
+use bevy_garage_car::CarRes;
+use serde::{Deserialize, Serialize};
+use std::{fs, path::PathBuf};
+
+// meters between optimised points
+const RACING_LINE_STEP: f32 = 4.;
+const RACING_LINE_ITERATIONS: usize = 20_000;
+// below 2 / 32, the largest eigenvalue of the curvature gradient
+const RACING_LINE_RATE: f32 = 0.05;
+
+// pair of edge points across the track
+struct Cross {
+    left: Vec3,
+    right: Vec3,
+}
+
+// edge pairs at uniform distance along the center line
+fn resample(track: &Track, step: f32) -> Vec<Cross> {
+    let n = track.points.len() - 1;
+    let mut crosses: Vec<Cross> = vec![];
+    let mut carry = 0.;
+    for i in 0..n {
+        let length = track.points[i].distance(track.points[i + 1]);
+        let mut shift = carry;
+        while shift < length {
+            let t = shift / length;
+            crosses.push(Cross {
+                left: track.left[i].lerp(track.left[i + 1], t),
+                right: track.right[i].lerp(track.right[i + 1], t),
+            });
+            shift += step;
+        }
+        carry = shift - length;
+    }
+    crosses
+}
+
+// minimum curvature line: minimises sum of squared second differences of the points,
+// every point slides on its cross section between the edges keeping half the car inside
+pub fn solve_racing_line(track: &Track, limits: &CarLimits) -> TrackLine {
+    let crosses = resample(track, RACING_LINE_STEP);
+    let n = crosses.len();
+    let bounds: Vec<(f32, f32)> = crosses
+        .iter()
+        .map(|c| {
+            let margin = (limits.half_width / c.left.distance(c.right).max(f32::EPSILON)).min(0.5);
+            (margin, 1. - margin)
+        })
+        .collect();
+    // 0 - left edge, 1 - right edge
+    let mut alpha = vec![0.5; n];
+    let point = |alpha: &[f32], i: usize| crosses[i].left.lerp(crosses[i].right, alpha[i]);
+    let wrap = |i: isize| i.rem_euclid(n as isize) as usize;
+    let mut second = vec![Vec3::ZERO; n];
+    for _ in 0..RACING_LINE_ITERATIONS {
+        for (i, d) in second.iter_mut().enumerate() {
+            let i = i as isize;
+            *d = point(&alpha, wrap(i - 1)) - 2. * point(&alpha, wrap(i))
+                + point(&alpha, wrap(i + 1));
+        }
+        for i in 0..n {
+            let ii = i as isize;
+            let gradient = second[wrap(ii - 1)] - 2. * second[i] + second[wrap(ii + 1)];
+            let across = crosses[i].right - crosses[i].left;
+            let step = RACING_LINE_RATE * gradient.dot(across) / across.length_squared();
+            alpha[i] = (alpha[i] - step).clamp(bounds[i].0, bounds[i].1);
+        }
+    }
+    let points: Vec<Vec3> = (0..n).map(|i| point(&alpha, i)).collect();
+    TrackLine::new(points).with_speed_profile(limits)
+}
+
+#[derive(Resource, Debug)]
+pub struct RacingLine(pub TrackLine);
+
+#[derive(Serialize, Deserialize)]
+pub struct RacingLineFile {
+    pub points: Vec<[f32; 3]>,
+    pub speeds: Vec<f32>,
+    pub lap_time: f32,
+}
+
+impl From<&TrackLine> for RacingLineFile {
+    fn from(line: &TrackLine) -> Self {
+        Self {
+            points: line.points.iter().map(|p| p.to_array()).collect(),
+            speeds: line.speeds.clone(),
+            lap_time: line.lap_time(),
+        }
+    }
+}
+
+impl From<RacingLineFile> for TrackLine {
+    fn from(file: RacingLineFile) -> Self {
+        let mut line = TrackLine::new(file.points.into_iter().map(Vec3::from).collect());
+        if file.speeds.len() == line.points.len() {
+            line.speeds = file.speeds;
+        }
+        line
+    }
+}
+
+// RACING_LINE_DIR=path, otherwise the user cache directory, the line is generated data
+pub fn racing_line_dir() -> PathBuf {
+    if let Ok(dir) = std::env::var("RACING_LINE_DIR") {
+        return PathBuf::from(dir);
+    }
+    let cache = std::env::var("XDG_CACHE_HOME")
+        .map(PathBuf::from)
+        .or_else(|_| std::env::var("HOME").map(|home| PathBuf::from(home).join(".cache")))
+        .unwrap_or_else(|_| std::env::temp_dir());
+    cache.join("bevy_garage")
+}
+
+pub fn racing_line_path(track_index: usize) -> PathBuf {
+    racing_line_dir().join(match track_index {
+        0 => "track-racing-line.ron".to_string(),
+        i => format!("track-{i}-racing-line.ron"),
+    })
+}
+
+pub fn load_racing_line(track_index: usize) -> Option<TrackLine> {
+    let ron = fs::read_to_string(racing_line_path(track_index)).ok()?;
+    let file: RacingLineFile = ron::from_str(&ron).ok()?;
+    Some(file.into())
+}
+
+pub fn save_racing_line(track_index: usize, line: &TrackLine) -> Result<(), String> {
+    let ron = ron::ser::to_string_pretty(&RacingLineFile::from(line), Default::default())
+        .map_err(|e| e.to_string())?;
+    fs::create_dir_all(racing_line_dir()).map_err(|e| e.to_string())?;
+    fs::write(racing_line_path(track_index), ron).map_err(|e| e.to_string())
+}
+
+// racing line solved off the main thread, autopilot follows the center line meanwhile
+#[derive(Resource)]
+pub struct RacingLineTask(Task<TrackLine>);
+
+// loads the saved line, RACING_LINE_SOLVE=1 or a missing file solves and saves it again
+pub fn racing_line_start_system(
+    mut cmd: Commands,
+    track_config: Res<TrackConfig>,
+    track: Res<Track>,
+) {
+    let index = track_config.track_index;
+    let solve = std::env::var("RACING_LINE_SOLVE").is_ok_and(|v| v == "1");
+    let loaded = match solve {
+        true => None,
+        false => load_racing_line(index),
+    };
+    match loaded {
+        Some(line) => cmd.insert_resource(RacingLine(line)),
+        None => {
+            let track = track.clone();
+            let task = AsyncComputeTaskPool::get()
+                .spawn(async move { solve_racing_line(&track, &CarLimits::default()) });
+            cmd.insert_resource(RacingLineTask(task));
+        }
+    }
+}
+
+pub fn racing_line_task_system(
+    mut cmd: Commands,
+    track_config: Res<TrackConfig>,
+    task: Option<ResMut<RacingLineTask>>,
+) {
+    let Some(mut task) = task else {
+        return;
+    };
+    let Some(line) = block_on(future::poll_once(&mut task.0)) else {
+        return;
+    };
+    cmd.remove_resource::<RacingLineTask>();
+    println!("racing line solved, lap time {:.2}s", line.lap_time());
+    let index = track_config.track_index;
+    if let Err(err) = save_racing_line(index, &line) {
+        println!(
+            "racing line save error {:?}: {err}",
+            racing_line_path(index)
+        );
+    }
+    cmd.insert_resource(RacingLine(line));
+}
+
+// red is slow, green is max speed
+pub fn racing_line_gizmos_system(
+    car_res: Res<CarRes>,
+    racing_line: Option<Res<RacingLine>>,
+    mut gizmos: Gizmos,
+) {
+    let Some(racing_line) = racing_line else {
+        return;
+    };
+    if !car_res.show_rays {
+        return;
+    }
+    let line = &racing_line.0;
+    let max_speed = line.speeds.iter().copied().fold(f32::EPSILON, f32::max);
+    let h = Vec3::Y * 0.1;
+    for i in 0..line.points.len() {
+        let next = (i + 1) % line.points.len();
+        let speed = (line.speeds[i] / max_speed).clamp(0., 1.);
+        gizmos.line(
+            h + line.points[i],
+            h + line.points[next],
+            Color::srgb(1. - speed, speed, 0.),
+        );
+    }
+}
@@ -0,0 +1,722 @@
+use crate::{
+    CarTrack, LapCompleted, PitStopCompleted, RaceResult, RaceSession, RaceState,
+    SpawnCarOnTrackEvent, TrackLimitsViolation, WrongWay,
+};
+use bevy::{app::AppExit, prelude::*};
+use bevy_garage_car::{Car, CarSet, CarWheels, Player};
+use bevy_rapier3d::prelude::*;
+use std::{fs, path::PathBuf};
+
+const REPLAY_MAGIC: &[u8; 4] = b"BGRP";
+const REPLAY_VERSION: u8 = 1;
+// seconds per seek step
+const REPLAY_SEEK: f32 = 5.;
+
+#[derive(Debug, Clone, Copy)]
+pub struct ReplayCar {
+    // CarTrack index
+    pub index: usize,
+    pub player: bool,
+    pub body: Transform,
+    pub wheels: [Transform; 4],
+    pub gas: f32,
+    pub brake: f32,
+    pub steering: f32,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ReplayFrame {
+    // seconds from the recording start
+    pub time: f32,
+    pub cars: Vec<ReplayCar>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum ReplayEventKind {
+    RaceState(RaceState),
+    LapCompleted { lap: usize, time: f32, valid: bool },
+    TrackLimits { warnings: u32, penalty: Option<f32> },
+    WrongWay { gate: usize },
+    PitStop { duration: f32 },
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct ReplayEvent {
+    pub time: f32,
+    // CarTrack index, 0 for session events
+    pub index: usize,
+    pub kind: ReplayEventKind,
+}
+
+// all cars at a fixed rate plus race events
+#[derive(Debug, Clone, Default)]
+pub struct Replay {
+    // frames per second
+    pub rate: f32,
+    pub frames: Vec<ReplayFrame>,
+    pub events: Vec<ReplayEvent>,
+}
+
+impl Replay {
+    pub fn duration(&self) -> f32 {
+        self.frames.last().map_or(0., |frame| frame.time)
+    }
+    // cars interpolated between the frames around the time, matched by index
+    pub fn cars_at(&self, time: f32) -> Vec<ReplayCar> {
+        let Some(last) = self.frames.len().checked_sub(1) else {
+            return vec![];
+        };
+        let i = self.frames.partition_point(|f| f.time <= time);
+        let (a, b) = (&self.frames[i.saturating_sub(1)], &self.frames[i.min(last)]);
+        let t = match b.time > a.time {
+            true => ((time - a.time) / (b.time - a.time)).clamp(0., 1.),
+            false => 0.,
+        };
+        let lerp = |a: &Transform, b: &Transform| Transform {
+            translation: a.translation.lerp(b.translation, t),
+            rotation: a.rotation.slerp(b.rotation, t),
+            scale: a.scale,
+        };
+        a.cars
+            .iter()
+            .map(
+                |car| match b.cars.iter().find(|next| next.index == car.index) {
+                    Some(next) => ReplayCar {
+                        body: lerp(&car.body, &next.body),
+                        wheels: [0, 1, 2, 3].map(|w| lerp(&car.wheels[w], &next.wheels[w])),
+                        gas: car.gas + (next.gas - car.gas) * t,
+                        brake: car.brake + (next.brake - car.brake) * t,
+                        steering: car.steering + (next.steering - car.steering) * t,
+                        ..*car
+                    },
+                    None => *car,
+                },
+            )
+            .collect()
+    }
+
+    // little endian binary: header, frames, events
+    pub fn encode(&self) -> Vec<u8> {
+        let mut w = ReplayWriter::default();
+        w.0.extend(REPLAY_MAGIC);
+        w.u8(REPLAY_VERSION);
+        w.f32(self.rate);
+        w.u32(self.frames.len() as u32);
+        for frame in self.frames.iter() {
+            w.f32(frame.time);
+            w.u32(frame.cars.len() as u32);
+            for car in frame.cars.iter() {
+                w.u32(car.index as u32);
+                w.u8(car.player as u8);
+                w.transform(&car.body);
+                for wheel in car.wheels.iter() {
+                    w.transform(wheel);
+                }
+                w.f32(car.gas);
+                w.f32(car.brake);
+                w.f32(car.steering);
+            }
+        }
+        w.u32(self.events.len() as u32);
+        for event in self.events.iter() {
+            w.f32(event.time);
+            w.u32(event.index as u32);
+            // kind, then one float, one integer and one byte
+            let (kind, a, b, c) = match event.kind {
+                ReplayEventKind::RaceState(state) => (0, 0., 0, race_state_byte(state)),
+                ReplayEventKind::LapCompleted { lap, time, valid } => {
+                    (1, time, lap as u32, valid as u8)
+                }
+                ReplayEventKind::TrackLimits { warnings, penalty } => {
+                    (2, penalty.unwrap_or(0.), warnings, penalty.is_some() as u8)
+                }
+                ReplayEventKind::WrongWay { gate } => (3, 0., gate as u32, 0),
+                ReplayEventKind::PitStop { duration } => (4, duration, 0, 0),
+            };
+            w.u8(kind);
+            w.f32(a);
+            w.u32(b);
+            w.u8(c);
+        }
+        w.0
+    }
+
+    pub fn decode(bytes: &[u8]) -> Option<Self> {
+        let mut r = ReplayReader { bytes, at: 0 };
+        if r.take::<4>()? != *REPLAY_MAGIC || r.u8()? != REPLAY_VERSION {
+            return None;
+        }
+        let rate = r.f32()?;
+        let frames = (0..r.u32()?)
+            .map(|_| {
+                let time = r.f32()?;
+                let cars = (0..r.u32()?)
+                    .map(|_| {
+                        Some(ReplayCar {
+                            index: r.u32()? as usize,
+                            player: r.u8()? != 0,
+                            body: r.transform()?,
+                            wheels: [
+                                r.transform()?,
+                                r.transform()?,
+                                r.transform()?,
+                                r.transform()?,
+                            ],
+                            gas: r.f32()?,
+                            brake: r.f32()?,
+                            steering: r.f32()?,
+                        })
+                    })
+                    .collect::<Option<Vec<_>>>()?;
+                Some(ReplayFrame { time, cars })
+            })
+            .collect::<Option<Vec<_>>>()?;
+        let events = (0..r.u32()?)
+            .map(|_| {
+                let (time, index) = (r.f32()?, r.u32()? as usize);
+                let (kind, a, b, c) = (r.u8()?, r.f32()?, r.u32()?, r.u8()?);
+                let kind = match kind {
+                    0 => ReplayEventKind::RaceState(race_state_from_byte(c)?),
+                    1 => ReplayEventKind::LapCompleted {
+                        lap: b as usize,
+                        time: a,
+                        valid: c != 0,
+                    },
+                    2 => ReplayEventKind::TrackLimits {
+                        warnings: b,
+                        penalty: (c != 0).then_some(a),
+                    },
+                    3 => ReplayEventKind::WrongWay { gate: b as usize },
+                    4 => ReplayEventKind::PitStop { duration: a },
+                    _ => return None,
+                };
+                Some(ReplayEvent { time, index, kind })
+            })
+            .collect::<Option<Vec<_>>>()?;
+        Some(Self {
+            rate,
+            frames,
+            events,
+        })
+    }
+}
+
+fn race_state_byte(state: RaceState) -> u8 {
+    match state {
+        RaceState::Warmup => 0,
+        RaceState::Grid => 1,
+        RaceState::Countdown => 2,
+        RaceState::Green => 3,
+        RaceState::Finished => 4,
+    }
+}
+
+fn race_state_from_byte(byte: u8) -> Option<RaceState> {
+    Some(match byte {
+        0 => RaceState::Warmup,
+        1 => RaceState::Grid,
+        2 => RaceState::Countdown,
+        3 => RaceState::Green,
+        4 => RaceState::Finished,
+        _ => return None,
+    })
+}
+
+#[derive(Default)]
+pub(crate) struct ReplayWriter(pub(crate) Vec<u8>);
+
+impl ReplayWriter {
+    pub(crate) fn u8(&mut self, v: u8) {
+        self.0.push(v);
+    }
+    pub(crate) fn u32(&mut self, v: u32) {
+        self.0.extend(v.to_le_bytes());
+    }
+    pub(crate) fn u64(&mut self, v: u64) {
+        self.0.extend(v.to_le_bytes());
+    }
+    pub(crate) fn f32(&mut self, v: f32) {
+        self.0.extend(v.to_le_bytes());
+    }
+    // u32 length and utf8 bytes
+    pub(crate) fn str(&mut self, v: &str) {
+        self.u32(v.len() as u32);
+        self.0.extend(v.as_bytes());
+    }
+    pub(crate) fn transform(&mut self, t: &Transform) {
+        for v in t
+            .translation
+            .to_array()
+            .into_iter()
+            .chain(t.rotation.to_array())
+        {
+            self.f32(v);
+        }
+    }
+}
+
+pub(crate) struct ReplayReader<'a> {
+    pub(crate) bytes: &'a [u8],
+    pub(crate) at: usize,
+}
+
+impl ReplayReader<'_> {
+    pub(crate) fn take<const N: usize>(&mut self) -> Option<[u8; N]> {
+        let bytes = self.bytes.get(self.at..self.at + N)?.try_into().ok()?;
+        self.at += N;
+        Some(bytes)
+    }
+    pub(crate) fn u8(&mut self) -> Option<u8> {
+        Some(self.take::<1>()?[0])
+    }
+    pub(crate) fn u32(&mut self) -> Option<u32> {
+        Some(u32::from_le_bytes(self.take()?))
+    }
+    pub(crate) fn u64(&mut self) -> Option<u64> {
+        Some(u64::from_le_bytes(self.take()?))
+    }
+    pub(crate) fn f32(&mut self) -> Option<f32> {
+        Some(f32::from_le_bytes(self.take()?))
+    }
+    pub(crate) fn str(&mut self) -> Option<String> {
+        let len = self.u32()? as usize;
+        let bytes = self.bytes.get(self.at..self.at + len)?;
+        self.at += len;
+        String::from_utf8(bytes.to_vec()).ok()
+    }
+    pub(crate) fn transform(&mut self) -> Option<Transform> {
+        let translation = Vec3::new(self.f32()?, self.f32()?, self.f32()?);
+        let rotation = Quat::from_xyzw(self.f32()?, self.f32()?, self.f32()?, self.f32()?);
+        Some(Transform::from_translation(translation).with_rotation(rotation))
+    }
+}
+
+pub fn load_replay(path: &PathBuf) -> Option<Replay> {
+    Replay::decode(&fs::read(path).ok()?)
+}
+
+pub fn save_replay(path: &PathBuf, replay: &Replay) -> Result<(), String> {
+    fs::write(path, replay.encode()).map_err(|e| e.to_string())
+}
+
+// REPLAY_RECORD=path records the session, saved at the race result and on exit,
+// REPLAY_RATE=frames per second
+#[derive(Resource, Debug)]
+pub struct ReplayRecorder {
+    pub path: PathBuf,
+    pub replay: Replay,
+    started_at: Option<f64>,
+    race_state: Option<RaceState>,
+}
+
+impl ReplayRecorder {
+    pub fn from_env() -> Option<Self> {
+        let path = PathBuf::from(std::env::var("REPLAY_RECORD").ok()?);
+        let rate = std::env::var("REPLAY_RATE")
+            .ok()
+            .and_then(|v| v.parse::<f32>().ok())
+            .unwrap_or(30.);
+        Some(Self {
+            path,
+            replay: Replay { rate, ..default() },
+            started_at: None,
+            race_state: None,
+        })
+    }
+    fn time(&self, seconds: f64) -> f32 {
+        self.started_at.map_or(0., |at| (seconds - at) as f32)
+    }
+    pub fn save(&self) {
+        match save_replay(&self.path, &self.replay) {
+            Ok(_) => println!(
+                "replay saved {:?}, {} frames {:.1}s",
+                self.path,
+                self.replay.frames.len(),
+                self.replay.duration()
+            ),
+            Err(err) => println!("replay save error {:?}: {err}", self.path),
+        }
+    }
+}
+
+pub fn replay_record_system(
+    time: Res<Time>,
+    mut recorder: ResMut<ReplayRecorder>,
+    q_car: Query<(&Car, &CarTrack, &Transform, &CarWheels, Has<Player>)>,
+    q_wheel: Query<&Transform>,
+) {
+    let seconds = time.elapsed_seconds_f64();
+    let started_at = *recorder.started_at.get_or_insert(seconds);
+    let frame_time = (seconds - started_at) as f32;
+    let next = recorder.replay.frames.len() as f32 / recorder.replay.rate.max(1.);
+    if frame_time < next {
+        return;
+    }
+    let cars = q_car
+        .iter()
+        .map(|(car, car_track, transform, wheels, player)| ReplayCar {
+            index: car_track.index,
+            player,
+            body: *transform,
+            wheels: wheels
+                .entities
+                .map(|w| q_wheel.get(w).copied().unwrap_or_default()),
+            gas: car.gas,
+            brake: car.brake,
+            steering: car.steering,
+        })
+        .collect();
+    recorder.replay.frames.push(ReplayFrame {
+        time: frame_time,
+        cars,
+    });
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn replay_record_events_system(
+    time: Res<Time>,
+    mut recorder: ResMut<ReplayRecorder>,
+    session: Option<Res<RaceSession>>,
+    q_car_track: Query<&CarTrack>,
+    mut lap_events: EventReader<LapCompleted>,
+    mut limits_events: EventReader<TrackLimitsViolation>,
+    mut wrong_way_events: EventReader<WrongWay>,
+    mut pit_events: EventReader<PitStopCompleted>,
+) {
+    let t = recorder.time(time.elapsed_seconds_f64());
+    let index = |e: Entity| q_car_track.get(e).map_or(0, |car_track| car_track.index);
+    let mut events: Vec<ReplayEvent> = vec![];
+    let mut push = |index: usize, kind: ReplayEventKind| {
+        events.push(ReplayEvent {
+            time: t,
+            index,
+            kind,
+        })
+    };
+    if let Some(session) = session {
+        if recorder.race_state != Some(session.state) {
+            recorder.race_state = Some(session.state);
+            push(0, ReplayEventKind::RaceState(session.state));
+        }
+    }
+    for event in lap_events.read() {
+        let kind = ReplayEventKind::LapCompleted {
+            lap: event.lap,
+            time: event.time,
+            valid: event.valid,
+        };
+        push(index(event.entity), kind);
+    }
+    for event in limits_events.read() {
+        let kind = ReplayEventKind::TrackLimits {
+            warnings: event.warnings,
+            penalty: event.penalty,
+        };
+        push(index(event.entity), kind);
+    }
+    for event in wrong_way_events.read() {
+        push(
+            index(event.entity),
+            ReplayEventKind::WrongWay { gate: event.gate },
+        );
+    }
+    for event in pit_events.read() {
+        let kind = ReplayEventKind::PitStop {
+            duration: event.duration,
+        };
+        push(index(event.entity), kind);
+    }
+    recorder.replay.events.extend(events);
+}
+
+pub fn replay_save_system(
+    recorder: Res<ReplayRecorder>,
+    mut result_events: EventReader<RaceResult>,
+    mut exit_events: EventReader<AppExit>,
+) {
+    let result = result_events.read().count() > 0;
+    let exit = exit_events.read().count() > 0;
+    if result || exit {
+        recorder.save();
+    }
+}
+
+// REPLAY_PLAY=path plays the recording back with physics disabled
+#[derive(Resource, Debug)]
+pub struct ReplayPlayback {
+    pub replay: Replay,
+    // seconds
+    pub time: f32,
+    pub speed: f32,
+    pub paused: bool,
+    pub reverse: bool,
+}
+
+impl ReplayPlayback {
+    pub fn from_env() -> Option<Self> {
+        let path = PathBuf::from(std::env::var("REPLAY_PLAY").ok()?);
+        let Some(replay) = load_replay(&path) else {
+            println!("replay load error {path:?}");
+            return None;
+        };
+        println!(
+            "replay loaded {path:?}, {} frames {:.1}s",
+            replay.frames.len(),
+            replay.duration()
+        );
+        Some(Self {
+            replay,
+            time: 0.,
+            speed: 1.,
+            paused: false,
+            reverse: false,
+        })
+    }
+    pub fn toggle_pause(&mut self) {
+        self.paused = !self.paused;
+    }
+    pub fn toggle_reverse(&mut self) {
+        self.reverse = !self.reverse;
+    }
+    // multiplies the speed, 1/16 to 16
+    pub fn scale_speed(&mut self, scale: f32) {
+        self.speed = (self.speed * scale).clamp(1. / 16., 16.);
+    }
+    pub fn seek(&mut self, time: f32) {
+        self.time = time.clamp(0., self.replay.duration());
+    }
+    // seek steps back or forward
+    pub fn step(&mut self, steps: f32) {
+        self.seek(self.time + steps * REPLAY_SEEK);
+    }
+}
+
+pub fn replay_playback_start_system(mut rapier_config: ResMut<RapierConfiguration>) {
+    rapier_config.physics_pipeline_active = false;
+}
+
+// replay cars are spawned at their recorded transform when they first appear,
+// the usual startup spawns are skipped during playback
+pub fn replay_spawn_system(
+    playback: Res<ReplayPlayback>,
+    q_car_track: Query<&CarTrack>,
+    mut requested: Local<Vec<usize>>,
+    mut car_spawn_events: EventWriter<SpawnCarOnTrackEvent>,
+) {
+    let cars = playback.replay.cars_at(playback.time);
+    for car in cars {
+        let spawned = q_car_track.iter().any(|t| t.index == car.index);
+        if spawned || requested.contains(&car.index) {
+            continue;
+        }
+        requested.push(car.index);
+        car_spawn_events.send(SpawnCarOnTrackEvent {
+            player: car.player,
+            index: car.index,
+            position: None,
+            slot: Some(crate::GridSlot {
+                position: 0,
+                index: car.index,
+                meters: 0.,
+                transform: car.body,
+                velocity: Vec3::ZERO,
+            }),
+        });
+    }
+}
+
+// moves cars kinematically, controls are shown as recorded, events are printed when passed
+pub fn replay_playback_system(
+    time: Res<Time>,
+    mut playback: ResMut<ReplayPlayback>,
+    mut q_car: Query<(
+        &mut Car,
+        &CarTrack,
+        &mut Transform,
+        &mut Velocity,
+        &CarWheels,
+    )>,
+    mut q_wheel: Query<&mut Transform, Without<Car>>,
+) {
+    let prev_time = playback.time;
+    if !playback.paused {
+        let direction = if playback.reverse { -1. } else { 1. };
+        let time = playback.time + time.delta_seconds() * playback.speed * direction;
+        playback.seek(time);
+    }
+    let dt = playback.time - prev_time;
+    if dt != 0. {
+        let (from, to) = (prev_time.min(playback.time), prev_time.max(playback.time));
+        for event in playback.replay.events.iter() {
+            if event.time > from && event.time <= to {
+                println!(
+                    "replay {:.2}s car {} {:?}",
+                    event.time, event.index, event.kind
+                );
+            }
+        }
+    }
+    let cars = playback.replay.cars_at(playback.time);
+    for (mut car, car_track, mut transform, mut v, wheels) in q_car.iter_mut() {
+        let Some(replay_car) = cars.iter().find(|c| c.index == car_track.index) else {
+            continue;
+        };
+        if dt != 0. {
+            v.linvel = (replay_car.body.translation - transform.translation) / dt.abs();
+        } else {
+            v.linvel = Vec3::ZERO;
+        }
+        *transform = replay_car.body;
+        car.gas = replay_car.gas;
+        car.brake = replay_car.brake;
+        car.steering = replay_car.steering;
+        for (wheel, replay_wheel) in wheels.entities.iter().zip(replay_car.wheels) {
+            if let Ok(mut wheel_transform) = q_wheel.get_mut(*wheel) {
+                *wheel_transform = replay_wheel;
+            }
+        }
+    }
+}
+
+pub struct ReplayPlugin;
+
+impl Plugin for ReplayPlugin {
+    fn build(&self, app: &mut App) {
+        if let Some(playback) = ReplayPlayback::from_env() {
+            app.insert_resource(playback)
+                .add_systems(Startup, replay_playback_start_system)
+                .add_systems(
+                    Update,
+                    (
+                        replay_spawn_system,
+                        replay_playback_system.after(CarSet::Esp),
+                    ),
+                );
+        } else if let Some(recorder) = ReplayRecorder::from_env() {
+            app.insert_resource(recorder)
+                .add_systems(
+                    Update,
+                    (
+                        replay_record_system.after(CarSet::Esp),
+                        replay_record_events_system.after(crate::timing_system),
+                    ),
+                )
+                .add_systems(Last, replay_save_system);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn car(index: usize, x: f32) -> ReplayCar {
+        let transform = Transform::from_xyz(x, 0.5, 2. * x).with_rotation(Quat::from_rotation_y(x));
+        ReplayCar {
+            index,
+            player: index == 0,
+            body: transform,
+            wheels: [0, 1, 2, 3].map(|i| transform.with_translation(Vec3::splat(i as f32))),
+            gas: x / 10.,
+            brake: 0.25,
+            steering: -x / 10.,
+        }
+    }
+
+    fn replay() -> Replay {
+        Replay {
+            rate: 20.,
+            frames: vec![
+                ReplayFrame {
+                    time: 0.,
+                    cars: vec![car(0, 0.), car(1, 1.)],
+                },
+                ReplayFrame {
+                    time: 1.,
+                    cars: vec![car(0, 2.)],
+                },
+            ],
+            events: vec![
+                ReplayEvent {
+                    time: 0.5,
+                    index: 0,
+                    kind: ReplayEventKind::RaceState(RaceState::Green),
+                },
+                ReplayEvent {
+                    time: 0.75,
+                    index: 1,
+                    kind: ReplayEventKind::LapCompleted {
+                        lap: 3,
+                        time: 91.5,
+                        valid: false,
+                    },
+                },
+                ReplayEvent {
+                    time: 0.8,
+                    index: 1,
+                    kind: ReplayEventKind::TrackLimits {
+                        warnings: 3,
+                        penalty: Some(5.),
+                    },
+                },
+                ReplayEvent {
+                    time: 0.9,
+                    index: 0,
+                    kind: ReplayEventKind::WrongWay { gate: 7 },
+                },
+                ReplayEvent {
+                    time: 1.,
+                    index: 0,
+                    kind: ReplayEventKind::PitStop { duration: 12.5 },
+                },
+            ],
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let replay = replay();
+        let decoded = Replay::decode(&replay.encode()).unwrap();
+        assert_eq!(decoded.rate, 20.);
+        assert_eq!(decoded.frames.len(), 2);
+        for (a, b) in replay.frames.iter().zip(decoded.frames.iter()) {
+            assert_eq!(a.time, b.time);
+            assert_eq!(a.cars.len(), b.cars.len());
+            for (a, b) in a.cars.iter().zip(b.cars.iter()) {
+                assert_eq!((a.index, a.player), (b.index, b.player));
+                assert_eq!(a.body, b.body);
+                assert_eq!(a.wheels, b.wheels);
+                assert_eq!((a.gas, a.brake, a.steering), (b.gas, b.brake, b.steering));
+            }
+        }
+        assert_eq!(decoded.events.len(), replay.events.len());
+        let kinds: Vec<String> = decoded.events.iter().map(|e| format!("{e:?}")).collect();
+        let expected: Vec<String> = replay.events.iter().map(|e| format!("{e:?}")).collect();
+        assert_eq!(kinds, expected);
+    }
+
+    #[test]
+    fn rejects_truncated_and_foreign() {
+        let bytes = replay().encode();
+        assert!(Replay::decode(&bytes[..bytes.len() - 1]).is_none());
+        let mut foreign = bytes.clone();
+        foreign[4] = REPLAY_VERSION + 1;
+        assert!(Replay::decode(&foreign).is_none());
+    }
+
+    #[test]
+    fn cars_at_interpolates() {
+        let replay = replay();
+        assert_eq!(replay.duration(), 1.);
+        let cars = replay.cars_at(0.5);
+        assert_eq!(cars.len(), 2);
+        assert!((cars[0].body.translation.x - 1.).abs() < 1e-5);
+        assert!((cars[0].gas - 0.1).abs() < 1e-5);
+        // car 1 is missing in the next frame and held
+        assert_eq!(cars[1].body, car(1, 1.).body);
+        // held at the last frame
+        let last = replay.cars_at(5.)[0].body;
+        assert!(last.translation.distance(car(0, 2.).body.translation) < 1e-5);
+        assert!(Replay::default().cars_at(0.).is_empty());
+    }
+}
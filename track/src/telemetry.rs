@@ -0,0 +1,437 @@
+use crate::CarTrack;
+use bevy::{app::AppExit, prelude::*, utils::HashMap};
+use bevy_garage_car::{Car, CarSet, CarSpec, CarWheels, Wheel};
+use bevy_rapier3d::prelude::*;
+use std::{
+    fs,
+    io::Write,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+const G: f32 = 9.81;
+
+pub struct TelemetryChannel {
+    pub name: &'static str,
+    // up to 8 characters
+    pub short: &'static str,
+    pub unit: &'static str,
+}
+
+const fn channel(name: &'static str, short: &'static str, unit: &'static str) -> TelemetryChannel {
+    TelemetryChannel { name, short, unit }
+}
+
+// wheels in CarSpec mount order
+pub const TELEMETRY_CHANNELS: [TelemetryChannel; 29] = [
+    channel("Time", "Time", "s"),
+    channel("Lap Distance", "LapDist", "m"),
+    channel("Lap Number", "Lap", ""),
+    channel("Position X", "PosX", "m"),
+    channel("Position Y", "PosY", "m"),
+    channel("Position Z", "PosZ", "m"),
+    channel("Ground Speed", "Speed", "km/h"),
+    channel("Throttle Pos", "Throttle", "%"),
+    channel("Brake Pos", "Brake", "%"),
+    channel("Steering", "Steer", "%"),
+    channel("Yaw Rate", "YawRate", "deg/s"),
+    channel("G Force Lat", "GLat", "G"),
+    channel("G Force Long", "GLong", "G"),
+    channel("Wheel Speed FR", "WSpdFR", "rad/s"),
+    channel("Wheel Speed FL", "WSpdFL", "rad/s"),
+    channel("Wheel Speed RR", "WSpdRR", "rad/s"),
+    channel("Wheel Speed RL", "WSpdRL", "rad/s"),
+    channel("Wheel Slip FR", "SlipFR", "ratio"),
+    channel("Wheel Slip FL", "SlipFL", "ratio"),
+    channel("Wheel Slip RR", "SlipRR", "ratio"),
+    channel("Wheel Slip RL", "SlipRL", "ratio"),
+    channel("Wheel Load FR", "LoadFR", "N"),
+    channel("Wheel Load FL", "LoadFL", "N"),
+    channel("Wheel Load RR", "LoadRR", "N"),
+    channel("Wheel Load RL", "LoadRL", "N"),
+    channel("Susp Pos FR", "SuspFR", "mm"),
+    channel("Susp Pos FL", "SuspFL", "mm"),
+    channel("Susp Pos RR", "SuspRR", "mm"),
+    channel("Susp Pos RL", "SuspRL", "mm"),
+];
+
+// first wheel channel, then 4 channels per wheel quantity
+pub const TELEMETRY_WHEELS: usize = 13;
+
+pub type TelemetryValues = [f32; TELEMETRY_CHANNELS.len()];
+
+// one sample of every channel, sent per car at the telemetry rate
+#[derive(Event, Debug, Clone)]
+pub struct TelemetrySample {
+    pub entity: Entity,
+    // CarTrack index
+    pub index: usize,
+    pub values: TelemetryValues,
+}
+
+impl TelemetrySample {
+    pub fn get(&self, short: &str) -> f32 {
+        TELEMETRY_CHANNELS
+            .iter()
+            .position(|c| c.short == short)
+            .map_or(0., |i| self.values[i])
+    }
+}
+
+// TELEMETRY_RATE=samples per second
+#[derive(Resource, Debug, Clone)]
+pub struct TelemetryConfig {
+    pub rate: f32,
+}
+
+impl TelemetryConfig {
+    pub fn from_env() -> Self {
+        let rate = std::env::var("TELEMETRY_RATE")
+            .ok()
+            .and_then(|v| v.parse::<f32>().ok())
+            .unwrap_or(50.);
+        Self { rate }
+    }
+}
+
+#[derive(Component, Debug, Default)]
+pub struct TelemetryState {
+    prev_linvel: Option<Vec3>,
+    next_at: f64,
+}
+
+pub fn add_telemetry_state_system(q_car: Query<Entity, Added<CarTrack>>, mut cmd: Commands) {
+    for e in q_car.iter() {
+        cmd.entity(e).insert(TelemetryState::default());
+    }
+}
+
+#[allow(clippy::type_complexity)]
+pub fn telemetry_system(
+    time: Res<Time>,
+    config: Res<TelemetryConfig>,
+    context: Res<RapierContext>,
+    mut q_car: Query<(
+        Entity,
+        &Car,
+        &CarTrack,
+        &CarSpec,
+        &CarWheels,
+        &Transform,
+        &Velocity,
+        &mut TelemetryState,
+    )>,
+    q_wheel: Query<(&Wheel, &Transform, &Velocity)>,
+    mut sample_events: EventWriter<TelemetrySample>,
+) {
+    let seconds = time.elapsed_seconds_f64();
+    let dt = time.delta_seconds();
+    // impulses are from the last substep
+    let step = context.integration_parameters.dt.max(f32::EPSILON);
+    for (e, car, car_track, spec, wheels, t, v, mut state) in q_car.iter_mut() {
+        let accel = match (state.prev_linvel, dt > 0.) {
+            (Some(prev_linvel), true) => (v.linvel - prev_linvel) / dt,
+            _ => Vec3::ZERO,
+        };
+        state.prev_linvel = Some(v.linvel);
+        // fixed cadence at the declared rate, a frame longer than the period repeats the sample
+        let period = 1. / config.rate.max(1.) as f64;
+        if state.next_at <= 0. || seconds - state.next_at > 1. {
+            state.next_at = seconds;
+        }
+        let due_at = state.next_at;
+        let mut count = 0;
+        while state.next_at <= seconds {
+            state.next_at += period;
+            count += 1;
+        }
+        if count == 0 {
+            continue;
+        }
+
+        let forward = t.rotation.mul_vec3(Vec3::Z);
+        let left = t.rotation.mul_vec3(Vec3::X);
+        let up = t.rotation.mul_vec3(Vec3::Y);
+        let mut values: TelemetryValues = [0.; TELEMETRY_CHANNELS.len()];
+        let head = [
+            seconds as f32,
+            car_track.track_position,
+            car_track.lap as f32,
+            t.translation.x,
+            t.translation.y,
+            t.translation.z,
+            v.linvel.length() * 3.6,
+            car.gas * 100.,
+            car.brake * 100.,
+            car.steering * 100.,
+            v.angvel.dot(up).to_degrees(),
+            // positive to the left
+            accel.dot(left) / G,
+            accel.dot(forward) / G,
+        ];
+        values[..TELEMETRY_WHEELS].copy_from_slice(&head);
+        let inverse_rotation = t.rotation.inverse();
+        for (i, wheel_entity) in wheels.entities.iter().enumerate() {
+            let Ok((wheel, wheel_t, wheel_v)) = q_wheel.get(*wheel_entity) else {
+                continue;
+            };
+            // contact patch speed from the spin relative to the body, positive forward
+            let roll_speed = (wheel_v.angvel - v.angvel).cross(up).dot(forward) * wheel.radius;
+            let ground_speed = wheel_v.linvel.dot(forward);
+            let slip = (roll_speed - ground_speed) / ground_speed.abs().max(1.);
+            let load = context
+                .contact_pairs_with(*wheel_entity)
+                .map(|pair| {
+                    pair.manifolds()
+                        .map(|m| m.points().map(|p| p.impulse()).sum::<f32>())
+                        .sum::<f32>()
+                })
+                .sum::<f32>()
+                / step;
+            let local = inverse_rotation.mul_vec3(wheel_t.translation - t.translation);
+            let compression = local.y - spec.wheel_mount[i].anchor.y;
+            values[TELEMETRY_WHEELS + i] = roll_speed / wheel.radius;
+            values[TELEMETRY_WHEELS + 4 + i] = slip;
+            values[TELEMETRY_WHEELS + 8 + i] = load;
+            values[TELEMETRY_WHEELS + 12 + i] = compression * 1000.;
+        }
+        for i in 0..count {
+            values[0] = (due_at + i as f64 * period) as f32;
+            sample_events.send(TelemetrySample {
+                entity: e,
+                index: car_track.index,
+                values,
+            });
+        }
+    }
+}
+
+// TELEMETRY_DIR=path keeps every sample, written per car as CSV and MoTeC .ld on exit
+#[derive(Resource, Debug)]
+pub struct TelemetryLogger {
+    pub dir: PathBuf,
+    pub cars: HashMap<usize, Vec<TelemetryValues>>,
+}
+
+impl TelemetryLogger {
+    pub fn from_env() -> Option<Self> {
+        let dir = PathBuf::from(std::env::var("TELEMETRY_DIR").ok()?);
+        Some(Self {
+            dir,
+            cars: HashMap::default(),
+        })
+    }
+    pub fn save(&self, rate: f32) -> Result<(), String> {
+        fs::create_dir_all(&self.dir).map_err(|e| e.to_string())?;
+        for (index, samples) in self.cars.iter() {
+            let path = self.dir.join(format!("car-{index}"));
+            write_csv(&path.with_extension("csv"), samples).map_err(|e| e.to_string())?;
+            let vehicle = format!("car {index}");
+            write_ld(&path.with_extension("ld"), samples, rate, &vehicle)
+                .map_err(|e| e.to_string())?;
+        }
+        Ok(())
+    }
+}
+
+pub fn telemetry_log_system(
+    mut logger: ResMut<TelemetryLogger>,
+    mut sample_events: EventReader<TelemetrySample>,
+) {
+    for sample in sample_events.read() {
+        logger
+            .cars
+            .entry(sample.index)
+            .or_default()
+            .push(sample.values);
+    }
+}
+
+pub fn telemetry_save_system(
+    logger: Res<TelemetryLogger>,
+    config: Res<TelemetryConfig>,
+    mut exit_events: EventReader<AppExit>,
+) {
+    if exit_events.read().count() == 0 {
+        return;
+    }
+    match logger.save(config.rate) {
+        Ok(_) => println!(
+            "telemetry saved {:?}, {} cars",
+            logger.dir,
+            logger.cars.len()
+        ),
+        Err(err) => println!("telemetry save error {:?}: {err}", logger.dir),
+    }
+}
+
+pub fn write_csv(path: &Path, samples: &[TelemetryValues]) -> std::io::Result<()> {
+    let mut file = std::io::BufWriter::new(fs::File::create(path)?);
+    let header: Vec<String> = TELEMETRY_CHANNELS
+        .iter()
+        .map(|c| match c.unit {
+            "" => c.name.to_string(),
+            unit => format!("{} [{unit}]", c.name),
+        })
+        .collect();
+    writeln!(file, "{}", header.join(","))?;
+    for values in samples.iter() {
+        let row: Vec<String> = values.iter().map(|v| v.to_string()).collect();
+        writeln!(file, "{}", row.join(","))?;
+    }
+    file.flush()
+}
+
+// MoTeC i2 log: header, event, linked channel descriptors, float32 data per channel
+const LD_HEADER_SIZE: u32 = 1762;
+const LD_EVENT_SIZE: u32 = 1154;
+const LD_CHANNEL_SIZE: u32 = 124;
+
+fn ld_str(bytes: &mut Vec<u8>, s: &str, size: usize) {
+    let mut field = s.as_bytes().to_vec();
+    field.resize(size, 0);
+    bytes.extend(field);
+}
+
+fn ld_zeros(bytes: &mut Vec<u8>, size: usize) {
+    bytes.resize(bytes.len() + size, 0);
+}
+
+pub fn write_ld(
+    path: &Path,
+    samples: &[TelemetryValues],
+    rate: f32,
+    vehicle: &str,
+) -> std::io::Result<()> {
+    let channels = TELEMETRY_CHANNELS.len() as u32;
+    let event_ptr = LD_HEADER_SIZE;
+    let meta_ptr = event_ptr + LD_EVENT_SIZE;
+    let data_ptr = meta_ptr + channels * LD_CHANNEL_SIZE;
+    let (date, clock) = ld_date_time();
+    let mut b: Vec<u8> = vec![];
+
+    b.extend(0x40u32.to_le_bytes());
+    ld_zeros(&mut b, 4);
+    b.extend(meta_ptr.to_le_bytes());
+    b.extend(data_ptr.to_le_bytes());
+    ld_zeros(&mut b, 20);
+    b.extend(event_ptr.to_le_bytes());
+    ld_zeros(&mut b, 24);
+    for v in [1u16, 0x4240, 0xf] {
+        b.extend(v.to_le_bytes());
+    }
+    // device serial, type and version
+    b.extend(0x1f44u32.to_le_bytes());
+    ld_str(&mut b, "ADL", 8);
+    b.extend(420u16.to_le_bytes());
+    b.extend(0xadb0u16.to_le_bytes());
+    b.extend(channels.to_le_bytes());
+    ld_zeros(&mut b, 4);
+    ld_str(&mut b, &date, 16);
+    ld_zeros(&mut b, 16);
+    ld_str(&mut b, &clock, 16);
+    ld_zeros(&mut b, 16);
+    ld_str(&mut b, "bevy_garage", 64);
+    ld_str(&mut b, vehicle, 64);
+    ld_zeros(&mut b, 64);
+    ld_str(&mut b, "bevy_garage", 64);
+    ld_zeros(&mut b, 64 + 1024);
+    b.extend(0xc81a4u32.to_le_bytes());
+    ld_zeros(&mut b, 66);
+    ld_str(&mut b, "telemetry", 64);
+    ld_zeros(&mut b, 126);
+    debug_assert_eq!(b.len() as u32, LD_HEADER_SIZE);
+
+    // event name, session, comment, no venue
+    ld_str(&mut b, "bevy_garage", 64);
+    ld_str(&mut b, "session", 64);
+    ld_zeros(&mut b, 1024);
+    b.extend(0u16.to_le_bytes());
+
+    let n = samples.len() as u32;
+    for i in 0..channels {
+        let channel = &TELEMETRY_CHANNELS[i as usize];
+        let prev = match i {
+            0 => 0,
+            i => meta_ptr + (i - 1) * LD_CHANNEL_SIZE,
+        };
+        let next = match i + 1 < channels {
+            true => meta_ptr + (i + 1) * LD_CHANNEL_SIZE,
+            false => 0,
+        };
+        for v in [prev, next, data_ptr + i * n * 4, n] {
+            b.extend(v.to_le_bytes());
+        }
+        b.extend((0x2ee1 + i as u16).to_le_bytes());
+        // float32 data, frequency
+        for v in [0x07u16, 4, rate.round() as u16] {
+            b.extend(v.to_le_bytes());
+        }
+        // shift, multiplier, scale, decimal places
+        for v in [0i16, 1, 1, 0] {
+            b.extend(v.to_le_bytes());
+        }
+        ld_str(&mut b, channel.name, 32);
+        ld_str(&mut b, channel.short, 8);
+        ld_str(&mut b, channel.unit, 12);
+        ld_zeros(&mut b, 40);
+    }
+    for i in 0..channels as usize {
+        for values in samples.iter() {
+            b.extend(values[i].to_le_bytes());
+        }
+    }
+    fs::write(path, b)
+}
+
+// dd/mm/yyyy and hh:mm:ss in UTC
+fn ld_date_time() -> (String, String) {
+    let secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs()) as i64;
+    let (days, rem) = (secs.div_euclid(86400), secs.rem_euclid(86400));
+    // civil from days, proleptic gregorian
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + (month <= 2) as i64;
+    (
+        format!("{day:02}/{month:02}/{year}"),
+        format!("{:02}:{:02}:{:02}", rem / 3600, rem / 60 % 60, rem % 60),
+    )
+}
+
+pub struct TelemetryPlugin;
+
+impl Plugin for TelemetryPlugin {
+    fn build(&self, app: &mut App) {
+        let logger = TelemetryLogger::from_env();
+        let udp = crate::UdpTelemetry::from_env();
+        if logger.is_none() && udp.is_none() {
+            return;
+        }
+        app.insert_resource(TelemetryConfig::from_env())
+            .add_event::<TelemetrySample>()
+            .add_systems(
+                Update,
+                (
+                    add_telemetry_state_system,
+                    telemetry_system.after(CarSet::Esp),
+                ),
+            );
+        if let Some(logger) = logger {
+            app.insert_resource(logger)
+                .add_systems(Update, telemetry_log_system.after(telemetry_system))
+                .add_systems(Last, telemetry_save_system);
+        }
+        if let Some(udp) = udp {
+            app.insert_resource(udp)
+                .add_systems(Update, crate::udp_telemetry_system.after(telemetry_system));
+        }
+    }
+}
@@ -0,0 +1,268 @@
+use crate::{CarTrack, GateCrossed, TrackConfig};
+use bevy::{prelude::*, utils::HashMap};
+
+// meters between delta trace samples
+const TRACE_STEP: f32 = 2.;
+// position change per frame treated as a respawn
+const TELEPORT_METERS: f32 = 50.;
+
+// sector ends in meters from the start line, the last sector ends at the line
+#[derive(Resource, Debug, Clone, Default)]
+pub struct TimingConfig {
+    pub sectors: Vec<f32>,
+}
+
+impl TimingConfig {
+    pub fn equal(track_length: f32, count: usize) -> Self {
+        let count = count.max(1);
+        Self {
+            sectors: (1..count)
+                .map(|i| track_length * i as f32 / count as f32)
+                .collect(),
+        }
+    }
+    pub fn sector_count(&self) -> usize {
+        self.sectors.len() + 1
+    }
+    pub fn sector_at(&self, meters: f32) -> usize {
+        self.sectors.iter().take_while(|s| **s <= meters).count()
+    }
+}
+
+// lap distance and seconds samples, used to interpolate the best lap at any point
+#[derive(Debug, Clone, Default)]
+pub struct LapTrace {
+    pub samples: Vec<(f32, f32)>,
+}
+
+impl LapTrace {
+    pub fn push(&mut self, meters: f32, seconds: f32) {
+        match self.samples.last() {
+            Some((last, _)) if meters < last + TRACE_STEP => {}
+            _ => self.samples.push((meters, seconds)),
+        }
+    }
+    pub fn seconds_at(&self, meters: f32) -> Option<f32> {
+        let i = self.samples.partition_point(|(m, _)| *m < meters);
+        let (m1, s1) = *self.samples.get(i)?;
+        let Some((m0, s0)) = i.checked_sub(1).map(|i| self.samples[i]) else {
+            return Some(s1);
+        };
+        let t = (meters - m0) / (m1 - m0).max(f32::EPSILON);
+        Some(s0 + (s1 - s0) * t)
+    }
+}
+
+#[derive(Component, Debug, Clone, Default)]
+pub struct LapTiming {
+    // completed timed laps
+    pub laps: usize,
+    // None until the car crosses the start line
+    pub lap_started_at: Option<f64>,
+    pub sector: usize,
+    pub sector_started_at: f64,
+    pub sector_times: Vec<f32>,
+    // track limits exceeded, the lap is not counted for best times
+    pub invalid: bool,
+    pub last_lap: Option<f32>,
+    pub best_lap: Option<f32>,
+    pub best_sectors: Vec<Option<f32>>,
+    // seconds against the personal best at the same distance, negative is faster
+    pub delta: Option<f32>,
+    pub trace: LapTrace,
+    pub best_trace: LapTrace,
+    // seconds spent in the pit lane on the current lap
+    pub pit_time: f32,
+    prev_position: Option<f32>,
+}
+
+impl LapTiming {
+    pub fn current(&self, seconds: f64) -> Option<f32> {
+        self.lap_started_at.map(|at| (seconds - at) as f32)
+    }
+    fn start_lap(&mut self, seconds: f64) {
+        self.lap_started_at = Some(seconds);
+        self.sector = 0;
+        self.sector_started_at = seconds;
+        self.sector_times.clear();
+        self.invalid = false;
+        self.pit_time = 0.;
+        self.trace = LapTrace::default();
+        self.trace.push(0., 0.);
+    }
+    // out lap until the next line crossing
+    pub fn abort_lap(&mut self) {
+        self.lap_started_at = None;
+        self.delta = None;
+        self.sector_times.clear();
+    }
+}
+
+#[derive(Event, Debug, Clone)]
+pub struct SectorCompleted {
+    pub entity: Entity,
+    pub lap: usize,
+    pub sector: usize,
+    pub time: f32,
+    pub valid: bool,
+    pub personal_best: bool,
+}
+
+#[derive(Event, Debug, Clone)]
+pub struct LapCompleted {
+    pub entity: Entity,
+    // 1 based number of the completed lap
+    pub lap: usize,
+    pub time: f32,
+    pub sectors: Vec<f32>,
+    pub valid: bool,
+    pub personal_best: bool,
+    // pit lane seconds included in the lap time
+    pub pit_time: f32,
+}
+
+// m:ss.mmm
+pub fn format_lap_time(seconds: f32) -> String {
+    let minutes = (seconds / 60.).floor();
+    format!("{minutes:.0}:{:06.3}", seconds - minutes * 60.)
+}
+
+pub fn timing_config_start_system(
+    mut cmd: Commands,
+    track_config: Res<TrackConfig>,
+    timing_config: Option<Res<TimingConfig>>,
+) {
+    if timing_config.is_none() {
+        cmd.insert_resource(TimingConfig::equal(track_config.track_length, 3));
+    }
+}
+
+pub fn add_lap_timing_system(q_car: Query<Entity, Added<CarTrack>>, mut cmd: Commands) {
+    for e in q_car.iter() {
+        cmd.entity(e).insert(LapTiming::default());
+    }
+}
+
+pub fn timing_system(
+    time: Res<Time>,
+    track_config: Res<TrackConfig>,
+    timing_config: Res<TimingConfig>,
+    mut q_car: Query<(Entity, &CarTrack, &mut LapTiming)>,
+    mut gate_events: EventReader<GateCrossed>,
+    mut sector_events: EventWriter<SectorCompleted>,
+    mut lap_events: EventWriter<LapCompleted>,
+) {
+    let seconds = time.elapsed_seconds_f64();
+    let length = track_config.track_length;
+    // forward over the start line, false when gates were missed
+    let line_crossings: HashMap<Entity, bool> = gate_events
+        .read()
+        .filter(|gate| gate.gate == 0 && !gate.backward)
+        .map(|gate| (gate.entity, gate.counted))
+        .collect();
+    let sectors = timing_config.sector_count();
+    for (e, car_track, mut timing) in q_car.iter_mut() {
+        let timing = timing.as_mut();
+        let position = car_track.track_position;
+        let Some(prev_position) = timing.prev_position.replace(position) else {
+            // spawned on the line starts a lap, otherwise out lap
+            if position < TELEPORT_METERS {
+                timing.start_lap(seconds);
+            }
+            continue;
+        };
+        let crossed = match line_crossings.get(&e) {
+            Some(&counted) => {
+                // gates skipped, the lap is not counted for best times
+                timing.invalid |= !counted;
+                true
+            }
+            None => false,
+        };
+        let jump = (position - prev_position).abs();
+        if !crossed && jump > TELEPORT_METERS {
+            timing.abort_lap();
+            continue;
+        }
+        if timing.best_sectors.len() != sectors {
+            timing.best_sectors = vec![None; sectors];
+        }
+
+        if let Some(lap_time) = timing.current(seconds) {
+            let sector_at = match crossed {
+                true => sectors,
+                false => timing_config.sector_at(position),
+            };
+            let valid = !timing.invalid;
+            // sector ends, the last one on the line
+            while timing.sector < sector_at {
+                let sector = timing.sector;
+                let time = (seconds - timing.sector_started_at) as f32;
+                let personal_best =
+                    valid && timing.best_sectors[sector].is_none_or(|best| time < best);
+                if personal_best {
+                    timing.best_sectors[sector] = Some(time);
+                }
+                timing.sector_times.push(time);
+                timing.sector = sector + 1;
+                timing.sector_started_at = seconds;
+                sector_events.send(SectorCompleted {
+                    entity: e,
+                    lap: timing.laps + 1,
+                    sector,
+                    time,
+                    valid,
+                    personal_best,
+                });
+            }
+            if crossed {
+                timing.laps += 1;
+                let personal_best = valid && timing.best_lap.is_none_or(|best| lap_time < best);
+                timing.last_lap = Some(lap_time);
+                if personal_best {
+                    timing.best_lap = Some(lap_time);
+                    timing.trace.push(length, lap_time);
+                    timing.best_trace = std::mem::take(&mut timing.trace);
+                }
+                lap_events.send(LapCompleted {
+                    entity: e,
+                    lap: timing.laps,
+                    time: lap_time,
+                    sectors: timing.sector_times.clone(),
+                    valid,
+                    personal_best,
+                    pit_time: timing.pit_time,
+                });
+            } else {
+                timing.trace.push(position, lap_time);
+                timing.delta = timing
+                    .best_trace
+                    .seconds_at(position)
+                    .map(|best| lap_time - best);
+            }
+        }
+        if crossed {
+            timing.start_lap(seconds);
+        }
+    }
+}
+
+pub struct TimingPlugin;
+
+impl Plugin for TimingPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<SectorCompleted>()
+            .add_event::<LapCompleted>()
+            .add_systems(
+                Startup,
+                timing_config_start_system.after(crate::track_polyline_start_system),
+            )
+            .add_systems(
+                Update,
+                (
+                    add_lap_timing_system,
+                    timing_system.after(crate::checkpoint_system),
+                ),
+            );
+    }
+}
@@ -7,7 +7,8 @@ use std::f32::consts::FRAC_PI_2;
 #[derive(Component, Debug)]
 pub struct TrackRoad;
 
-#[derive(Component, Debug)]
+// built once from the chosen TRACKS positions by track_start_system
+#[derive(Component, Resource, Debug, Clone)]
 pub struct Track {
     width: f32,
     pub points: Vec<Vec3>,
@@ -32,8 +33,7 @@ impl Track {
             right_norm: Vec::new(),
         }
     }
-    pub fn new() -> Self {
-        let positions = TRACK_POSITIONS;
+    pub fn new(positions: &[(f32, f32, f32, f32)]) -> Self {
         let mut track = Track::empty();
         let mut points: Vec<Vec3> = vec![];
         points.extend(
@@ -82,6 +82,17 @@ impl Track {
         }
         track
     }
+    // asphalt edges at the segment and shift along it from the polyline projection
+    pub fn contains(&self, segment: usize, t: f32, point: Vec3, tolerance: f32) -> bool {
+        let next = (segment + 1) % self.left.len();
+        let flat = Vec3::new(1., 0., 1.);
+        let left = self.left[segment].lerp(self.left[next], t) * flat;
+        let right = self.right[segment].lerp(self.right[next], t) * flat;
+        let across = right - left;
+        let width = across.length().max(f32::EPSILON);
+        let lateral = (point * flat - left).dot(across) / width;
+        (-tolerance..=width + tolerance).contains(&lateral)
+    }
     pub fn road(&self) -> (Vec<[f32; 3]>, Vec<[f32; 3]>) {
         let mut vertices: Vec<[f32; 3]> = vec![];
         let mut normals: Vec<[f32; 3]> = vec![];
@@ -100,7 +111,39 @@ impl Track {
 //     (b - a).cross(c - a).normalize().into()
 // }
 
-pub const _TRACK_POSITIONS: [(f32, f32, f32, f32); 7] = [
+pub const TRACKS: [&[(f32, f32, f32, f32)]; 2] = [&TRACK_POSITIONS, &SQUARE_TRACK_POSITIONS];
+
+// pit lane along the start straight, meters from the start line, negative before it
+#[derive(Debug, Clone, Copy)]
+pub struct PitLaneLayout {
+    pub entry: f32,
+    pub exit: f32,
+    pub box_meters: f32,
+    // lane center to the right of the track center
+    pub offset: f32,
+    // the lane fits between the 5m asphalt edge and the 7.5m wall
+    pub width: f32,
+}
+
+// one for each of TRACKS
+pub const PIT_LANES: [PitLaneLayout; 2] = [
+    PitLaneLayout {
+        entry: -250.,
+        exit: 250.,
+        box_meters: 50.,
+        offset: 6.25,
+        width: 2.4,
+    },
+    PitLaneLayout {
+        entry: -80.,
+        exit: 80.,
+        box_meters: 20.,
+        offset: 6.25,
+        width: 2.4,
+    },
+];
+
+pub const SQUARE_TRACK_POSITIONS: [(f32, f32, f32, f32); 7] = [
     (0., 0.0, 0., 1.0),
     (100., 0.0, 0., 1.0),
     (100., 0.0, 100., 1.0),
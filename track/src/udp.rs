@@ -0,0 +1,468 @@
+use crate::{
+    replay::ReplayReader, replay::ReplayWriter, CarCondition, CarTrack, LapTiming, PitLaneState,
+    TelemetrySample, TELEMETRY_WHEELS,
+};
+use bevy::{prelude::*, utils::HashMap};
+use bevy_garage_car::Player;
+use bevy_rapier3d::prelude::*;
+use std::net::{SocketAddr, UdpSocket};
+
+// little endian, fixed size per packet id, see README "Telemetry UDP packets"
+pub const PACKET_MAGIC: [u8; 2] = *b"BG";
+pub const PACKET_VERSION: u8 = 1;
+pub const PACKET_HEADER_SIZE: usize = 16;
+pub const MOTION_PACKET_SIZE: usize = PACKET_HEADER_SIZE + 17 * 4;
+pub const STATUS_PACKET_SIZE: usize = PACKET_HEADER_SIZE + 23 * 4;
+pub const LAP_PACKET_SIZE: usize = PACKET_HEADER_SIZE + 9 * 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PacketId {
+    Motion = 0,
+    Status = 1,
+    Lap = 2,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct PacketHeader {
+    pub id: PacketId,
+    // broadcast counter, the same for the packets of one send
+    pub frame: u32,
+    // seconds since the start
+    pub time: f32,
+    // CarTrack index
+    pub car: u16,
+    pub player: bool,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MotionPacket {
+    pub position: Vec3,
+    // m/s
+    pub velocity: Vec3,
+    pub forward: Vec3,
+    pub up: Vec3,
+    // rad/s
+    pub angular_velocity: Vec3,
+    pub g_lat: f32,
+    pub g_long: f32,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct StatusPacket {
+    // km/h
+    pub speed: f32,
+    // 0..1
+    pub throttle: f32,
+    pub brake: f32,
+    // -1..1
+    pub steering: f32,
+    // wheels in CarSpec mount order: front right, front left, rear right, rear left
+    pub wheel_speed: [f32; 4],
+    pub wheel_slip: [f32; 4],
+    pub wheel_load: [f32; 4],
+    // mm
+    pub suspension: [f32; 4],
+    // liters
+    pub fuel: f32,
+    // 0..1
+    pub tire_wear: f32,
+    pub damage: f32,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LapPacket {
+    pub lap: u32,
+    // meters
+    pub lap_distance: f32,
+    // seconds, 0 without a time
+    pub current_lap: f32,
+    pub last_lap: f32,
+    pub best_lap: f32,
+    pub delta: f32,
+    // 0 based
+    pub sector: u32,
+    // 1 based race position
+    pub place: u32,
+    pub invalid: bool,
+    pub in_pit: bool,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum TelemetryPacket {
+    Motion(MotionPacket),
+    Status(StatusPacket),
+    Lap(LapPacket),
+}
+
+impl TelemetryPacket {
+    pub fn id(&self) -> PacketId {
+        match self {
+            TelemetryPacket::Motion(_) => PacketId::Motion,
+            TelemetryPacket::Status(_) => PacketId::Status,
+            TelemetryPacket::Lap(_) => PacketId::Lap,
+        }
+    }
+
+    pub fn encode(&self, header: &PacketHeader) -> Vec<u8> {
+        let mut w = ReplayWriter::default();
+        w.0.extend(PACKET_MAGIC);
+        w.u8(PACKET_VERSION);
+        w.u8(self.id() as u8);
+        w.u32(header.frame);
+        w.f32(header.time);
+        w.0.extend(header.car.to_le_bytes());
+        w.u8(header.player as u8);
+        w.u8(0);
+        let floats = |w: &mut ReplayWriter, values: &[f32]| {
+            for v in values {
+                w.f32(*v);
+            }
+        };
+        match self {
+            TelemetryPacket::Motion(m) => {
+                for v in [m.position, m.velocity, m.forward, m.up, m.angular_velocity] {
+                    floats(&mut w, &v.to_array());
+                }
+                floats(&mut w, &[m.g_lat, m.g_long]);
+            }
+            TelemetryPacket::Status(s) => {
+                floats(&mut w, &[s.speed, s.throttle, s.brake, s.steering]);
+                for wheels in [s.wheel_speed, s.wheel_slip, s.wheel_load, s.suspension] {
+                    floats(&mut w, &wheels);
+                }
+                floats(&mut w, &[s.fuel, s.tire_wear, s.damage]);
+            }
+            TelemetryPacket::Lap(l) => {
+                w.u32(l.lap);
+                floats(
+                    &mut w,
+                    &[
+                        l.lap_distance,
+                        l.current_lap,
+                        l.last_lap,
+                        l.best_lap,
+                        l.delta,
+                    ],
+                );
+                w.u32(l.sector);
+                w.u32(l.place);
+                w.0.extend([l.invalid as u8, l.in_pit as u8, 0, 0]);
+            }
+        }
+        w.0
+    }
+
+    pub fn decode(bytes: &[u8]) -> Option<(PacketHeader, TelemetryPacket)> {
+        let mut r = ReplayReader { bytes, at: 0 };
+        if r.take::<2>()? != PACKET_MAGIC || r.u8()? != PACKET_VERSION {
+            return None;
+        }
+        let id = match r.u8()? {
+            0 => PacketId::Motion,
+            1 => PacketId::Status,
+            2 => PacketId::Lap,
+            _ => return None,
+        };
+        let header = PacketHeader {
+            id,
+            frame: r.u32()?,
+            time: r.f32()?,
+            car: u16::from_le_bytes(r.take()?),
+            player: r.take::<2>()?[0] != 0,
+        };
+        let vec3 = |r: &mut ReplayReader| Some(Vec3::new(r.f32()?, r.f32()?, r.f32()?));
+        let wheels = |r: &mut ReplayReader| Some([r.f32()?, r.f32()?, r.f32()?, r.f32()?]);
+        let packet = match id {
+            PacketId::Motion => TelemetryPacket::Motion(MotionPacket {
+                position: vec3(&mut r)?,
+                velocity: vec3(&mut r)?,
+                forward: vec3(&mut r)?,
+                up: vec3(&mut r)?,
+                angular_velocity: vec3(&mut r)?,
+                g_lat: r.f32()?,
+                g_long: r.f32()?,
+            }),
+            PacketId::Status => TelemetryPacket::Status(StatusPacket {
+                speed: r.f32()?,
+                throttle: r.f32()?,
+                brake: r.f32()?,
+                steering: r.f32()?,
+                wheel_speed: wheels(&mut r)?,
+                wheel_slip: wheels(&mut r)?,
+                wheel_load: wheels(&mut r)?,
+                suspension: wheels(&mut r)?,
+                fuel: r.f32()?,
+                tire_wear: r.f32()?,
+                damage: r.f32()?,
+            }),
+            PacketId::Lap => {
+                let lap = r.u32()?;
+                let (lap_distance, current_lap, last_lap, best_lap, delta) =
+                    (r.f32()?, r.f32()?, r.f32()?, r.f32()?, r.f32()?);
+                let (sector, place) = (r.u32()?, r.u32()?);
+                let flags = r.take::<4>()?;
+                TelemetryPacket::Lap(LapPacket {
+                    lap,
+                    lap_distance,
+                    current_lap,
+                    last_lap,
+                    best_lap,
+                    delta,
+                    sector,
+                    place,
+                    invalid: flags[0] != 0,
+                    in_pit: flags[1] != 0,
+                })
+            }
+        };
+        Some((header, packet))
+    }
+}
+
+// TELEMETRY_UDP=host:port broadcasts the player, TELEMETRY_UDP_ALL=1 every car,
+// TELEMETRY_UDP_RATE=sends per second, limited by TELEMETRY_RATE
+#[derive(Resource, Debug)]
+pub struct UdpTelemetry {
+    pub socket: UdpSocket,
+    pub target: SocketAddr,
+    pub rate: f32,
+    pub all_cars: bool,
+    frame: u32,
+    next_at: HashMap<usize, f64>,
+}
+
+impl UdpTelemetry {
+    pub fn from_env() -> Option<Self> {
+        let target: SocketAddr = std::env::var("TELEMETRY_UDP").ok()?.parse().ok()?;
+        let socket = match UdpSocket::bind(("0.0.0.0", 0)) {
+            Ok(socket) => socket,
+            Err(err) => {
+                println!("telemetry udp bind error: {err}");
+                return None;
+            }
+        };
+        socket.set_nonblocking(true).ok()?;
+        let rate = std::env::var("TELEMETRY_UDP_RATE")
+            .ok()
+            .and_then(|v| v.parse::<f32>().ok())
+            .unwrap_or(20.);
+        println!("telemetry udp to {target}");
+        Some(Self {
+            socket,
+            target,
+            rate,
+            all_cars: std::env::var("TELEMETRY_UDP_ALL").is_ok_and(|v| v == "1"),
+            frame: 0,
+            next_at: HashMap::default(),
+        })
+    }
+}
+
+#[allow(clippy::type_complexity)]
+pub fn udp_telemetry_system(
+    time: Res<Time>,
+    mut udp: ResMut<UdpTelemetry>,
+    mut sample_events: EventReader<TelemetrySample>,
+    q_car: Query<(&Transform, &Velocity, &CarTrack, Has<Player>)>,
+    q_state: Query<(
+        Option<&LapTiming>,
+        Option<&CarCondition>,
+        Option<&PitLaneState>,
+    )>,
+) {
+    let seconds = time.elapsed_seconds_f64();
+    let udp = udp.as_mut();
+    for sample in sample_events.read() {
+        let Ok((t, v, car_track, player)) = q_car.get(sample.entity) else {
+            continue;
+        };
+        if !player && !udp.all_cars {
+            continue;
+        }
+        // fixed cadence at the send rate, one send per due sample
+        let period = 1. / udp.rate.max(1.) as f64;
+        let next_at = udp.next_at.entry(sample.index).or_insert(0.);
+        if *next_at <= 0. || seconds - *next_at > 1. {
+            *next_at = seconds;
+        }
+        if seconds < *next_at {
+            continue;
+        }
+        while *next_at <= seconds {
+            *next_at += period;
+        }
+        let (timing, condition, pit) = q_state.get(sample.entity).unwrap_or_default();
+        let wheels = |offset: usize| {
+            let at = TELEMETRY_WHEELS + offset * 4;
+            [0, 1, 2, 3].map(|i| sample.values[at + i])
+        };
+        let motion = MotionPacket {
+            position: t.translation,
+            velocity: v.linvel,
+            forward: t.rotation.mul_vec3(Vec3::Z),
+            up: t.rotation.mul_vec3(Vec3::Y),
+            angular_velocity: v.angvel,
+            g_lat: sample.get("GLat"),
+            g_long: sample.get("GLong"),
+        };
+        let status = StatusPacket {
+            speed: sample.get("Speed"),
+            throttle: sample.get("Throttle") / 100.,
+            brake: sample.get("Brake") / 100.,
+            steering: sample.get("Steer") / 100.,
+            wheel_speed: wheels(0),
+            wheel_slip: wheels(1),
+            wheel_load: wheels(2),
+            suspension: wheels(3),
+            fuel: condition.map_or(0., |c| c.fuel),
+            tire_wear: condition.map_or(0., |c| c.tire_wear),
+            damage: condition.map_or(0., |c| c.damage),
+        };
+        let lap = LapPacket {
+            lap: car_track.lap.max(0) as u32,
+            lap_distance: car_track.track_position,
+            current_lap: timing.and_then(|t| t.current(seconds)).unwrap_or(0.),
+            last_lap: timing.and_then(|t| t.last_lap).unwrap_or(0.),
+            best_lap: timing.and_then(|t| t.best_lap).unwrap_or(0.),
+            delta: timing.and_then(|t| t.delta).unwrap_or(0.),
+            sector: timing.map_or(0, |t| t.sector as u32),
+            place: car_track.place as u32 + 1,
+            invalid: timing.is_some_and(|t| t.invalid),
+            in_pit: pit.is_some_and(|p| p.in_lane),
+        };
+        for packet in [
+            TelemetryPacket::Motion(motion),
+            TelemetryPacket::Status(status),
+            TelemetryPacket::Lap(lap),
+        ] {
+            let header = PacketHeader {
+                id: packet.id(),
+                frame: udp.frame,
+                time: seconds as f32,
+                car: sample.index as u16,
+                player,
+            };
+            // nobody listening is fine
+            let _ = udp.socket.send_to(&packet.encode(&header), udp.target);
+        }
+        udp.frame = udp.frame.wrapping_add(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header(id: PacketId) -> PacketHeader {
+        PacketHeader {
+            id,
+            frame: 7,
+            time: 12.5,
+            car: 3,
+            player: true,
+        }
+    }
+
+    #[test]
+    fn encoded_sizes() {
+        let packets = [
+            (
+                TelemetryPacket::Motion(MotionPacket::default()),
+                MOTION_PACKET_SIZE,
+            ),
+            (
+                TelemetryPacket::Status(StatusPacket::default()),
+                STATUS_PACKET_SIZE,
+            ),
+            (TelemetryPacket::Lap(LapPacket::default()), LAP_PACKET_SIZE),
+        ];
+        for (packet, size) in packets {
+            assert_eq!(packet.encode(&header(packet.id())).len(), size);
+        }
+        assert_eq!(LAP_PACKET_SIZE, 52);
+    }
+
+    #[test]
+    fn round_trip() {
+        let motion = MotionPacket {
+            position: Vec3::new(1., 2., 3.),
+            velocity: Vec3::new(-4., 0.5, 6.),
+            forward: Vec3::Z,
+            up: Vec3::Y,
+            angular_velocity: Vec3::new(0.1, -0.2, 0.3),
+            g_lat: 1.25,
+            g_long: -0.75,
+        };
+        let bytes = TelemetryPacket::Motion(motion).encode(&header(PacketId::Motion));
+        let (h, packet) = TelemetryPacket::decode(&bytes).unwrap();
+        assert_eq!(
+            (h.id, h.frame, h.time, h.car, h.player),
+            (PacketId::Motion, 7, 12.5, 3, true)
+        );
+        let TelemetryPacket::Motion(m) = packet else {
+            panic!("not a motion packet");
+        };
+        assert_eq!(m.position, motion.position);
+        assert_eq!(m.velocity, motion.velocity);
+        assert_eq!(m.angular_velocity, motion.angular_velocity);
+        assert_eq!((m.g_lat, m.g_long), (motion.g_lat, motion.g_long));
+
+        let status = StatusPacket {
+            speed: 123.,
+            throttle: 0.5,
+            brake: 0.25,
+            steering: -1.,
+            wheel_speed: [1., 2., 3., 4.],
+            wheel_slip: [0.1, 0.2, 0.3, 0.4],
+            wheel_load: [10., 20., 30., 40.],
+            suspension: [5., 6., 7., 8.],
+            fuel: 42.,
+            tire_wear: 0.3,
+            damage: 0.1,
+        };
+        let bytes = TelemetryPacket::Status(status).encode(&header(PacketId::Status));
+        let Some((_, TelemetryPacket::Status(s))) = TelemetryPacket::decode(&bytes) else {
+            panic!("not a status packet");
+        };
+        assert_eq!(
+            (s.speed, s.throttle, s.brake, s.steering),
+            (123., 0.5, 0.25, -1.)
+        );
+        assert_eq!(s.wheel_slip, status.wheel_slip);
+        assert_eq!(s.suspension, status.suspension);
+        assert_eq!((s.fuel, s.tire_wear, s.damage), (42., 0.3, 0.1));
+
+        let lap = LapPacket {
+            lap: 4,
+            lap_distance: 1234.5,
+            current_lap: 61.25,
+            last_lap: 90.5,
+            best_lap: 88.75,
+            delta: -1.5,
+            sector: 2,
+            place: 1,
+            invalid: true,
+            in_pit: false,
+        };
+        let bytes = TelemetryPacket::Lap(lap).encode(&header(PacketId::Lap));
+        let Some((_, TelemetryPacket::Lap(l))) = TelemetryPacket::decode(&bytes) else {
+            panic!("not a lap packet");
+        };
+        assert_eq!((l.lap, l.sector, l.place), (4, 2, 1));
+        assert_eq!(
+            (l.lap_distance, l.current_lap, l.last_lap),
+            (1234.5, 61.25, 90.5)
+        );
+        assert_eq!((l.best_lap, l.delta), (88.75, -1.5));
+        assert!(l.invalid && !l.in_pit);
+    }
+
+    #[test]
+    fn rejects_truncated_and_foreign() {
+        let bytes = TelemetryPacket::Lap(LapPacket::default()).encode(&header(PacketId::Lap));
+        assert!(TelemetryPacket::decode(&bytes[..bytes.len() - 1]).is_none());
+        let mut foreign = bytes.clone();
+        foreign[0] = b'X';
+        assert!(TelemetryPacket::decode(&foreign).is_none());
+    }
+}
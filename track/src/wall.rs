@@ -3,7 +3,7 @@ use bevy::{
     prelude::*,
     render::{mesh::*, render_asset::RenderAssetUsages},
 };
-use bevy_garage_car::STATIC_GROUP;
+use bevy_garage_car::{SurfaceKind, STATIC_GROUP};
 use bevy_rapier3d::{na::Point3, prelude::Real, prelude::*, rapier::prelude::ColliderShape};
 use std::ops::{Mul, Sub};
 
@@ -117,6 +117,7 @@ pub fn spawn_walls(
             ..default()
         },
         Collider::from(ColliderShape::trimesh(collider_vertices, collider_indices)),
+        SurfaceKind::Wall,
         ColliderScale::Absolute(Vec3::ONE),
         CollisionGroups::new(STATIC_GROUP, Group::ALL),
         Restitution::coefficient(0.),